@@ -0,0 +1,4 @@
+// Migrations are embedded in the binary, so adding one has to trigger a rebuild.
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,12 @@
+CREATE TABLE ugocoin_tips (
+    id INTEGER PRIMARY KEY NOT NULL,
+    tip_time INTEGER NOT NULL,
+    tip_date VARCHAR(255) NOT NULL,
+    message_id VARCHAR(255) NOT NULL,
+    from_user_id INTEGER NOT NULL,
+    to_user_id INTEGER NOT NULL,
+    amount INTEGER NOT NULL,
+    refunded BOOLEAN NOT NULL DEFAULT false,
+    FOREIGN KEY (from_user_id) REFERENCES users(id),
+    FOREIGN KEY (to_user_id) REFERENCES users(id)
+);
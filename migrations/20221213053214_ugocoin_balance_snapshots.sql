@@ -0,0 +1,10 @@
+CREATE TABLE ugocoin_balance_snapshots (
+    id INTEGER PRIMARY KEY NOT NULL,
+    snapshot_date VARCHAR(255) NOT NULL,
+    snapshot_time INTEGER NOT NULL,
+    account_id INTEGER NOT NULL,
+    balance INTEGER NOT NULL,
+    streak INTEGER NOT NULL,
+    FOREIGN KEY (account_id) REFERENCES ugocoin_accounts(id),
+    UNIQUE (snapshot_date, account_id) ON CONFLICT ABORT
+);
@@ -0,0 +1,9 @@
+CREATE TABLE scrum_attendance (
+    id INTEGER PRIMARY KEY NOT NULL,
+    scrum_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    response VARCHAR(255) NOT NULL,
+    FOREIGN KEY (scrum_id) REFERENCES scrums(id),
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    UNIQUE (scrum_id, user_id) ON CONFLICT ABORT
+);
@@ -0,0 +1,10 @@
+CREATE UNIQUE INDEX users_discord_ids_discord_id ON users_discord_ids(discord_id);
+
+CREATE TABLE link_requests (
+    id INTEGER PRIMARY KEY NOT NULL,
+    discord_id VARCHAR(255) NOT NULL,
+    user_id INTEGER NOT NULL,
+    request_time INTEGER NOT NULL,
+    status VARCHAR(255) NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id)
+);
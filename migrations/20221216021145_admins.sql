@@ -0,0 +1,5 @@
+CREATE TABLE admins (
+    id INTEGER PRIMARY KEY NOT NULL,
+    user_id INTEGER NOT NULL UNIQUE,
+    FOREIGN KEY (user_id) REFERENCES users(id)
+);
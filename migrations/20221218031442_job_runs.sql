@@ -0,0 +1,19 @@
+-- One row per scheduled slot a job has claimed. The unique constraint is what stops a slot from
+-- running twice.
+CREATE TABLE job_runs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    job_name VARCHAR(255) NOT NULL,
+    scheduled_for INTEGER NOT NULL,
+    started_at INTEGER NOT NULL,
+    finished_at INTEGER,
+    succeeded BOOLEAN,
+    error TEXT,
+    UNIQUE (job_name, scheduled_for) ON CONFLICT ABORT
+);
+
+-- Only the instance holding this lease runs jobs.
+CREATE TABLE scheduler_lease (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+    owner VARCHAR(255) NOT NULL,
+    expires_at INTEGER NOT NULL
+);
@@ -0,0 +1,24 @@
+-- At most one reward per user per scrum, however many times a close is retried.
+CREATE TABLE scrum_rewards (
+    id INTEGER PRIMARY KEY NOT NULL,
+    scrum_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    amount INTEGER NOT NULL,
+    FOREIGN KEY (scrum_id) REFERENCES scrums(id),
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    UNIQUE (scrum_id, user_id) ON CONFLICT ABORT
+);
+
+-- Chat messages owed because of a committed change, sent after the fact until they go through.
+CREATE TABLE outbox (
+    id INTEGER PRIMARY KEY NOT NULL,
+    kind VARCHAR(255) NOT NULL,
+    channel_id VARCHAR(255) NOT NULL,
+    message_id VARCHAR(255),
+    content TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    claimed_until INTEGER,
+    sent_at INTEGER
+);
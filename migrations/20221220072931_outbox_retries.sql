@@ -0,0 +1,34 @@
+-- Nothing references the outbox, so rebuild it with room for reactions and DMs, and with retry
+-- bookkeeping.
+CREATE TABLE outbox_new (
+    id INTEGER PRIMARY KEY NOT NULL,
+    -- send, edit, react or dm
+    kind VARCHAR(255) NOT NULL,
+    channel_id VARCHAR(255),
+    user_id VARCHAR(255),
+    message_id VARCHAR(255),
+    -- The message text, or the emoji for reactions.
+    content TEXT NOT NULL,
+    -- Enqueueing the same key twice only queues it once.
+    dedupe_key VARCHAR(255) UNIQUE,
+    -- Bookkeeping to do once a message is sent and has an ID.
+    on_sent VARCHAR(255),
+    on_sent_arg VARCHAR(255),
+    created_at INTEGER NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at INTEGER NOT NULL,
+    last_error TEXT,
+    claimed_until INTEGER,
+    sent_at INTEGER,
+    -- Set when we've given up. An admin can retry it.
+    failed_at INTEGER
+);
+
+INSERT INTO outbox_new (id, kind, channel_id, message_id, content, created_at, attempts,
+    next_attempt_at, last_error, claimed_until, sent_at)
+SELECT id, kind, channel_id, message_id, content, created_at, attempts, created_at, last_error,
+    claimed_until, sent_at
+FROM outbox;
+
+DROP TABLE outbox;
+ALTER TABLE outbox_new RENAME TO outbox;
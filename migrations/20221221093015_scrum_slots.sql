@@ -0,0 +1,32 @@
+-- Candidate times for scrums that poll time slots instead of asking yes or no. Scrums without any
+-- are yes or no.
+CREATE TABLE scrum_slots (
+    scrum_id INTEGER NOT NULL,
+    -- HH:MM, local time.
+    slot VARCHAR(5) NOT NULL,
+    FOREIGN KEY (scrum_id) REFERENCES scrums(id),
+    PRIMARY KEY (scrum_id, slot)
+);
+
+-- Everyone who has answered a slot poll. Answering without picking any slot means unavailable.
+CREATE TABLE scrum_slot_answers (
+    scrum_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    answered_at INTEGER NOT NULL,
+    FOREIGN KEY (scrum_id) REFERENCES scrums(id),
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    PRIMARY KEY (scrum_id, user_id)
+);
+
+-- The slots each person can make. Answering again replaces these.
+CREATE TABLE scrum_slot_picks (
+    scrum_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    slot VARCHAR(5) NOT NULL,
+    FOREIGN KEY (scrum_id, slot) REFERENCES scrum_slots(scrum_id, slot),
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    PRIMARY KEY (scrum_id, user_id, slot)
+);
+
+-- Menus sent through the outbox, as JSON.
+ALTER TABLE outbox ADD COLUMN menu TEXT;
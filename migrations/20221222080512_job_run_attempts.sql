@@ -0,0 +1,2 @@
+-- Failed runs are retried with backoff until the job's next trigger time, so count the tries.
+ALTER TABLE job_runs ADD COLUMN attempts INTEGER NOT NULL DEFAULT 1;
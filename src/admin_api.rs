@@ -0,0 +1,343 @@
+// A small read-only HTTP API for checking on the bot without Discord. It only ever listens on
+// localhost. Amounts are in ugocents, like exports.
+use std::env;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+
+use log::{error, info};
+
+use serde::{Deserialize, Serialize};
+
+use sqlx::SqlitePool;
+
+use crate::error::{new_incident_id, Error, InnerError, WithContext};
+use crate::jobs::{self, scheduler};
+use crate::metrics;
+use crate::scrum;
+use crate::ugocoin::{account, tx};
+use crate::user;
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 500;
+
+// What only the running bot knows about itself, kept up to date by the event handler.
+#[derive(Default)]
+pub struct BotStatus {
+    gateway_connected: AtomicBool,
+}
+
+impl BotStatus {
+    pub fn new() -> BotStatus {
+        BotStatus::default()
+    }
+
+    pub fn set_gateway_connected(&self, connected: bool) {
+        self.gateway_connected.store(connected, Ordering::Relaxed);
+        metrics::set_gateway_connected(connected);
+    }
+
+    pub fn gateway_connected(&self) -> bool {
+        self.gateway_connected.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone)]
+pub struct ApiState {
+    pub db: SqlitePool,
+    pub status: Arc<BotStatus>,
+}
+
+// The API is off unless ADMIN_API_PORT is set.
+pub fn port_from_env() -> Option<u16> {
+    env::var("ADMIN_API_PORT").ok()?.parse().ok()
+}
+
+pub fn router(state: ApiState) -> Router {
+    Router::new()
+        .route("/health", get(health))
+        .route("/metrics", get(metrics))
+        .route("/api/users", get(users))
+        .route("/api/balances", get(balances))
+        .route("/api/transactions", get(transactions))
+        .route("/api/transactions/:id", get(transaction))
+        .route("/api/scrums", get(scrums))
+        .with_state(state)
+}
+
+pub async fn serve(port: u16, state: ApiState) -> Result<(), Error> {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let server = axum::Server::try_bind(&addr)
+        .map_err(|err| InnerError::HttpServerError(err.to_string()))
+        .with_context("Binding admin API")?;
+
+    info!("Admin API listening on {}.", addr);
+    server
+        .serve(router(state).into_make_service())
+        .await
+        .map_err(|err| InnerError::HttpServerError(err.to_string()))
+        .with_context("Serving admin API")
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self.error {
+            InnerError::UserNotFound | InnerError::TransactionNotFound => StatusCode::NOT_FOUND,
+            InnerError::InvalidArgument(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        let message = match self.error.user_message() {
+            Some(message) => message,
+            None => {
+                let incident_id = new_incident_id();
+                error!("Incident {}: {}", incident_id, self);
+                format!("Internal error. See incident {} in the log.", incident_id)
+            }
+        };
+
+        (status, Json(ApiError { error: message })).into_response()
+    }
+}
+
+#[derive(Serialize)]
+struct ApiError {
+    error: String,
+}
+
+fn limit(requested: Option<i64>) -> Result<i64, Error> {
+    match requested {
+        None => Ok(DEFAULT_LIMIT),
+        Some(limit) if (1..=MAX_LIMIT).contains(&limit) => Ok(limit),
+        Some(_) => Err(InnerError::InvalidArgument(format!(
+            "limit must be between 1 and {}.",
+            MAX_LIMIT
+        ))
+        .into()),
+    }
+}
+
+#[derive(Serialize)]
+struct Health {
+    healthy: bool,
+    gateway_connected: bool,
+    database_reachable: bool,
+    jobs: Vec<JobHealth>,
+}
+
+#[derive(Serialize)]
+struct JobHealth {
+    name: &'static str,
+    last_scheduled_for: Option<i64>,
+    last_finished_at: Option<i64>,
+    last_succeeded: Option<bool>,
+    last_error: Option<String>,
+}
+
+async fn job_health(db: &SqlitePool) -> Result<Vec<JobHealth>, Error> {
+    let mut health = Vec::new();
+    for job in jobs::jobs() {
+        let last_run = scheduler::get_last_run(db, job.name()).await?;
+        health.push(JobHealth {
+            name: job.name(),
+            last_scheduled_for: last_run.as_ref().map(|run| run.scheduled_for),
+            last_finished_at: last_run.as_ref().and_then(|run| run.finished_at),
+            last_succeeded: last_run.as_ref().and_then(|run| run.succeeded),
+            last_error: last_run.and_then(|run| run.error),
+        });
+    }
+
+    Ok(health)
+}
+
+// 200 when the bot can talk to both Discord and its database, 503 otherwise.
+async fn health(State(state): State<ApiState>) -> Response {
+    let gateway_connected = state.status.gateway_connected();
+    let jobs = job_health(&state.db).await;
+    let database_reachable = jobs.is_ok();
+    let healthy = gateway_connected && database_reachable;
+
+    let status = if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    let health = Health {
+        healthy,
+        gateway_connected,
+        database_reachable,
+        jobs: jobs.unwrap_or_default(),
+    };
+
+    (status, Json(health)).into_response()
+}
+
+// Prometheus text format.
+async fn metrics(State(state): State<ApiState>) -> Result<String, Error> {
+    metrics::render(&state.db).await
+}
+
+#[derive(Serialize)]
+struct ApiUser {
+    id: i64,
+    display_name: String,
+    streak: i64,
+    discord_ids: Vec<String>,
+    balance: i64,
+}
+
+async fn users(State(state): State<ApiState>) -> Result<Json<Vec<ApiUser>>, Error> {
+    let db = &state.db;
+    let mut users = Vec::new();
+    for u in user::get_all_users(db).await? {
+        let account = account::get_user_account(db, &u).await?;
+        users.push(ApiUser {
+            discord_ids: user::get_discord_ids(db, u.id).await?,
+            id: u.id,
+            display_name: u.display_name,
+            streak: u.streak,
+            balance: account.balance.as_ugocents(),
+        });
+    }
+
+    Ok(Json(users))
+}
+
+#[derive(Serialize)]
+struct ApiBalance {
+    account_id: i64,
+    user_id: Option<i64>,
+    name: String,
+    balance: i64,
+}
+
+// Same table as /balances, richest first.
+async fn balances(State(state): State<ApiState>) -> Result<Json<Vec<ApiBalance>>, Error> {
+    let db = &state.db;
+    let mut balances = Vec::new();
+    for u in user::get_all_users(db).await? {
+        let account = account::get_user_account(db, &u).await?;
+        balances.push(ApiBalance {
+            account_id: account.id,
+            user_id: Some(u.id),
+            name: u.display_name,
+            balance: account.balance.as_ugocents(),
+        });
+    }
+
+    let central_account = account::get_central_bank_account(db).await?;
+    balances.push(ApiBalance {
+        account_id: central_account.id,
+        user_id: None,
+        name: String::from("UGOcoin Central Bank"),
+        balance: central_account.balance.as_ugocents(),
+    });
+
+    balances.sort_by_key(|balance| -balance.balance);
+    Ok(Json(balances))
+}
+
+#[derive(Serialize)]
+struct ApiTransaction {
+    id: i64,
+    tx_time: i64,
+    from_account_id: i64,
+    to_account_id: i64,
+    amount: i64,
+    memo: String,
+}
+
+impl From<tx::UgocoinTransaction> for ApiTransaction {
+    fn from(tx: tx::UgocoinTransaction) -> Self {
+        ApiTransaction {
+            id: tx.id,
+            tx_time: tx.tx_time,
+            from_account_id: tx.from_account_id,
+            to_account_id: tx.to_account_id,
+            amount: tx.amount.as_ugocents(),
+            memo: tx.memo,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TransactionsQuery {
+    // Only transactions to or from this user.
+    user_id: Option<i64>,
+    limit: Option<i64>,
+}
+
+async fn transactions(
+    State(state): State<ApiState>,
+    Query(query): Query<TransactionsQuery>,
+) -> Result<Json<Vec<ApiTransaction>>, Error> {
+    let db = &state.db;
+    let limit = limit(query.limit)?;
+
+    let txs = match query.user_id {
+        Some(user_id) => {
+            let u = user::get_user_by_id(db, user_id).await?;
+            let account = account::get_user_account(db, &u).await?;
+            tx::get_recent_transactions(db, &account, limit).await?
+        }
+        None => tx::get_latest_transactions(db, limit).await?,
+    };
+
+    Ok(Json(txs.into_iter().map(ApiTransaction::from).collect()))
+}
+
+async fn transaction(
+    State(state): State<ApiState>,
+    Path(id): Path<i64>,
+) -> Result<Json<ApiTransaction>, Error> {
+    let tx = tx::get_transaction(&state.db, id).await?;
+    Ok(Json(tx.into()))
+}
+
+#[derive(Serialize)]
+struct ApiScrum {
+    id: i64,
+    scrum_date: String,
+    is_open: bool,
+    message_id: String,
+    available: i64,
+    unavailable: i64,
+    unknown: i64,
+}
+
+#[derive(Deserialize)]
+struct ScrumsQuery {
+    #[serde(default)]
+    open: bool,
+    limit: Option<i64>,
+}
+
+async fn scrums(
+    State(state): State<ApiState>,
+    Query(query): Query<ScrumsQuery>,
+) -> Result<Json<Vec<ApiScrum>>, Error> {
+    let limit = limit(query.limit)?;
+    let scrums = scrum::get_recent_scrums(&state.db, limit, query.open).await?;
+
+    Ok(Json(
+        scrums
+            .into_iter()
+            .map(|summary| ApiScrum {
+                id: summary.scrum.id,
+                scrum_date: summary.scrum.scrum_date,
+                is_open: summary.scrum.is_open,
+                message_id: summary.scrum.message_id,
+                available: summary.available,
+                unavailable: summary.unavailable,
+                unknown: summary.unknown,
+            })
+            .collect(),
+    ))
+}
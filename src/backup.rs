@@ -0,0 +1,146 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+
+use log::info;
+
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use sqlx::SqlitePool;
+
+use crate::error::{Error, InnerError, WithContext};
+
+pub const BACKUP_HOUR: u32 = 2;
+
+const DEFAULT_KEEP: usize = 14;
+const SNAPSHOT_PREFIX: &str = "ugo-";
+const SNAPSHOT_EXTENSION: &str = ".db";
+
+pub struct BackupConfig {
+    pub dir: PathBuf,
+    // How many snapshots to keep. Older ones are deleted after each successful backup.
+    pub keep: usize,
+}
+
+impl BackupConfig {
+    // Backups are off unless BACKUP_DIR is set. BACKUP_KEEP sets how many are kept.
+    pub fn from_env() -> Option<BackupConfig> {
+        let dir = env::var("BACKUP_DIR").ok()?;
+        let keep = env::var("BACKUP_KEEP")
+            .ok()
+            .and_then(|keep| keep.parse().ok())
+            .filter(|keep| *keep > 0)
+            .unwrap_or(DEFAULT_KEEP);
+
+        Some(BackupConfig {
+            dir: PathBuf::from(dir),
+            keep,
+        })
+    }
+}
+
+// Takes a consistent snapshot of the live database, checks it, then prunes old snapshots.
+// Returns the new snapshot's path.
+pub async fn run_backup(
+    db: &SqlitePool,
+    now: DateTime<Local>,
+    config: &BackupConfig,
+) -> Result<PathBuf, Error> {
+    fs::create_dir_all(&config.dir).with_context("Creating backup directory")?;
+
+    let name = format!(
+        "{}{}{}",
+        SNAPSHOT_PREFIX,
+        now.format("%Y%m%d-%H%M%S"),
+        SNAPSHOT_EXTENSION
+    );
+    let path = config.dir.join(&name);
+    // Written under another name first, so a half-made snapshot never looks like a backup.
+    let partial = config.dir.join(format!("{}.partial", name));
+    if partial.exists() {
+        fs::remove_file(&partial).with_context("Removing old partial backup")?;
+    }
+
+    let partial_str = partial.to_string_lossy().to_string();
+    sqlx::query("VACUUM INTO ?")
+        .bind(&partial_str)
+        .execute(db)
+        .await
+        .with_context("Snapshotting database")?;
+
+    if let Err(why) = verify_snapshot(&partial).await {
+        fs::remove_file(&partial).ok();
+        return Err(why);
+    }
+
+    fs::rename(&partial, &path).with_context("Renaming backup")?;
+    info!("Backed up database to {}.", path.display());
+
+    prune_snapshots(&config.dir, config.keep).with_context("Pruning old backups")?;
+
+    Ok(path)
+}
+
+// Opens a snapshot on its own and has SQLite check every page of it.
+pub async fn verify_snapshot(path: &Path) -> Result<(), Error> {
+    // Snapshots use a rollback journal, and a read-only connection can't switch them to WAL.
+    let options = SqliteConnectOptions::new()
+        .filename(path)
+        .journal_mode(SqliteJournalMode::Delete)
+        .read_only(true);
+    let snapshot = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await
+        .with_context("Opening backup")?;
+
+    let problems: Vec<String> = sqlx::query_scalar("PRAGMA integrity_check")
+        .fetch_all(&snapshot)
+        .await
+        .with_context("Checking backup integrity")?;
+    snapshot.close().await;
+
+    if problems != ["ok"] {
+        return Err(InnerError::BackupFailed(format!(
+            "{} failed its integrity check: {}",
+            path.display(),
+            problems.join("; ")
+        ))
+        .into());
+    }
+
+    Ok(())
+}
+
+// Snapshot names sort by the time they were taken, so the newest are last.
+pub fn list_snapshots(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut snapshots = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_snapshot = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| name.starts_with(SNAPSHOT_PREFIX) && name.ends_with(SNAPSHOT_EXTENSION))
+            .unwrap_or(false);
+
+        if is_snapshot {
+            snapshots.push(path);
+        }
+    }
+
+    snapshots.sort();
+    Ok(snapshots)
+}
+
+fn prune_snapshots(dir: &Path, keep: usize) -> Result<(), Error> {
+    let snapshots = list_snapshots(dir)?;
+    let excess = snapshots.len().saturating_sub(keep);
+
+    for old in &snapshots[..excess] {
+        fs::remove_file(old)?;
+        info!("Deleted old backup {}.", old.display());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,502 @@
+// Offline admin tools. These work on the database directly, so stop the bot first.
+
+use std::env;
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use dotenv::dotenv;
+
+use serenity::model::id::UserId;
+
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::SqlitePool;
+
+use ugo_ii_bot::clock::{Clock, SystemClock};
+use ugo_ii_bot::db::{self, MigrationMode};
+use ugo_ii_bot::error::{Error, WithContext};
+use ugo_ii_bot::jobs::scheduler;
+use ugo_ii_bot::ugocoin::account::{self, Ugocoin};
+use ugo_ii_bot::ugocoin::audit;
+use ugo_ii_bot::{export, scrum, user};
+
+#[derive(Parser)]
+#[command(
+    name = "ugo-admin",
+    about = "Offline database tools for the UGO-II bot."
+)]
+struct Cli {
+    /// The database to work on. Defaults to DATABASE_URL.
+    #[arg(long, global = true)]
+    database: Option<String>,
+
+    /// Run even though a bot seems to be using the database.
+    #[arg(long, global = true)]
+    force: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Apply any pending database migrations.
+    Migrate,
+    /// Manage users and their Discord accounts.
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Adjust UGOcoin balances.
+    #[command(subcommand)]
+    Account(AccountCommand),
+    /// Inspect scrums.
+    #[command(subcommand)]
+    Scrum(ScrumCommand),
+    /// Check the UGOcoin ledger.
+    #[command(subcommand)]
+    Ledger(LedgerCommand),
+    /// Export users, accounts, transactions and scrums.
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// Where to write the export: a file for JSON, or a directory for CSV. JSON defaults to
+        /// stdout.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Rebuild a new database from an export. Pass a JSON file or a CSV directory.
+    Import { path: PathBuf },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Json,
+    /// One file per table.
+    Csv,
+}
+
+#[derive(Subcommand)]
+enum UserCommand {
+    /// Register a new user with their Discord ID and a UGOcoin account.
+    Add {
+        display_name: String,
+        discord_id: u64,
+    },
+    /// Link another Discord ID to an existing user.
+    Link { user_id: i64, discord_id: u64 },
+    /// List every user.
+    List,
+}
+
+#[derive(Subcommand)]
+enum AccountCommand {
+    /// Pay a user from the central bank.
+    Credit {
+        user_id: i64,
+        #[arg(value_parser = parse_amount)]
+        amount: Ugocoin,
+        #[arg(long, default_value = "Admin credit")]
+        memo: String,
+    },
+    /// Take UGOcoin from a user back to the central bank.
+    Debit {
+        user_id: i64,
+        #[arg(value_parser = parse_amount)]
+        amount: Ugocoin,
+        #[arg(long, default_value = "Admin debit")]
+        memo: String,
+    },
+    /// Create new UGOcoin in the central bank.
+    Mint {
+        #[arg(value_parser = parse_amount)]
+        amount: Ugocoin,
+        #[arg(long, default_value = "Minted by admin")]
+        memo: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ScrumCommand {
+    /// List recent scrums, newest first.
+    List {
+        /// Only show scrums that are still open.
+        #[arg(long)]
+        open: bool,
+        #[arg(long, default_value_t = 14)]
+        limit: i64,
+    },
+}
+
+#[derive(Subcommand)]
+enum LedgerCommand {
+    /// Replay the transaction logs and check them against account balances.
+    Audit,
+}
+
+// Parses a decimal amount of UGOcoin, like 12 or 3.50, without going through floats.
+fn parse_amount(amount: &str) -> Result<Ugocoin, String> {
+    let invalid = || format!("{} isn't an amount of UGOcoin", amount);
+
+    let (coins, cents) = match amount.split_once('.') {
+        Some((coins, cents)) if cents.len() == 1 => (coins, format!("{}0", cents)),
+        Some((coins, cents)) if cents.len() == 2 => (coins, cents.to_string()),
+        Some(_) => return Err(invalid()),
+        None => (amount, String::from("0")),
+    };
+
+    if !coins
+        .chars()
+        .chain(cents.chars())
+        .all(|c| c.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+    let coins: i64 = coins.parse().map_err(|_| invalid())?;
+    let cents: i64 = cents.parse().map_err(|_| invalid())?;
+
+    let ugocents = coins
+        .checked_mul(100)
+        .and_then(|ugocents| ugocents.checked_add(cents))
+        .ok_or_else(invalid)?;
+
+    Ok(Ugocoin::from_ugocents(ugocents))
+}
+
+async fn connect(database_url: &str, create: bool) -> Result<SqlitePool, Error> {
+    let options = SqliteConnectOptions::from_str(database_url)
+        .with_context("Parsing database URL")?
+        .create_if_missing(create);
+
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await
+        .with_context("Connecting to database")
+}
+
+async fn run_user(db: &SqlitePool, command: UserCommand) -> Result<(), Error> {
+    match command {
+        UserCommand::Add {
+            display_name,
+            discord_id,
+        } => {
+            let user = user::register_user(db, &UserId(discord_id), &display_name)
+                .await
+                .with_context("Registering user")?;
+            println!("Registered {} as user {}.", user.display_name, user.id);
+        }
+        UserCommand::Link {
+            user_id,
+            discord_id,
+        } => {
+            let user = user::get_user_by_id(db, user_id)
+                .await
+                .with_context("Fetching user")?;
+            user::link_discord_id(db, user.id, &UserId(discord_id))
+                .await
+                .with_context("Linking Discord ID")?;
+            println!("Linked {} to {}.", discord_id, user.display_name);
+        }
+        UserCommand::List => {
+            let users = user::get_all_users(db)
+                .await
+                .with_context("Fetching users")?;
+
+            println!(
+                "{:>4}  {:<20} {:>6} {:>14}  Discord IDs",
+                "ID", "Name", "Streak", "Balance"
+            );
+            for user in users {
+                let account = account::get_user_account(db, &user)
+                    .await
+                    .with_context("Fetching account")?;
+                let discord_ids = user::get_discord_ids(db, user.id)
+                    .await
+                    .with_context("Fetching Discord IDs")?;
+                println!(
+                    "{:>4}  {:<20} {:>6} {:>14}  {}",
+                    user.id,
+                    user.display_name,
+                    user.streak,
+                    account.balance,
+                    discord_ids.join(", ")
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_account(
+    db: &SqlitePool,
+    clock: &dyn Clock,
+    command: AccountCommand,
+) -> Result<(), Error> {
+    match command {
+        AccountCommand::Credit {
+            user_id,
+            amount,
+            memo,
+        } => {
+            let user = user::get_user_by_id(db, user_id)
+                .await
+                .with_context("Fetching user")?;
+            let user_account = account::get_user_account(db, &user)
+                .await
+                .with_context("Fetching account")?;
+            account::credit_account(db, clock.now(), &user_account, amount, &memo)
+                .await
+                .with_context("Crediting account")?;
+            println!("Credited {} {}.", user.display_name, amount);
+        }
+        AccountCommand::Debit {
+            user_id,
+            amount,
+            memo,
+        } => {
+            let user = user::get_user_by_id(db, user_id)
+                .await
+                .with_context("Fetching user")?;
+            let user_account = account::get_user_account(db, &user)
+                .await
+                .with_context("Fetching account")?;
+            account::debit_account(db, clock.now(), &user_account, amount, &memo)
+                .await
+                .with_context("Debiting account")?;
+            println!("Debited {} {}.", user.display_name, amount);
+        }
+        AccountCommand::Mint { amount, memo } => {
+            account::mint(db, clock.now(), amount, &memo)
+                .await
+                .with_context("Minting UGOcoin")?;
+            println!("Minted {} into the central bank.", amount);
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_scrum(db: &SqlitePool, command: ScrumCommand) -> Result<(), Error> {
+    match command {
+        ScrumCommand::List { open, limit } => {
+            let scrums = scrum::get_recent_scrums(db, limit, open)
+                .await
+                .with_context("Fetching scrums")?;
+
+            if scrums.is_empty() {
+                println!("No scrums.");
+            }
+            for summary in scrums {
+                let state = if summary.scrum.is_open {
+                    String::from("open")
+                } else {
+                    format!(
+                        "closed: {} available, {} unavailable, {} no answer",
+                        summary.available, summary.unavailable, summary.unknown
+                    )
+                };
+                println!(
+                    "{}  #{:<4} message {}  {}",
+                    summary.scrum.scrum_date, summary.scrum.id, summary.scrum.message_id, state
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Returns whether the ledger checked out.
+async fn run_ledger(db: &SqlitePool, command: LedgerCommand) -> Result<bool, Error> {
+    match command {
+        LedgerCommand::Audit => {
+            let report = audit::audit_ledger(db)
+                .await
+                .with_context("Auditing ledger")?;
+
+            for account in &report.accounts {
+                println!(
+                    "#{:<4} {:<20} {:>14}",
+                    account.account_id, account.name, account.balance
+                );
+            }
+            println!("Total supply: {}", report.total_supply());
+            if report.untraced_central_funds > Ugocoin::from_ugocents(0) {
+                println!(
+                    "Central bank funds not explained by the ledger: {}",
+                    report.untraced_central_funds
+                );
+            }
+
+            if report.is_clean() {
+                println!("Ledger OK.");
+            } else {
+                for problem in &report.problems {
+                    println!("PROBLEM: {}", problem);
+                }
+            }
+
+            Ok(report.is_clean())
+        }
+    }
+}
+
+async fn run_export(
+    db: &SqlitePool,
+    clock: &dyn Clock,
+    format: ExportFormat,
+    output: Option<PathBuf>,
+) -> Result<ExitCode, Error> {
+    let export = export::export(db, clock.now())
+        .await
+        .with_context("Exporting data")?;
+
+    match (format, output) {
+        (ExportFormat::Json, Some(path)) => {
+            let mut file = File::create(&path).with_context("Creating export file")?;
+            serde_json::to_writer_pretty(&mut file, &export).with_context("Writing export file")?;
+            file.write_all(b"\n").with_context("Writing export file")?;
+            eprintln!("Exported to {}.", path.display());
+        }
+        (ExportFormat::Json, None) => {
+            let json = serde_json::to_string_pretty(&export).with_context("Serializing export")?;
+            println!("{}", json);
+        }
+        (ExportFormat::Csv, Some(dir)) => {
+            export::write_csv(&export, &dir).with_context("Writing CSV export")?;
+            eprintln!("Exported to {}.", dir.display());
+        }
+        (ExportFormat::Csv, None) => {
+            eprintln!("A CSV export needs a directory to go in. Pass --output.");
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn read_export(path: &Path) -> Result<export::Export, Error> {
+    if path.is_dir() {
+        return export::read_csv(path);
+    }
+
+    let file = File::open(path)?;
+    Ok(serde_json::from_reader(BufReader::new(file))?)
+}
+
+async fn run_import(db: &SqlitePool, path: &Path) -> Result<(), Error> {
+    let export = read_export(path).with_context("Reading export")?;
+    let summary = export::import(db, &export)
+        .await
+        .with_context("Importing export")?;
+
+    println!(
+        "Imported {} users, {} transactions and {} scrums. Total supply: {}.",
+        summary.users, summary.transactions, summary.scrums, summary.total_supply
+    );
+
+    Ok(())
+}
+
+// The bot holding the scheduler lease, if any. Databases from before the scheduler have no lease
+// table, and nothing new enough to run against them could hold it anyway.
+async fn running_bot(db: &SqlitePool, clock: &dyn Clock) -> Result<Option<String>, Error> {
+    let has_lease_table = sqlx::query!(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'scheduler_lease'"
+    )
+    .fetch_optional(db)
+    .await
+    .with_context("Looking for the scheduler lease")?
+    .is_some();
+
+    if !has_lease_table {
+        return Ok(None);
+    }
+
+    scheduler::lease_holder(db, clock.now())
+        .await
+        .with_context("Checking for a running bot")
+}
+
+async fn run(cli: Cli) -> Result<ExitCode, Error> {
+    let database_url = match cli.database.or_else(|| env::var("DATABASE_URL").ok()) {
+        Some(database_url) => database_url,
+        None => {
+            eprintln!("Pass --database or set DATABASE_URL.");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    let clock = SystemClock;
+
+    // These set up the schema themselves, so they can start from a new file.
+    let creates_schema = matches!(cli.command, Command::Migrate | Command::Import { .. });
+    let db = connect(&database_url, creates_schema).await?;
+
+    // Two writers would step on each other, e.g. both paying out the same scrum.
+    if let Some(holder) = running_bot(&db, &clock).await? {
+        if !cli.force {
+            eprintln!(
+                "A bot ({}) is running against this database. Stop it first, or pass --force.",
+                holder
+            );
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+
+    match &cli.command {
+        Command::Migrate => {
+            db::prepare(&db, MigrationMode::Auto)
+                .await
+                .with_context("Migrating database")?;
+            println!("Database is up to date.");
+            return Ok(ExitCode::SUCCESS);
+        }
+        Command::Import { path } => {
+            run_import(&db, path).await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        _ => {}
+    }
+
+    db::prepare(&db, MigrationMode::Check)
+        .await
+        .with_context("Checking database schema")?;
+
+    match cli.command {
+        Command::Migrate | Command::Import { .. } => {}
+        Command::User(command) => run_user(&db, command).await?,
+        Command::Account(command) => run_account(&db, &clock, command).await?,
+        Command::Scrum(command) => run_scrum(&db, command).await?,
+        Command::Ledger(command) => {
+            if !run_ledger(&db, command).await? {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Export { format, output } => {
+            return run_export(&db, &clock, format, output).await;
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    dotenv().ok();
+    let cli = Cli::parse();
+
+    match run(cli).await {
+        Ok(code) => code,
+        Err(why) => {
+            eprintln!("{}", why);
+            if let Some(message) = why.error.user_message() {
+                eprintln!("{}", message);
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Once;
+
+use chrono::{Days, NaiveDate};
+
+use plotters::coord::ranged1d::SegmentValue;
+use plotters::prelude::*;
+use plotters::style::FontStyle;
+
+use sqlx::SqlitePool;
+
+use crate::digest;
+use crate::error::{Error, InnerError};
+use crate::scrum::{self, ScrumReact};
+use crate::ugocoin::account::{get_user_account, Ugocoin};
+use crate::ugocoin::tx;
+use crate::user::{self, User};
+
+// Charts render entirely on the CPU, so we ship our own font instead of relying on the host having one.
+const FONT_FAMILY: &str = "sans-serif";
+static FONT_DATA: &[u8] = include_bytes!("../assets/DejaVuSans.ttf");
+static REGISTER_FONT: Once = Once::new();
+
+const CHART_WIDTH: u32 = 800;
+const CHART_HEIGHT: u32 = 480;
+
+type ChartResult = Result<(), Box<dyn std::error::Error>>;
+
+fn register_font() {
+    REGISTER_FONT.call_once(|| {
+        // The font is compiled in, so it failing to parse is a programming error.
+        plotters::style::register_font(FONT_FAMILY, FontStyle::Normal, FONT_DATA)
+            .map_err(|_| "invalid font")
+            .expect("Failed to register chart font!");
+    });
+}
+
+// Renders into an RGB buffer and encodes it as a PNG.
+fn render_png<F>(width: u32, height: u32, draw: F) -> Result<Vec<u8>, Error>
+where
+    F: FnOnce(DrawingArea<BitMapBackend, plotters::coord::Shift>) -> ChartResult,
+{
+    register_font();
+
+    let mut buffer = vec![0u8; (width * height * 3) as usize];
+    {
+        let root = BitMapBackend::with_buffer(&mut buffer, (width, height)).into_drawing_area();
+        draw(root).map_err(|err| InnerError::ChartError(err.to_string()))?;
+    }
+
+    let image = image::RgbImage::from_raw(width, height, buffer)
+        .ok_or_else(|| InnerError::ChartError("Chart buffer has the wrong size".to_string()))?;
+
+    let mut png: Vec<u8> = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .map_err(|err| InnerError::ChartError(err.to_string()))?;
+
+    Ok(png)
+}
+
+fn format_day_label(dates: &[NaiveDate], idx: i32) -> String {
+    usize::try_from(idx)
+        .ok()
+        .and_then(|idx| dates.get(idx))
+        .map(|date| date.format("%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+pub fn render_balance_chart(
+    name: &str,
+    history: &[(NaiveDate, Ugocoin)],
+) -> Result<Vec<u8>, Error> {
+    let dates: Vec<NaiveDate> = history.iter().map(|(date, _)| *date).collect();
+    let coins: Vec<f64> = history
+        .iter()
+        .map(|(_, balance)| balance.as_ugocents() as f64 / 100.0)
+        .collect();
+
+    let min = coins.iter().cloned().fold(0.0, f64::min);
+    let max = coins.iter().cloned().fold(1.0, f64::max) * 1.1;
+    let num_days = dates.len() as i32;
+
+    render_png(CHART_WIDTH, CHART_HEIGHT, |root| {
+        root.fill(&WHITE)?;
+
+        let mut chart = ChartBuilder::on(&root)
+            .caption(format!("UGOcoin balance for {}", name), (FONT_FAMILY, 24))
+            .margin(16)
+            .x_label_area_size(32)
+            .y_label_area_size(64)
+            .build_cartesian_2d(0..(num_days - 1).max(1), min..max)?;
+
+        chart
+            .configure_mesh()
+            .x_labels(8)
+            .x_label_formatter(&|idx| format_day_label(&dates, *idx))
+            .y_label_formatter(&|coins| format!("U${:.2}", coins))
+            .draw()?;
+
+        chart.draw_series(LineSeries::new(
+            coins.iter().enumerate().map(|(idx, c)| (idx as i32, *c)),
+            BLUE.stroke_width(2),
+        ))?;
+
+        root.present()?;
+        Ok(())
+    })
+}
+
+// One row per user, one column per scrum. Missing cells mean no scrum was held that day.
+pub struct AttendanceGrid {
+    pub users: Vec<String>,
+    pub dates: Vec<NaiveDate>,
+    pub cells: Vec<(usize, usize, ScrumReact)>,
+}
+
+fn attendance_color(react: &ScrumReact) -> RGBColor {
+    match react {
+        ScrumReact::Available => RGBColor(76, 175, 80),
+        ScrumReact::Unavailable => RGBColor(229, 57, 53),
+        ScrumReact::Unknown => RGBColor(189, 189, 189),
+    }
+}
+
+pub fn render_attendance_heatmap(grid: &AttendanceGrid) -> Result<Vec<u8>, Error> {
+    let num_days = grid.dates.len() as i32;
+    let num_users = grid.users.len() as i32;
+
+    render_png(CHART_WIDTH, CHART_HEIGHT, |root| {
+        root.fill(&WHITE)?;
+
+        let mut chart = ChartBuilder::on(&root)
+            .caption("Scrum attendance", (FONT_FAMILY, 24))
+            .margin(16)
+            .x_label_area_size(32)
+            .y_label_area_size(96)
+            .build_cartesian_2d(
+                // Segmented ranges include their end, so these give one segment per day and user.
+                (0..(num_days - 1).max(0)).into_segmented(),
+                (0..(num_users - 1).max(0)).into_segmented(),
+            )?;
+
+        chart
+            .configure_mesh()
+            .disable_mesh()
+            .x_labels(8)
+            .x_label_formatter(&|value| match value {
+                SegmentValue::CenterOf(idx) => format_day_label(&grid.dates, *idx),
+                _ => String::new(),
+            })
+            .y_labels(grid.users.len())
+            .y_label_formatter(&|value| match value {
+                SegmentValue::CenterOf(idx) => usize::try_from(*idx)
+                    .ok()
+                    .and_then(|idx| grid.users.get(idx))
+                    .cloned()
+                    .unwrap_or_default(),
+                _ => String::new(),
+            })
+            .draw()?;
+
+        chart.draw_series(grid.cells.iter().map(|(day, user, react)| {
+            let day = *day as i32;
+            let user = *user as i32;
+            Rectangle::new(
+                [
+                    (SegmentValue::Exact(day), SegmentValue::Exact(user)),
+                    (SegmentValue::Exact(day + 1), SegmentValue::Exact(user + 1)),
+                ],
+                attendance_color(react).filled(),
+            )
+        }))?;
+
+        root.present()?;
+        Ok(())
+    })
+}
+
+pub struct StreakSeries {
+    pub name: String,
+    pub streaks: Vec<(NaiveDate, i64)>,
+}
+
+pub fn render_streak_timeline(
+    dates: &[NaiveDate],
+    series: &[StreakSeries],
+) -> Result<Vec<u8>, Error> {
+    let num_days = dates.len() as i32;
+    let max_streak = series
+        .iter()
+        .flat_map(|s| s.streaks.iter().map(|(_, streak)| *streak))
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    let day_index = |date: &NaiveDate| dates.iter().position(|d| d == date).map(|i| i as i32);
+
+    render_png(CHART_WIDTH, CHART_HEIGHT, |root| {
+        root.fill(&WHITE)?;
+
+        let mut chart = ChartBuilder::on(&root)
+            .caption("Scrum streaks", (FONT_FAMILY, 24))
+            .margin(16)
+            .x_label_area_size(32)
+            .y_label_area_size(48)
+            .build_cartesian_2d(0..(num_days - 1).max(1), 0..max_streak + 1)?;
+
+        chart
+            .configure_mesh()
+            .x_labels(8)
+            .x_label_formatter(&|idx| format_day_label(dates, *idx))
+            .draw()?;
+
+        for (idx, s) in series.iter().enumerate() {
+            let color = Palette99::pick(idx).to_rgba();
+            chart
+                .draw_series(LineSeries::new(
+                    s.streaks
+                        .iter()
+                        .filter_map(|(date, streak)| Some((day_index(date)?, *streak))),
+                    color.stroke_width(2),
+                ))?
+                .label(s.name.clone())
+                .legend(move |(x, y)| {
+                    PathElement::new(vec![(x, y), (x + 16, y)], color.stroke_width(2))
+                });
+        }
+
+        chart
+            .configure_series_labels()
+            .label_font((FONT_FAMILY, 14))
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()?;
+
+        root.present()?;
+        Ok(())
+    })
+}
+
+// The last `days` days, oldest first, ending with today.
+pub fn chart_dates(today: NaiveDate, days: u64) -> Vec<NaiveDate> {
+    (0..days)
+        .rev()
+        .filter_map(|offset| today.checked_sub_days(Days::new(offset)))
+        .collect()
+}
+
+pub async fn chart_balance(
+    db: &SqlitePool,
+    user: &User,
+    dates: &[NaiveDate],
+) -> Result<Vec<u8>, Error> {
+    let account = get_user_account(db, user).await?;
+    let history = tx::get_balance_history(db, &account, dates).await?;
+
+    render_balance_chart(&user.display_name, &history)
+}
+
+pub async fn chart_attendance(db: &SqlitePool, dates: &[NaiveDate]) -> Result<Vec<u8>, Error> {
+    let mut users = user::get_all_users(db).await?;
+    users.sort_by_key(|u| u.id);
+
+    let user_rows: HashMap<i64, usize> = users
+        .iter()
+        .enumerate()
+        .map(|(idx, u)| (u.id, idx))
+        .collect();
+
+    let attendance = match dates.first() {
+        Some(first) => scrum::get_attendance_since(db, *first).await?,
+        None => Vec::new(),
+    };
+
+    let cells = attendance
+        .iter()
+        .filter_map(|a| {
+            let day = dates.iter().position(|d| *d == a.scrum_date)?;
+            let row = *user_rows.get(&a.user_id)?;
+            Some((day, row, a.response))
+        })
+        .collect();
+
+    render_attendance_heatmap(&AttendanceGrid {
+        users: users.into_iter().map(|u| u.display_name).collect(),
+        dates: dates.to_vec(),
+        cells,
+    })
+}
+
+pub async fn chart_streaks(db: &SqlitePool, dates: &[NaiveDate]) -> Result<Vec<u8>, Error> {
+    let mut users = user::get_all_users(db).await?;
+    users.sort_by_key(|u| u.id);
+
+    let history = match dates.first() {
+        Some(first) => digest::get_streak_history_since(db, *first).await?,
+        None => Vec::new(),
+    };
+
+    let series: Vec<StreakSeries> = users
+        .into_iter()
+        .map(|u| StreakSeries {
+            streaks: history
+                .iter()
+                .filter(|h| h.user_id == u.id)
+                .map(|h| (h.snapshot_date, h.streak))
+                .collect(),
+            name: u.display_name,
+        })
+        .collect();
+
+    render_streak_timeline(dates, &series)
+}
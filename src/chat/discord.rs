@@ -0,0 +1,278 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use serenity::async_trait;
+use serenity::builder::{CreateInteractionResponse, CreateInteractionResponseFollowup};
+use serenity::http::Http;
+use serenity::json::{hashmap_to_json_map, Value};
+use serenity::model::application::interaction::InteractionResponseType;
+use serenity::model::channel::{AttachmentType, ReactionType};
+use serenity::model::id::{ChannelId, MessageId, UserId};
+
+use super::{Author, Chat, InteractionHandle, InteractionReply, Reply, SelectMenu};
+use crate::error::Error;
+use crate::metrics;
+
+// Discord returns at most this many reactors per request.
+const REACTORS_PAGE_SIZE: u8 = 100;
+
+pub struct DiscordChat {
+    http: Arc<Http>,
+}
+
+impl DiscordChat {
+    pub fn new(http: Arc<Http>) -> DiscordChat {
+        DiscordChat { http }
+    }
+}
+
+// Every failed Discord call goes through here, so they all get counted.
+fn api_error(err: serenity::Error) -> Error {
+    metrics::record_discord_error(&err);
+    err.into()
+}
+
+fn unicode_reaction(emoji: &str) -> ReactionType {
+    ReactionType::Unicode(emoji.to_string())
+}
+
+fn attachments<'a>(reply: &Reply<'a>) -> Vec<AttachmentType<'a>> {
+    reply
+        .file
+        .iter()
+        .map(|file| AttachmentType::Bytes {
+            data: Cow::Borrowed(file.data),
+            filename: file.filename.to_string(),
+        })
+        .collect()
+}
+
+#[async_trait]
+impl Chat for DiscordChat {
+    async fn send_message(&self, channel_id: ChannelId, content: &str) -> Result<MessageId, Error> {
+        let message = channel_id
+            .send_message(&self.http, |message| message.content(content))
+            .await
+            .map_err(api_error)?;
+
+        Ok(message.id)
+    }
+
+    async fn send_direct_message(
+        &self,
+        user_id: UserId,
+        content: &str,
+    ) -> Result<MessageId, Error> {
+        let channel = user_id
+            .create_dm_channel(&self.http)
+            .await
+            .map_err(api_error)?;
+        let message = channel
+            .send_message(&self.http, |message| message.content(content))
+            .await
+            .map_err(api_error)?;
+
+        Ok(message.id)
+    }
+
+    async fn send_menu(
+        &self,
+        channel_id: ChannelId,
+        content: &str,
+        menu: &SelectMenu,
+    ) -> Result<MessageId, Error> {
+        let message = channel_id
+            .send_message(&self.http, |message| {
+                message.content(content).components(|components| {
+                    components.create_action_row(|row| {
+                        row.create_select_menu(|select| {
+                            select
+                                .custom_id(&menu.custom_id)
+                                .placeholder(&menu.placeholder)
+                                .min_values(1)
+                                .max_values(menu.options.len() as u64)
+                                .options(|options| {
+                                    for option in &menu.options {
+                                        options.create_option(|created| {
+                                            created.label(&option.label).value(&option.value)
+                                        });
+                                    }
+                                    options
+                                })
+                        })
+                    })
+                })
+            })
+            .await
+            .map_err(api_error)?;
+
+        Ok(message.id)
+    }
+
+    async fn edit_message(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: &str,
+    ) -> Result<(), Error> {
+        channel_id
+            .edit_message(&self.http, message_id, |edited| {
+                edited.content(content).components(|components| components)
+            })
+            .await
+            .map_err(api_error)?;
+
+        Ok(())
+    }
+
+    async fn message_author(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> Result<Author, Error> {
+        let message = channel_id
+            .message(&self.http, message_id)
+            .await
+            .map_err(api_error)?;
+
+        Ok(Author {
+            id: message.author.id,
+            bot: message.author.bot,
+        })
+    }
+
+    async fn react(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        emoji: &str,
+    ) -> Result<(), Error> {
+        channel_id
+            .create_reaction(&self.http, message_id, unicode_reaction(emoji))
+            .await
+            .map_err(api_error)?;
+
+        Ok(())
+    }
+
+    async fn reactors(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        emoji: &str,
+    ) -> Result<Vec<UserId>, Error> {
+        let mut reactors: Vec<UserId> = Vec::new();
+
+        // Page through by the last user seen, until a page comes back short.
+        loop {
+            let page = channel_id
+                .reaction_users(
+                    &self.http,
+                    message_id,
+                    unicode_reaction(emoji),
+                    Some(REACTORS_PAGE_SIZE),
+                    reactors.last().copied(),
+                )
+                .await
+                .map_err(api_error)?;
+
+            let page_len = page.len();
+            reactors.extend(page.into_iter().map(|user| user.id));
+
+            if page_len < REACTORS_PAGE_SIZE as usize {
+                return Ok(reactors);
+            }
+        }
+    }
+
+    async fn respond_to_interaction(
+        &self,
+        interaction: &InteractionHandle,
+        reply: InteractionReply<'_>,
+    ) -> Result<(), Error> {
+        let mut response = CreateInteractionResponse::default();
+        match &reply {
+            InteractionReply::Defer { ephemeral } => {
+                response
+                    .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                    .interaction_response_data(|data| data.ephemeral(*ephemeral));
+            }
+            InteractionReply::Message(message) => {
+                response
+                    .kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|data| {
+                        if let Some(content) = &message.content {
+                            data.content(content);
+                        }
+                        data.add_files(attachments(message))
+                            .ephemeral(message.ephemeral)
+                    });
+            }
+        }
+
+        let files = response.1;
+        let map = Value::from(hashmap_to_json_map(response.0));
+        if files.is_empty() {
+            self.http
+                .create_interaction_response(interaction.id.0, &interaction.token, &map)
+                .await
+                .map_err(api_error)?;
+        } else {
+            self.http
+                .create_interaction_response_with_files(
+                    interaction.id.0,
+                    &interaction.token,
+                    &map,
+                    files,
+                )
+                .await
+                .map_err(api_error)?;
+        }
+
+        Ok(())
+    }
+
+    async fn edit_interaction_response(
+        &self,
+        interaction: &InteractionHandle,
+        content: &str,
+    ) -> Result<(), Error> {
+        let map = serenity::json::json!({ "content": content });
+        self.http
+            .edit_original_interaction_response(&interaction.token, &map)
+            .await
+            .map_err(api_error)?;
+
+        Ok(())
+    }
+
+    async fn send_followup(
+        &self,
+        interaction: &InteractionHandle,
+        reply: Reply<'_>,
+    ) -> Result<(), Error> {
+        let mut followup = CreateInteractionResponseFollowup::default();
+        if let Some(content) = &reply.content {
+            followup.content(content);
+        }
+        followup
+            .add_files(attachments(&reply))
+            .ephemeral(reply.ephemeral);
+
+        let files = followup.1;
+        let map = Value::from(hashmap_to_json_map(followup.0));
+        if files.is_empty() {
+            self.http
+                .create_followup_message(&interaction.token, &map)
+                .await
+                .map_err(api_error)?;
+        } else {
+            self.http
+                .create_followup_message_with_files(&interaction.token, &map, files)
+                .await
+                .map_err(api_error)?;
+        }
+
+        Ok(())
+    }
+}
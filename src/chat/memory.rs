@@ -0,0 +1,366 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::sync::Mutex;
+
+use serenity::async_trait;
+use serenity::model::id::{ChannelId, MessageId, UserId};
+
+use super::{Author, Chat, InteractionHandle, InteractionReply, Reply, SelectMenu};
+use crate::error::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryMessage {
+    pub id: MessageId,
+    pub channel_id: ChannelId,
+    pub author: Author,
+    pub content: String,
+    pub ephemeral: bool,
+    // Reactors per emoji, in the order they reacted.
+    pub reactions: BTreeMap<String, Vec<UserId>>,
+    pub menu: Option<SelectMenu>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    next_id: u64,
+    messages: BTreeMap<MessageId, MemoryMessage>,
+    // Replies to each interaction, by token. The first is the interaction response.
+    interactions: BTreeMap<String, Vec<MemoryMessage>>,
+    // Deferred interactions still showing "thinking...". Like Discord, the first followup replaces
+    // that message instead of adding one, and keeps its visibility.
+    thinking: BTreeSet<String>,
+    // Channels that fail every send, and whether the failure is worth retrying.
+    broken_channels: BTreeMap<ChannelId, bool>,
+}
+
+// An in-memory stand-in for Discord, for running workflows in tests. Messages sent by the bot are
+// authored by `bot_id`, and tests play the part of users with the post and react helpers.
+pub struct MemoryChat {
+    bot_id: UserId,
+    state: Mutex<MemoryState>,
+}
+
+// What Discord answers for a message that doesn't exist.
+fn message_not_found() -> Error {
+    serenity::Error::Other("Unknown Message").into()
+}
+
+impl MemoryChat {
+    pub fn new(bot_id: UserId) -> MemoryChat {
+        MemoryChat {
+            bot_id,
+            state: Mutex::new(MemoryState {
+                next_id: 1,
+                ..Default::default()
+            }),
+        }
+    }
+
+    // Makes everything the bot does in a channel fail until it's restored. A retryable break
+    // looks like a dropped connection, otherwise like missing access.
+    pub fn break_channel(&self, channel_id: ChannelId, retryable: bool) {
+        self.state
+            .lock()
+            .unwrap()
+            .broken_channels
+            .insert(channel_id, retryable);
+    }
+
+    pub fn restore_channel(&self, channel_id: ChannelId) {
+        self.state
+            .lock()
+            .unwrap()
+            .broken_channels
+            .remove(&channel_id);
+    }
+
+    fn check_channel(&self, channel_id: ChannelId) -> Result<(), Error> {
+        match self.state.lock().unwrap().broken_channels.get(&channel_id) {
+            Some(true) => Err(serenity::Error::Io(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "Connection reset",
+            ))
+            .into()),
+            Some(false) => Err(serenity::Error::Other("Missing access").into()),
+            None => Ok(()),
+        }
+    }
+
+    fn bot(&self) -> Author {
+        Author {
+            id: self.bot_id,
+            bot: true,
+        }
+    }
+
+    fn insert_message(
+        &self,
+        channel_id: ChannelId,
+        author: Author,
+        content: &str,
+        ephemeral: bool,
+        menu: Option<SelectMenu>,
+    ) -> MemoryMessage {
+        let mut state = self.state.lock().unwrap();
+        let id = MessageId(state.next_id);
+        state.next_id += 1;
+
+        let message = MemoryMessage {
+            id,
+            channel_id,
+            author,
+            content: content.to_string(),
+            ephemeral,
+            reactions: BTreeMap::new(),
+            menu,
+        };
+        state.messages.insert(id, message.clone());
+        message
+    }
+
+    // Posts a message as a user, e.g. to have something to tip.
+    pub fn post_as(&self, user_id: UserId, channel_id: ChannelId, content: &str) -> MessageId {
+        let author = Author {
+            id: user_id,
+            bot: false,
+        };
+        self.insert_message(channel_id, author, content, false, None)
+            .id
+    }
+
+    pub fn add_reaction(&self, message_id: MessageId, user_id: UserId, emoji: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(message) = state.messages.get_mut(&message_id) {
+            let reactors = message.reactions.entry(emoji.to_string()).or_default();
+            if !reactors.contains(&user_id) {
+                reactors.push(user_id);
+            }
+        }
+    }
+
+    pub fn remove_reaction(&self, message_id: MessageId, user_id: UserId, emoji: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(message) = state.messages.get_mut(&message_id) {
+            if let Some(reactors) = message.reactions.get_mut(emoji) {
+                reactors.retain(|reactor| *reactor != user_id);
+            }
+        }
+    }
+
+    pub fn message(&self, message_id: MessageId) -> Option<MemoryMessage> {
+        self.state
+            .lock()
+            .unwrap()
+            .messages
+            .get(&message_id)
+            .cloned()
+    }
+
+    // Messages in a channel, oldest first.
+    pub fn messages(&self, channel_id: ChannelId) -> Vec<MemoryMessage> {
+        self.state
+            .lock()
+            .unwrap()
+            .messages
+            .values()
+            .filter(|message| message.channel_id == channel_id)
+            .cloned()
+            .collect()
+    }
+
+    // DMs land in a channel with the same ID as the user they were sent to.
+    pub fn direct_messages(&self, user_id: UserId) -> Vec<MemoryMessage> {
+        self.messages(ChannelId(user_id.0))
+    }
+
+    pub fn interaction_replies(&self, token: &str) -> Vec<MemoryMessage> {
+        self.state
+            .lock()
+            .unwrap()
+            .interactions
+            .get(token)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn push_interaction_reply(&self, interaction: &InteractionHandle, reply: &Reply<'_>) {
+        let content = reply.content.clone().unwrap_or_default();
+        let message = MemoryMessage {
+            id: MessageId(0),
+            channel_id: ChannelId(0),
+            author: self.bot(),
+            content,
+            ephemeral: reply.ephemeral,
+            reactions: BTreeMap::new(),
+            menu: None,
+        };
+
+        self.state
+            .lock()
+            .unwrap()
+            .interactions
+            .entry(interaction.token.clone())
+            .or_default()
+            .push(message);
+    }
+}
+
+#[async_trait]
+impl Chat for MemoryChat {
+    async fn send_message(&self, channel_id: ChannelId, content: &str) -> Result<MessageId, Error> {
+        self.check_channel(channel_id)?;
+        Ok(self
+            .insert_message(channel_id, self.bot(), content, false, None)
+            .id)
+    }
+
+    async fn send_direct_message(
+        &self,
+        user_id: UserId,
+        content: &str,
+    ) -> Result<MessageId, Error> {
+        self.check_channel(ChannelId(user_id.0))?;
+        Ok(self
+            .insert_message(ChannelId(user_id.0), self.bot(), content, false, None)
+            .id)
+    }
+
+    async fn send_menu(
+        &self,
+        channel_id: ChannelId,
+        content: &str,
+        menu: &SelectMenu,
+    ) -> Result<MessageId, Error> {
+        self.check_channel(channel_id)?;
+        Ok(self
+            .insert_message(channel_id, self.bot(), content, false, Some(menu.clone()))
+            .id)
+    }
+
+    async fn edit_message(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: &str,
+    ) -> Result<(), Error> {
+        self.check_channel(channel_id)?;
+        let mut state = self.state.lock().unwrap();
+        let message = state
+            .messages
+            .get_mut(&message_id)
+            .ok_or_else(message_not_found)?;
+        message.content = content.to_string();
+        message.menu = None;
+
+        Ok(())
+    }
+
+    async fn message_author(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> Result<Author, Error> {
+        self.check_channel(channel_id)?;
+        self.message(message_id)
+            .map(|message| message.author)
+            .ok_or_else(message_not_found)
+    }
+
+    async fn react(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        emoji: &str,
+    ) -> Result<(), Error> {
+        self.check_channel(channel_id)?;
+        self.message(message_id).ok_or_else(message_not_found)?;
+        self.add_reaction(message_id, self.bot_id, emoji);
+
+        Ok(())
+    }
+
+    async fn reactors(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        emoji: &str,
+    ) -> Result<Vec<UserId>, Error> {
+        self.check_channel(channel_id)?;
+        let message = self.message(message_id).ok_or_else(message_not_found)?;
+
+        Ok(message.reactions.get(emoji).cloned().unwrap_or_default())
+    }
+
+    async fn respond_to_interaction(
+        &self,
+        interaction: &InteractionHandle,
+        reply: InteractionReply<'_>,
+    ) -> Result<(), Error> {
+        if self
+            .state
+            .lock()
+            .unwrap()
+            .interactions
+            .contains_key(&interaction.token)
+        {
+            return Err(serenity::Error::Other("Interaction has already been acknowledged").into());
+        }
+
+        let reply = match reply {
+            InteractionReply::Defer { ephemeral } => {
+                self.state
+                    .lock()
+                    .unwrap()
+                    .thinking
+                    .insert(interaction.token.clone());
+                Reply {
+                    content: None,
+                    ephemeral,
+                    file: None,
+                }
+            }
+            InteractionReply::Message(reply) => reply,
+        };
+        self.push_interaction_reply(interaction, &reply);
+
+        Ok(())
+    }
+
+    async fn edit_interaction_response(
+        &self,
+        interaction: &InteractionHandle,
+        content: &str,
+    ) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        state.thinking.remove(&interaction.token);
+        let original = state
+            .interactions
+            .get_mut(&interaction.token)
+            .and_then(|replies| replies.first_mut())
+            .ok_or_else(message_not_found)?;
+        original.content = content.to_string();
+
+        Ok(())
+    }
+
+    async fn send_followup(
+        &self,
+        interaction: &InteractionHandle,
+        reply: Reply<'_>,
+    ) -> Result<(), Error> {
+        let replaces_thinking = self
+            .state
+            .lock()
+            .unwrap()
+            .thinking
+            .remove(&interaction.token);
+        if replaces_thinking {
+            return self
+                .edit_interaction_response(interaction, &reply.content.unwrap_or_default())
+                .await;
+        }
+        self.push_interaction_reply(interaction, &reply);
+
+        Ok(())
+    }
+}
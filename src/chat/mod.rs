@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+
+use serenity::async_trait;
+use serenity::model::id::{ChannelId, InteractionId, MessageId, UserId};
+
+use crate::error::Error;
+
+mod discord;
+mod memory;
+pub use discord::DiscordChat;
+pub use memory::{MemoryChat, MemoryMessage};
+
+// A file attached to a reply, e.g. a rendered chart.
+#[derive(Debug, Clone, Copy)]
+pub struct Attachment<'a> {
+    pub filename: &'a str,
+    pub data: &'a [u8],
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Reply<'a> {
+    pub content: Option<String>,
+    pub ephemeral: bool,
+    pub file: Option<Attachment<'a>>,
+}
+
+// A dropdown under a message where people pick one or more options.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SelectMenu {
+    // Comes back with every pick, to tell menus apart.
+    pub custom_id: String,
+    pub placeholder: String,
+    pub options: Vec<SelectOption>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SelectOption {
+    pub value: String,
+    pub label: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum InteractionReply<'a> {
+    // Tells Discord we're working on it, so the interaction doesn't time out.
+    Defer { ephemeral: bool },
+    Message(Reply<'a>),
+}
+
+// Everything needed to answer an interaction after the fact.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InteractionHandle {
+    pub id: InteractionId,
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Author {
+    pub id: UserId,
+    pub bot: bool,
+}
+
+// The chat operations the bot uses. Workflow code goes through this instead of serenity, so it can
+// run against MemoryChat in tests.
+#[async_trait]
+pub trait Chat: Send + Sync {
+    async fn send_message(&self, channel_id: ChannelId, content: &str) -> Result<MessageId, Error>;
+    async fn send_direct_message(&self, user_id: UserId, content: &str)
+        -> Result<MessageId, Error>;
+    async fn send_menu(
+        &self,
+        channel_id: ChannelId,
+        content: &str,
+        menu: &SelectMenu,
+    ) -> Result<MessageId, Error>;
+    // Also removes any menu, so nobody picks from a message that's done with.
+    async fn edit_message(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: &str,
+    ) -> Result<(), Error>;
+    async fn message_author(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> Result<Author, Error>;
+    async fn react(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        emoji: &str,
+    ) -> Result<(), Error>;
+    // Everyone who reacted to a message with an emoji, including the bot itself.
+    async fn reactors(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        emoji: &str,
+    ) -> Result<Vec<UserId>, Error>;
+    async fn respond_to_interaction(
+        &self,
+        interaction: &InteractionHandle,
+        reply: InteractionReply<'_>,
+    ) -> Result<(), Error>;
+    async fn edit_interaction_response(
+        &self,
+        interaction: &InteractionHandle,
+        content: &str,
+    ) -> Result<(), Error>;
+    async fn send_followup(
+        &self,
+        interaction: &InteractionHandle,
+        reply: Reply<'_>,
+    ) -> Result<(), Error>;
+}
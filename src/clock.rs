@@ -0,0 +1,47 @@
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Local};
+
+// Where entry points get the current time. Everything below them takes `now` as an argument, so
+// swapping the clock is enough to run the bot against a simulated calendar.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Local>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+}
+
+// A clock that only moves when told to.
+pub struct SimulatedClock {
+    now: Mutex<DateTime<Local>>,
+}
+
+impl SimulatedClock {
+    pub fn new(start: DateTime<Local>) -> SimulatedClock {
+        SimulatedClock {
+            now: Mutex::new(start),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Local>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    // Moves forward in real elapsed time, so DST changes shift the wall clock accordingly.
+    pub fn advance(&self, by: Duration) -> DateTime<Local> {
+        let mut now = self.now.lock().unwrap();
+        *now += by;
+        *now
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> DateTime<Local> {
+        *self.now.lock().unwrap()
+    }
+}
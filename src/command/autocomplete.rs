@@ -0,0 +1,31 @@
+// Discord shows at most this many autocomplete suggestions.
+pub const MAX_AUTOCOMPLETE_CHOICES: usize = 25;
+pub const MAX_CHOICE_NAME_LENGTH: usize = 100;
+
+// A suggestion offered while a user fills in an option.
+#[derive(Debug, Clone)]
+pub enum AutocompleteChoice {
+    Integer { name: String, value: i64 },
+}
+
+impl AutocompleteChoice {
+    pub fn name(&self) -> &str {
+        let AutocompleteChoice::Integer { name, .. } = self;
+        name
+    }
+}
+
+// Keeps the suggestions whose name contains what the user has typed so far.
+pub fn filter_choices(partial: &str, choices: Vec<AutocompleteChoice>) -> Vec<AutocompleteChoice> {
+    let partial = partial.trim().to_lowercase();
+    choices
+        .into_iter()
+        .filter(|choice| choice.name().to_lowercase().contains(&partial))
+        .take(MAX_AUTOCOMPLETE_CHOICES)
+        .collect()
+}
+
+// Discord rejects choices with longer names.
+pub fn truncate_choice_name(name: &str) -> String {
+    name.chars().take(MAX_CHOICE_NAME_LENGTH).collect()
+}
@@ -0,0 +1,1074 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use chrono::{DateTime, Local};
+
+use log::{error, info};
+
+use serenity::async_trait;
+use serenity::builder::{CreateApplicationCommand, CreateApplicationCommandOption};
+use serenity::client::Context;
+use serenity::json::Value;
+use serenity::model::application::command::CommandOptionType;
+use serenity::model::application::interaction::application_command::{
+    ApplicationCommandInteraction, CommandDataOption,
+};
+use serenity::model::application::interaction::autocomplete::AutocompleteInteraction;
+use serenity::model::id::{GuildId, UserId};
+use serenity::model::Permissions;
+
+use sqlx::SqlitePool;
+
+use crate::chart;
+use crate::chat::{Chat, InteractionHandle, InteractionReply, Reply};
+use crate::error::{new_incident_id, Error, InnerError, WithContext};
+use crate::jobs;
+use crate::metrics;
+use crate::outbox;
+use crate::permission::{self, Permission};
+use crate::ugocoin;
+use crate::ugocoin::account::Ugocoin;
+use crate::user;
+
+pub mod autocomplete;
+pub mod options;
+mod respond;
+use autocomplete::{
+    filter_choices, truncate_choice_name, AutocompleteChoice, MAX_AUTOCOMPLETE_CHOICES,
+};
+use options::{parse_options, CommandArgs, OptionSpec};
+use respond::{Responder, ResponseMode};
+
+type BoxedCommand = Box<dyn Command + 'static + Send + Sync>;
+
+#[async_trait]
+trait Command: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    // Who is allowed to run this command. Checked in run_command before the command runs.
+    fn permission(&self) -> Permission {
+        Permission::Everyone
+    }
+    // The options this command takes. These are registered with Discord, and validated into the
+    // CommandArgs passed to run.
+    fn options(&self) -> Vec<OptionSpec> {
+        Vec::new()
+    }
+    // Commands with subcommands are only containers: Discord always invokes one of the leaves, and
+    // that leaf's options and run are used. A subcommand with subcommands of its own is registered
+    // as a subcommand group.
+    fn subcommands(&self) -> &[BoxedCommand] {
+        &[]
+    }
+    fn register<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command.name(self.name()).description(self.description());
+        if self.subcommands().is_empty() {
+            for spec in self.options() {
+                command.create_option(|option| spec.register(option));
+            }
+        } else {
+            for subcommand in self.subcommands() {
+                command.create_option(|option| register_subcommand(subcommand.as_ref(), option));
+            }
+        }
+        command
+    }
+    // Commands that might not answer within Discord's 3 second limit should defer.
+    fn response_mode(&self) -> ResponseMode {
+        ResponseMode::Immediate
+    }
+    async fn run(
+        &self,
+        _db: &SqlitePool,
+        _responder: &Responder<'_>,
+        _args: &CommandArgs,
+    ) -> Result<(), Error> {
+        Err(InnerError::CommandNotFound(self.name().to_string()).into())
+    }
+    // Suggestions for an option declared with autocomplete, given what the user has typed so far.
+    async fn autocomplete(
+        &self,
+        _db: &SqlitePool,
+        _user_id: &UserId,
+        _option: &str,
+        _partial: &str,
+    ) -> Result<Vec<AutocompleteChoice>, Error> {
+        Ok(Vec::new())
+    }
+}
+
+fn register_subcommand<'a>(
+    command: &dyn Command,
+    option: &'a mut CreateApplicationCommandOption,
+) -> &'a mut CreateApplicationCommandOption {
+    option
+        .name(command.name())
+        .description(command.description());
+
+    if command.subcommands().is_empty() {
+        option.kind(CommandOptionType::SubCommand);
+        for spec in command.options() {
+            option.create_sub_option(|sub_option| spec.register(sub_option));
+        }
+    } else {
+        option.kind(CommandOptionType::SubCommandGroup);
+        for subcommand in command.subcommands() {
+            option.create_sub_option(|sub_option| {
+                register_subcommand(subcommand.as_ref(), sub_option)
+            });
+        }
+    }
+
+    option
+}
+
+// The leaf command an interaction invoked, and the options meant for it.
+struct ResolvedCommand<'a> {
+    command: &'a dyn Command,
+    options: &'a [CommandDataOption],
+    path: String,
+    // The strictest permission of any command along the way, so a group can guard all of its
+    // subcommands.
+    permission: Permission,
+}
+
+fn resolve_command<'a>(
+    top: &'a dyn Command,
+    options: &'a [CommandDataOption],
+) -> Result<ResolvedCommand<'a>, Error> {
+    let mut resolved = ResolvedCommand {
+        command: top,
+        options,
+        path: top.name().to_string(),
+        permission: top.permission(),
+    };
+
+    while !resolved.command.subcommands().is_empty() {
+        let option = resolved
+            .options
+            .first()
+            .filter(|option| {
+                matches!(
+                    option.kind,
+                    CommandOptionType::SubCommand | CommandOptionType::SubCommandGroup
+                )
+            })
+            .ok_or_else(|| InnerError::CommandNotFound(resolved.path.clone()))?;
+
+        resolved.path = format!("{} {}", resolved.path, option.name);
+        let subcommand = resolved
+            .command
+            .subcommands()
+            .iter()
+            .find(|subcommand| subcommand.name() == option.name)
+            .ok_or_else(|| InnerError::CommandNotFound(resolved.path.clone()))?;
+
+        resolved.command = subcommand.as_ref();
+        resolved.options = &option.options;
+        resolved.permission = resolved.permission.max(subcommand.permission());
+    }
+
+    Ok(resolved)
+}
+
+struct TestCommand {}
+
+#[async_trait]
+impl Command for TestCommand {
+    fn name(&self) -> &'static str {
+        "test"
+    }
+
+    fn description(&self) -> &'static str {
+        "A test command for the UGO bot"
+    }
+
+    async fn run(
+        &self,
+        db: &SqlitePool,
+        responder: &Responder<'_>,
+        _args: &CommandArgs,
+    ) -> Result<(), Error> {
+        let discord_id = responder.command.user.id;
+        let user = user::get_user(db, &discord_id)
+            .await
+            .with_context("Fetching command user")?;
+
+        let content: String = format!("Hello {}", user.display_name);
+
+        responder
+            .respond(content)
+            .await
+            .with_context("Creating command response")
+    }
+}
+
+struct BalanceInfo {
+    name: String,
+    balance: Ugocoin,
+    streak: i64,
+}
+struct BalanceCommand {}
+
+#[async_trait]
+impl Command for BalanceCommand {
+    fn name(&self) -> &'static str {
+        "balances"
+    }
+
+    fn description(&self) -> &'static str {
+        "Display balances for all employees, in the name of financial transparency."
+    }
+
+    // Looks up every account, which can take a while.
+    fn response_mode(&self) -> ResponseMode {
+        ResponseMode::Deferred { ephemeral: false }
+    }
+
+    async fn run(
+        &self,
+        db: &SqlitePool,
+        responder: &Responder<'_>,
+        _args: &CommandArgs,
+    ) -> Result<(), Error> {
+        let users = user::get_all_users(db).await?;
+
+        let mut balance_infos: Vec<BalanceInfo> = Vec::new();
+
+        for u in users {
+            let account = ugocoin::account::get_user_account(db, &u).await?;
+            balance_infos.push(BalanceInfo {
+                name: u.display_name,
+                balance: account.balance,
+                streak: u.streak,
+            });
+        }
+
+        let central_account = ugocoin::account::get_central_bank_account(db).await?;
+        balance_infos.push(BalanceInfo {
+            name: String::from("UGOcoin Central Bank"),
+            balance: central_account.balance,
+            streak: 0,
+        });
+
+        balance_infos.sort_by_key(|info| info.balance);
+        balance_infos.reverse();
+
+        let mut balance_string: String = String::new();
+
+        let max_name_width = balance_infos.iter().map(|i| i.name.len()).max().unwrap();
+        let max_coin_width = balance_infos
+            .iter()
+            .map(|i| format!("{}", i.balance).len())
+            .max()
+            .unwrap();
+
+        for info in balance_infos {
+            balance_string += &format!(
+                "{:<max_name_width$} | {:<max_coin_width$} | (scrum streak {})\n",
+                info.name, info.balance, info.streak,
+            );
+        }
+
+        responder
+            .respond(format!(
+                "Current UGOcoin balances:\n\n```{}```",
+                balance_string
+            ))
+            .await
+            .with_context("Creating balances response")
+    }
+}
+
+const DEFAULT_CHART_DAYS: i64 = 30;
+const MAX_CHART_DAYS: i64 = 365;
+
+const CHART_KINDS: [(&str, &str); 3] = [
+    ("balance", "UGOcoin balance over time."),
+    ("attendance", "Who answered the scrummons, and how."),
+    ("streak", "Scrum streaks over time."),
+];
+
+fn chart_options(kind: &str) -> Vec<OptionSpec> {
+    let mut options = Vec::new();
+    if kind == "balance" {
+        options.push(OptionSpec::user(
+            "user",
+            "Whose balance to chart. Defaults to you.",
+        ));
+    }
+    options.push(
+        OptionSpec::integer("days", "How many days of history to show.").range(2, MAX_CHART_DAYS),
+    );
+    options
+}
+
+struct ChartCommand {
+    subcommands: Vec<BoxedCommand>,
+}
+
+impl ChartCommand {
+    fn new() -> ChartCommand {
+        ChartCommand {
+            subcommands: CHART_KINDS
+                .iter()
+                .map(|(kind, description)| {
+                    Box::new(ChartSubcommand { kind, description }) as BoxedCommand
+                })
+                .collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for ChartCommand {
+    fn name(&self) -> &'static str {
+        "chart"
+    }
+
+    fn description(&self) -> &'static str {
+        "Chart the history of UGO."
+    }
+
+    fn subcommands(&self) -> &[BoxedCommand] {
+        &self.subcommands
+    }
+}
+
+struct ChartSubcommand {
+    kind: &'static str,
+    description: &'static str,
+}
+
+#[async_trait]
+impl Command for ChartSubcommand {
+    fn name(&self) -> &'static str {
+        self.kind
+    }
+
+    fn description(&self) -> &'static str {
+        self.description
+    }
+
+    fn options(&self) -> Vec<OptionSpec> {
+        chart_options(self.kind)
+    }
+
+    // Rendering a year of history takes longer than Discord will wait.
+    fn response_mode(&self) -> ResponseMode {
+        ResponseMode::Deferred { ephemeral: false }
+    }
+
+    async fn run(
+        &self,
+        db: &SqlitePool,
+        responder: &Responder<'_>,
+        args: &CommandArgs,
+    ) -> Result<(), Error> {
+        let days = args.integer("days").unwrap_or(DEFAULT_CHART_DAYS);
+        let dates = chart::chart_dates(responder.now.date_naive(), days as u64);
+
+        let png = match self.kind {
+            "balance" => {
+                let discord_id = args.user("user").unwrap_or(responder.command.user.id);
+                let user = user::get_user(db, &discord_id)
+                    .await
+                    .with_context("Fetching user to chart")?;
+
+                chart::chart_balance(db, &user, &dates)
+                    .await
+                    .with_context("Charting balance")?
+            }
+            "attendance" => chart::chart_attendance(db, &dates)
+                .await
+                .with_context("Charting attendance")?,
+            "streak" => chart::chart_streaks(db, &dates)
+                .await
+                .with_context("Charting streaks")?,
+            other => {
+                return Err(InnerError::CommandNotFound(format!("chart {}", other)).into());
+            }
+        };
+
+        responder
+            .respond_with_file(&format!("{}.png", self.kind), &png)
+            .await
+            .with_context("Creating chart response")
+    }
+}
+
+struct RegisterCommand {}
+
+#[async_trait]
+impl Command for RegisterCommand {
+    fn name(&self) -> &'static str {
+        "register"
+    }
+
+    fn description(&self) -> &'static str {
+        "Join UGO and open a UGOcoin account."
+    }
+
+    fn options(&self) -> Vec<OptionSpec> {
+        vec![
+            OptionSpec::string("display_name", "The name UGO will know you by.")
+                .length(1, 32)
+                .required(),
+        ]
+    }
+
+    async fn run(
+        &self,
+        db: &SqlitePool,
+        responder: &Responder<'_>,
+        args: &CommandArgs,
+    ) -> Result<(), Error> {
+        let display_name = args.string("display_name").unwrap_or_default();
+
+        let content = match user::register_user(db, &responder.command.user.id, display_name).await
+        {
+            Ok(user) => format!(
+                "Welcome to UGO, {}. Your UGOcoin account is open.",
+                user.display_name
+            ),
+            Err(Error {
+                error: InnerError::AlreadyRegistered,
+                ..
+            }) => {
+                return responder
+                    .respond_ephemeral("You're already registered with UGO.")
+                    .await
+                    .with_context("Creating register response");
+            }
+            Err(other) => return Err(other).with_context("Registering user"),
+        };
+
+        responder
+            .respond(content)
+            .await
+            .with_context("Creating register response")
+    }
+}
+
+struct LinkCommand {}
+
+#[async_trait]
+impl Command for LinkCommand {
+    fn name(&self) -> &'static str {
+        "link"
+    }
+
+    fn description(&self) -> &'static str {
+        "Link this Discord account to an existing UGO employee, pending admin approval."
+    }
+
+    fn options(&self) -> Vec<OptionSpec> {
+        vec![OptionSpec::user("user", "Your main Discord account.").required()]
+    }
+
+    async fn run(
+        &self,
+        db: &SqlitePool,
+        responder: &Responder<'_>,
+        args: &CommandArgs,
+    ) -> Result<(), Error> {
+        let main_id = match args.user("user") {
+            Some(main_id) => main_id,
+            None => return Err(InnerError::UserNotFound.into()),
+        };
+
+        let main_user = user::get_user(db, &main_id)
+            .await
+            .with_context("Fetching user to link to")?;
+
+        let content =
+            match user::request_link(db, responder.now, &responder.command.user.id, &main_user)
+                .await
+            {
+                Ok(request_id) => format!(
+                    "Link request #{} to {} submitted. An admin needs to approve it.",
+                    request_id, main_user.display_name
+                ),
+                Err(Error {
+                    error: InnerError::AlreadyRegistered,
+                    ..
+                }) => {
+                    return responder
+                        .respond_ephemeral(
+                            "This Discord account is already linked to a UGO employee.",
+                        )
+                        .await
+                        .with_context("Creating link response");
+                }
+                Err(other) => return Err(other).with_context("Requesting link"),
+            };
+
+        responder
+            .respond(content)
+            .await
+            .with_context("Creating link response")
+    }
+}
+
+struct LinksCommand {
+    subcommands: Vec<BoxedCommand>,
+}
+
+impl LinksCommand {
+    fn new() -> LinksCommand {
+        LinksCommand {
+            subcommands: vec![
+                Box::new(LinksPendingCommand {}),
+                Box::new(LinkDecisionCommand { approve: true }),
+                Box::new(LinkDecisionCommand { approve: false }),
+            ],
+        }
+    }
+}
+
+#[async_trait]
+impl Command for LinksCommand {
+    fn name(&self) -> &'static str {
+        "links"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Admin
+    }
+
+    fn description(&self) -> &'static str {
+        "Review requests to link Discord accounts."
+    }
+
+    fn subcommands(&self) -> &[BoxedCommand] {
+        &self.subcommands
+    }
+}
+
+struct LinksPendingCommand {}
+
+#[async_trait]
+impl Command for LinksPendingCommand {
+    fn name(&self) -> &'static str {
+        "pending"
+    }
+
+    fn description(&self) -> &'static str {
+        "List pending link requests."
+    }
+
+    async fn run(
+        &self,
+        db: &SqlitePool,
+        responder: &Responder<'_>,
+        _args: &CommandArgs,
+    ) -> Result<(), Error> {
+        let requests = user::get_pending_link_requests(db)
+            .await
+            .with_context("Fetching pending link requests")?;
+
+        let content = if requests.is_empty() {
+            String::from("No pending link requests.")
+        } else {
+            let mut content = String::from("Pending link requests:\n");
+            for request in requests {
+                content += &format!(
+                    "#{}: <@{}> as {} (requested <t:{}:R>)\n",
+                    request.id, request.discord_id, request.display_name, request.request_time
+                );
+            }
+            content
+        };
+
+        responder
+            .respond(content)
+            .await
+            .with_context("Creating links response")
+    }
+}
+
+struct LinkDecisionCommand {
+    approve: bool,
+}
+
+#[async_trait]
+impl Command for LinkDecisionCommand {
+    fn name(&self) -> &'static str {
+        if self.approve {
+            "approve"
+        } else {
+            "deny"
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        if self.approve {
+            "Approve a link request."
+        } else {
+            "Deny a link request."
+        }
+    }
+
+    fn options(&self) -> Vec<OptionSpec> {
+        vec![OptionSpec::integer("id", "The link request number.")
+            .autocomplete()
+            .required()]
+    }
+
+    async fn run(
+        &self,
+        db: &SqlitePool,
+        responder: &Responder<'_>,
+        args: &CommandArgs,
+    ) -> Result<(), Error> {
+        let request_id = args.integer("id").unwrap_or_default();
+
+        let content = if self.approve {
+            let request = user::approve_link_request(db, request_id)
+                .await
+                .with_context("Approving link request")?;
+            format!(
+                "Linked <@{}> to {}.",
+                request.discord_id, request.display_name
+            )
+        } else {
+            let request = user::deny_link_request(db, request_id)
+                .await
+                .with_context("Denying link request")?;
+            format!(
+                "Denied linking <@{}> to {}.",
+                request.discord_id, request.display_name
+            )
+        };
+
+        responder
+            .respond(content)
+            .await
+            .with_context("Creating links response")
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        _user_id: &UserId,
+        _option: &str,
+        partial: &str,
+    ) -> Result<Vec<AutocompleteChoice>, Error> {
+        let requests = user::get_pending_link_requests(db)
+            .await
+            .with_context("Fetching pending link requests")?;
+
+        let choices = requests
+            .into_iter()
+            .map(|request| AutocompleteChoice::Integer {
+                name: format!("#{}: {}", request.id, request.display_name),
+                value: request.id,
+            })
+            .collect();
+
+        Ok(filter_choices(partial, choices))
+    }
+}
+
+struct JobsCommand {}
+
+#[async_trait]
+impl Command for JobsCommand {
+    fn name(&self) -> &'static str {
+        "jobs"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Admin
+    }
+
+    fn description(&self) -> &'static str {
+        "List scheduled jobs, when they last ran and when they run next."
+    }
+
+    async fn run(
+        &self,
+        db: &SqlitePool,
+        responder: &Responder<'_>,
+        _args: &CommandArgs,
+    ) -> Result<(), Error> {
+        let mut content = String::new();
+        for job in jobs::jobs() {
+            let trigger = job.trigger();
+            content += &format!("**{}** ({})\n", job.name(), trigger);
+
+            let last_run = jobs::scheduler::get_last_run(db, job.name())
+                .await
+                .with_context("Fetching last job run")?;
+
+            content += &match last_run {
+                None => String::from("Last run: never\n"),
+                Some(run) => {
+                    let result = match (run.finished_at, run.succeeded, run.error) {
+                        (None, _, _) => String::from("⏳ still running"),
+                        (Some(_), Some(true), _) => String::from("✅ succeeded"),
+                        (Some(_), _, error) => format!(
+                            "❌ failed after {} attempt(s), retrying with backoff: {}",
+                            run.attempts,
+                            error.unwrap_or_default()
+                        ),
+                    };
+                    format!("Last run: <t:{}:f>, {}\n", run.started_at, result)
+                }
+            };
+
+            content += &format!(
+                "Next run: <t:{}:R>\n",
+                trigger.next(responder.now).timestamp()
+            );
+        }
+
+        responder
+            .respond(content)
+            .await
+            .with_context("Creating jobs response")
+    }
+}
+
+// Most undelivered outbox items to list, to stay under Discord's message length limit.
+const MAX_OUTBOX_ITEMS_SHOWN: usize = 15;
+
+struct OutboxCommand {
+    subcommands: Vec<BoxedCommand>,
+}
+
+impl OutboxCommand {
+    fn new() -> OutboxCommand {
+        OutboxCommand {
+            subcommands: vec![
+                Box::new(OutboxPendingCommand {}),
+                Box::new(OutboxRetryCommand {}),
+            ],
+        }
+    }
+}
+
+#[async_trait]
+impl Command for OutboxCommand {
+    fn name(&self) -> &'static str {
+        "outbox"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Admin
+    }
+
+    fn description(&self) -> &'static str {
+        "Inspect Discord messages the bot hasn't managed to deliver."
+    }
+
+    fn subcommands(&self) -> &[BoxedCommand] {
+        &self.subcommands
+    }
+}
+
+struct OutboxPendingCommand {}
+
+#[async_trait]
+impl Command for OutboxPendingCommand {
+    fn name(&self) -> &'static str {
+        "pending"
+    }
+
+    fn description(&self) -> &'static str {
+        "List undelivered and failed outbox items."
+    }
+
+    async fn run(
+        &self,
+        db: &SqlitePool,
+        responder: &Responder<'_>,
+        _args: &CommandArgs,
+    ) -> Result<(), Error> {
+        let entries = outbox::get_undelivered(db)
+            .await
+            .with_context("Fetching undelivered outbox items")?;
+
+        let content = if entries.is_empty() {
+            String::from("Nothing waiting in the outbox.")
+        } else {
+            let mut content = format!("{} undelivered outbox items:\n", entries.len());
+            for entry in entries.iter().take(MAX_OUTBOX_ITEMS_SHOWN) {
+                let status = match entry.failed_at {
+                    Some(failed_at) => format!("❌ gave up <t:{}:R>", failed_at),
+                    None => format!("⏳ next try <t:{}:R>", entry.next_attempt_at),
+                };
+                content += &format!(
+                    "#{}: {}, {} after {} attempts",
+                    entry.id,
+                    entry.describe(),
+                    status,
+                    entry.attempts
+                );
+                if let Some(last_error) = &entry.last_error {
+                    content += &format!(" (`{}`)", last_error);
+                }
+                content += "\n";
+            }
+            content
+        };
+
+        responder
+            .respond_ephemeral(content)
+            .await
+            .with_context("Creating outbox response")
+    }
+}
+
+struct OutboxRetryCommand {}
+
+#[async_trait]
+impl Command for OutboxRetryCommand {
+    fn name(&self) -> &'static str {
+        "retry"
+    }
+
+    fn description(&self) -> &'static str {
+        "Retry an outbox item the bot gave up on."
+    }
+
+    fn options(&self) -> Vec<OptionSpec> {
+        vec![OptionSpec::integer("id", "The outbox item number.")
+            .autocomplete()
+            .required()]
+    }
+
+    async fn run(
+        &self,
+        db: &SqlitePool,
+        responder: &Responder<'_>,
+        args: &CommandArgs,
+    ) -> Result<(), Error> {
+        let id = args.integer("id").unwrap_or_default();
+
+        outbox::retry(db, id, responder.now)
+            .await
+            .with_context(format!("Retrying outbox item {}", id))?;
+
+        responder
+            .respond_ephemeral(format!("Outbox item #{} will be retried shortly.", id))
+            .await
+            .with_context("Creating outbox retry response")
+    }
+
+    async fn autocomplete(
+        &self,
+        db: &SqlitePool,
+        _user_id: &UserId,
+        _option: &str,
+        partial: &str,
+    ) -> Result<Vec<AutocompleteChoice>, Error> {
+        let entries = outbox::get_undelivered(db)
+            .await
+            .with_context("Fetching undelivered outbox items")?;
+
+        let choices = entries
+            .into_iter()
+            .filter(|entry| entry.failed_at.is_some())
+            .map(|entry| AutocompleteChoice::Integer {
+                name: format!("#{}: {}", entry.id, entry.describe()),
+                value: entry.id,
+            })
+            .collect();
+
+        Ok(filter_choices(partial, choices))
+    }
+}
+
+type CommandMap = HashMap<String, BoxedCommand>;
+
+fn insert_command<C: Command + 'static + Send + Sync>(map: &mut CommandMap, command: C) {
+    map.insert(command.name().to_string(), Box::new(command));
+}
+
+lazy_static! {
+    static ref COMMAND_MAP: CommandMap = {
+        let mut m: CommandMap = HashMap::new();
+        insert_command(&mut m, TestCommand {});
+        insert_command(&mut m, BalanceCommand {});
+        insert_command(&mut m, ChartCommand::new());
+        insert_command(&mut m, RegisterCommand {});
+        insert_command(&mut m, LinkCommand {});
+        insert_command(&mut m, LinksCommand::new());
+        insert_command(&mut m, JobsCommand {});
+        insert_command(&mut m, OutboxCommand::new());
+        m
+    };
+}
+
+pub async fn register_commands(context: &Context, guild_id: GuildId) -> Result<(), Error> {
+    guild_id
+        .set_application_commands(&context.http, |commands| {
+            for (_, command) in COMMAND_MAP.iter() {
+                commands.create_application_command(|command_builder| {
+                    command.register(command_builder);
+
+                    // Hide admin commands from regular members. The runtime permission check
+                    // still guards them. Admins from the admins table or ADMIN_ROLE_ID who aren't
+                    // Discord administrators need a per-command override in the server's
+                    // integration settings to see them.
+                    if command.permission() >= Permission::Admin {
+                        command_builder.default_member_permissions(Permissions::ADMINISTRATOR);
+                    }
+
+                    command_builder
+                });
+            }
+            commands
+        })
+        .await?;
+
+    Ok(())
+}
+
+// Tells the user their command failed, privately. This works whether or not the command got as far as
+// responding before it failed.
+pub async fn reply_with_error(
+    chat: &dyn Chat,
+    command: &ApplicationCommandInteraction,
+    err: &Error,
+) -> Result<(), Error> {
+    let content = match err.error.user_message() {
+        Some(message) => {
+            info!("Command {} rejected: {}", command.data.name, err);
+            message
+        }
+        None => {
+            let incident_id = new_incident_id();
+            error!("Incident {}: {}", incident_id, err);
+            format!(
+                "Something went wrong on our end. Tell an admin about incident {}.",
+                incident_id
+            )
+        }
+    };
+
+    let interaction = InteractionHandle {
+        id: command.id,
+        token: command.token.clone(),
+    };
+    let reply = Reply {
+        content: Some(content),
+        ephemeral: true,
+        file: None,
+    };
+
+    let response = chat
+        .respond_to_interaction(&interaction, InteractionReply::Message(reply.clone()))
+        .await;
+
+    // Creating a response fails if the command already sent one or deferred, so follow up
+    // instead. A deferred response was cleared when the command failed, so the followup is its own
+    // private message.
+    if response.is_err() {
+        chat.send_followup(&interaction, reply).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn run_command(
+    db: &SqlitePool,
+    chat: &dyn Chat,
+    now: DateTime<Local>,
+    command: &ApplicationCommandInteraction,
+) -> Result<(), Error> {
+    let started = Instant::now();
+    let result = resolve_and_run_command(db, chat, now, command).await;
+    metrics::record_command(&command.data.name, &result, started.elapsed());
+
+    result
+}
+
+async fn resolve_and_run_command(
+    db: &SqlitePool,
+    chat: &dyn Chat,
+    now: DateTime<Local>,
+    command: &ApplicationCommandInteraction,
+) -> Result<(), Error> {
+    let command_struct = match COMMAND_MAP.get(&command.data.name) {
+        Some(command_struct) => command_struct,
+        None => return Err(InnerError::CommandNotFound(command.data.name.clone()).into()),
+    };
+    let resolved = resolve_command(command_struct.as_ref(), &command.data.options)?;
+
+    let user_permission = permission::get_permission(db, command.member.as_ref(), &command.user.id)
+        .await
+        .with_context("Checking command permissions")?;
+
+    if user_permission < resolved.permission {
+        return Err(InnerError::PermissionDenied.into());
+    }
+
+    // Bad options get reported to the user before the command ever runs.
+    let args = parse_options(&resolved.command.options(), resolved.options)?;
+
+    let responder = Responder::new(chat, command, now, resolved.command.response_mode());
+    responder.defer().await?;
+
+    let result = resolved.command.run(db, &responder, &args).await;
+    if result.is_err() {
+        // The error reply still goes out as a followup, so don't let this hide it.
+        if let Err(why) = responder.clear_deferred().await {
+            error!("Failed to clear deferred response: {}", why);
+        }
+    }
+
+    result
+}
+
+// Answers Discord's request for suggestions while the user is filling in an option.
+pub async fn run_autocomplete(
+    db: &SqlitePool,
+    context: &Context,
+    interaction: &AutocompleteInteraction,
+) -> Result<(), Error> {
+    let command_struct = match COMMAND_MAP.get(&interaction.data.name) {
+        Some(command_struct) => command_struct,
+        None => return Err(InnerError::CommandNotFound(interaction.data.name.clone()).into()),
+    };
+    let resolved = resolve_command(command_struct.as_ref(), &interaction.data.options)?;
+
+    let user_permission =
+        permission::get_permission(db, interaction.member.as_ref(), &interaction.user.id)
+            .await
+            .with_context("Checking autocomplete permissions")?;
+
+    // Don't leak suggestions to people who couldn't run the command anyway.
+    let mut choices = Vec::new();
+    if user_permission >= resolved.permission {
+        if let Some(focused) = resolved.options.iter().find(|option| option.focused) {
+            // Discord sends what's been typed so far as a string, even for number options.
+            let partial = match &focused.value {
+                Some(Value::String(partial)) => partial.clone(),
+                Some(other) => other.to_string(),
+                None => String::new(),
+            };
+
+            choices = resolved
+                .command
+                .autocomplete(db, &interaction.user.id, &focused.name, &partial)
+                .await
+                .with_context("Finding autocomplete choices")?;
+        }
+    }
+
+    interaction
+        .create_autocomplete_response(&context.http, |response| {
+            for choice in choices.iter().take(MAX_AUTOCOMPLETE_CHOICES) {
+                let AutocompleteChoice::Integer { name, value } = choice;
+                response.add_int_choice(truncate_choice_name(name), *value);
+            }
+            response
+        })
+        .await
+        .with_context("Creating autocomplete response")
+}
@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+
+use serenity::builder::CreateApplicationCommandOption;
+use serenity::model::application::command::CommandOptionType;
+use serenity::model::application::interaction::application_command::{
+    CommandDataOption, CommandDataOptionValue,
+};
+use serenity::model::id::UserId;
+
+use crate::error::{Error, InnerError};
+use crate::ugocoin::account::Ugocoin;
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+#[derive(Debug, Clone)]
+pub enum OptionKind {
+    User,
+    Integer {
+        min: Option<i64>,
+        max: Option<i64>,
+    },
+    String {
+        choices: &'static [&'static str],
+        min_length: Option<u16>,
+        max_length: Option<u16>,
+    },
+    // Entered as a decimal number of UGOcoin, and converted to ugocents.
+    Ugocoin {
+        min: Option<Ugocoin>,
+        max: Option<Ugocoin>,
+    },
+    // Entered as a YYYY-MM-DD string, since Discord has no date option type.
+    Date,
+}
+
+// Declares one option a command takes. The same declaration is used to register the option with
+// Discord and to validate what Discord sends back.
+#[derive(Debug, Clone)]
+pub struct OptionSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub kind: OptionKind,
+    pub required: bool,
+    // Whether Discord should ask the command for suggestions as the user types.
+    pub autocomplete: bool,
+}
+
+impl OptionSpec {
+    fn new(name: &'static str, description: &'static str, kind: OptionKind) -> OptionSpec {
+        OptionSpec {
+            name,
+            description,
+            kind,
+            required: false,
+            autocomplete: false,
+        }
+    }
+
+    pub fn user(name: &'static str, description: &'static str) -> OptionSpec {
+        OptionSpec::new(name, description, OptionKind::User)
+    }
+
+    pub fn integer(name: &'static str, description: &'static str) -> OptionSpec {
+        OptionSpec::new(
+            name,
+            description,
+            OptionKind::Integer {
+                min: None,
+                max: None,
+            },
+        )
+    }
+
+    pub fn string(name: &'static str, description: &'static str) -> OptionSpec {
+        OptionSpec::new(
+            name,
+            description,
+            OptionKind::String {
+                choices: &[],
+                min_length: None,
+                max_length: None,
+            },
+        )
+    }
+
+    pub fn ugocoin(name: &'static str, description: &'static str) -> OptionSpec {
+        OptionSpec::new(
+            name,
+            description,
+            OptionKind::Ugocoin {
+                min: None,
+                max: None,
+            },
+        )
+    }
+
+    pub fn date(name: &'static str, description: &'static str) -> OptionSpec {
+        OptionSpec::new(name, description, OptionKind::Date)
+    }
+
+    pub fn required(mut self) -> OptionSpec {
+        self.required = true;
+        self
+    }
+
+    pub fn autocomplete(mut self) -> OptionSpec {
+        self.autocomplete = true;
+        self
+    }
+
+    // Only meaningful for integer options.
+    pub fn range(mut self, min: i64, max: i64) -> OptionSpec {
+        if let OptionKind::Integer {
+            min: ref mut spec_min,
+            max: ref mut spec_max,
+        } = self.kind
+        {
+            *spec_min = Some(min);
+            *spec_max = Some(max);
+        }
+        self
+    }
+
+    // Only meaningful for UGOcoin options.
+    pub fn amount_range(mut self, min: Ugocoin, max: Ugocoin) -> OptionSpec {
+        if let OptionKind::Ugocoin {
+            min: ref mut spec_min,
+            max: ref mut spec_max,
+        } = self.kind
+        {
+            *spec_min = Some(min);
+            *spec_max = Some(max);
+        }
+        self
+    }
+
+    // Only meaningful for string options.
+    pub fn length(mut self, min: u16, max: u16) -> OptionSpec {
+        if let OptionKind::String {
+            min_length,
+            max_length,
+            ..
+        } = &mut self.kind
+        {
+            *min_length = Some(min);
+            *max_length = Some(max);
+        }
+        self
+    }
+
+    // Only meaningful for string options.
+    pub fn choices(mut self, values: &'static [&'static str]) -> OptionSpec {
+        if let OptionKind::String { choices, .. } = &mut self.kind {
+            *choices = values;
+        }
+        self
+    }
+
+    pub fn register<'a>(
+        &self,
+        option: &'a mut CreateApplicationCommandOption,
+    ) -> &'a mut CreateApplicationCommandOption {
+        option
+            .name(self.name)
+            .description(self.description)
+            .required(self.required)
+            .set_autocomplete(self.autocomplete);
+
+        match &self.kind {
+            OptionKind::User => {
+                option.kind(CommandOptionType::User);
+            }
+            OptionKind::Integer { min, max } => {
+                option.kind(CommandOptionType::Integer);
+                if let Some(min) = min {
+                    option.min_int_value(*min);
+                }
+                if let Some(max) = max {
+                    option.max_int_value(*max);
+                }
+            }
+            OptionKind::String {
+                choices,
+                min_length,
+                max_length,
+            } => {
+                option.kind(CommandOptionType::String);
+                for choice in choices.iter() {
+                    option.add_string_choice(choice, choice);
+                }
+                if let Some(min_length) = min_length {
+                    option.min_length(*min_length);
+                }
+                if let Some(max_length) = max_length {
+                    option.max_length(*max_length);
+                }
+            }
+            OptionKind::Ugocoin { min, max } => {
+                option.kind(CommandOptionType::Number);
+                if let Some(min) = min {
+                    option.min_number_value(min.as_ugocents() as f64 / 100.0);
+                }
+                if let Some(max) = max {
+                    option.max_number_value(max.as_ugocents() as f64 / 100.0);
+                }
+            }
+            OptionKind::Date => {
+                option
+                    .kind(CommandOptionType::String)
+                    .min_length(10)
+                    .max_length(10);
+            }
+        }
+
+        option
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum OptionValue {
+    User(UserId),
+    Integer(i64),
+    String(String),
+    Ugocoin(Ugocoin),
+    Date(NaiveDate),
+}
+
+// Validated option values for one command invocation, keyed by option name.
+#[derive(Debug, Default)]
+pub struct CommandArgs {
+    values: HashMap<&'static str, OptionValue>,
+}
+
+impl CommandArgs {
+    pub fn user(&self, name: &str) -> Option<UserId> {
+        match self.values.get(name) {
+            Some(OptionValue::User(user_id)) => Some(*user_id),
+            _ => None,
+        }
+    }
+
+    pub fn integer(&self, name: &str) -> Option<i64> {
+        match self.values.get(name) {
+            Some(OptionValue::Integer(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn string(&self, name: &str) -> Option<&str> {
+        match self.values.get(name) {
+            Some(OptionValue::String(value)) => Some(value.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn ugocoin(&self, name: &str) -> Option<Ugocoin> {
+        match self.values.get(name) {
+            Some(OptionValue::Ugocoin(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn date(&self, name: &str) -> Option<NaiveDate> {
+        match self.values.get(name) {
+            Some(OptionValue::Date(value)) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+fn invalid(message: String) -> Error {
+    InnerError::InvalidArgument(message).into()
+}
+
+// Discord enforces most of these limits client-side, but we can't trust that, so check again.
+fn parse_value(spec: &OptionSpec, value: &CommandDataOptionValue) -> Result<OptionValue, Error> {
+    match (&spec.kind, value) {
+        (OptionKind::User, CommandDataOptionValue::User(user, _)) => Ok(OptionValue::User(user.id)),
+        (OptionKind::Integer { min, max }, CommandDataOptionValue::Integer(value)) => {
+            if min.is_some_and(|min| *value < min) || max.is_some_and(|max| *value > max) {
+                return Err(invalid(format!(
+                    "`{}` must be between {} and {}.",
+                    spec.name,
+                    min.unwrap_or(i64::MIN),
+                    max.unwrap_or(i64::MAX)
+                )));
+            }
+            Ok(OptionValue::Integer(*value))
+        }
+        (
+            OptionKind::String {
+                choices,
+                min_length,
+                max_length,
+            },
+            CommandDataOptionValue::String(value),
+        ) => {
+            let value = value.trim();
+            if !choices.is_empty() && !choices.contains(&value) {
+                return Err(invalid(format!(
+                    "`{}` must be one of: {}.",
+                    spec.name,
+                    choices.join(", ")
+                )));
+            }
+
+            let length = value.chars().count();
+            if min_length.is_some_and(|min| length < min as usize)
+                || max_length.is_some_and(|max| length > max as usize)
+            {
+                return Err(invalid(format!(
+                    "`{}` must be between {} and {} characters long.",
+                    spec.name,
+                    min_length.unwrap_or(0),
+                    max_length.unwrap_or(u16::MAX)
+                )));
+            }
+            Ok(OptionValue::String(value.to_string()))
+        }
+        (OptionKind::Ugocoin { min, max }, CommandDataOptionValue::Number(coins)) => {
+            if !coins.is_finite() {
+                return Err(invalid(format!("`{}` must be a number.", spec.name)));
+            }
+
+            let amount = Ugocoin::from_ugocents((coins * 100.0).round() as i64);
+            if min.is_some_and(|min| amount < min) || max.is_some_and(|max| amount > max) {
+                return Err(invalid(format!(
+                    "`{}` must be between {} and {}.",
+                    spec.name,
+                    min.unwrap_or(Ugocoin::from_ugocents(0)),
+                    max.unwrap_or(Ugocoin::from_ugocents(i64::MAX))
+                )));
+            }
+            Ok(OptionValue::Ugocoin(amount))
+        }
+        (OptionKind::Date, CommandDataOptionValue::String(value)) => {
+            match NaiveDate::parse_from_str(value.trim(), DATE_FORMAT) {
+                Ok(date) => Ok(OptionValue::Date(date)),
+                Err(_) => Err(invalid(format!(
+                    "`{}` must be a date like 2022-12-25.",
+                    spec.name
+                ))),
+            }
+        }
+        _ => Err(invalid(format!(
+            "`{}` has the wrong type of value.",
+            spec.name
+        ))),
+    }
+}
+
+// Checks the options Discord sent against a command's declared specs.
+pub fn parse_options(
+    specs: &[OptionSpec],
+    options: &[CommandDataOption],
+) -> Result<CommandArgs, Error> {
+    let mut args = CommandArgs::default();
+
+    for spec in specs {
+        let resolved = options
+            .iter()
+            .find(|option| option.name == spec.name)
+            .and_then(|option| option.resolved.as_ref());
+
+        match resolved {
+            Some(value) => {
+                args.values.insert(spec.name, parse_value(spec, value)?);
+            }
+            None if spec.required => {
+                return Err(invalid(format!("`{}` is required.", spec.name)));
+            }
+            None => {}
+        }
+    }
+
+    Ok(args)
+}
@@ -0,0 +1,139 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chrono::{DateTime, Local};
+
+use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
+
+use crate::chat::{Attachment, Chat, InteractionHandle, InteractionReply, Reply};
+use crate::error::{Error, WithContext};
+
+// Discord fails an interaction that isn't answered within 3 seconds. Commands that might take
+// longer defer: Discord shows "thinking..." until the real response replaces it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseMode {
+    Immediate,
+    // Deferred responses can't change visibility later, so it's chosen up front.
+    Deferred { ephemeral: bool },
+}
+
+const DEFERRED_FAILURE_TEXT: &str = "This command didn't finish.";
+
+// Answers one command interaction. The first reply becomes the interaction response, and any
+// after that are sent as followups, so commands don't need to track which they're on.
+pub struct Responder<'a> {
+    pub command: &'a ApplicationCommandInteraction,
+    // When the command came in. Commands use this instead of reading the clock themselves.
+    pub now: DateTime<Local>,
+    chat: &'a dyn Chat,
+    interaction: InteractionHandle,
+    mode: ResponseMode,
+    deferred: AtomicBool,
+    responded: AtomicBool,
+}
+
+impl<'a> Responder<'a> {
+    pub fn new(
+        chat: &'a dyn Chat,
+        command: &'a ApplicationCommandInteraction,
+        now: DateTime<Local>,
+        mode: ResponseMode,
+    ) -> Responder<'a> {
+        Responder {
+            command,
+            now,
+            chat,
+            interaction: InteractionHandle {
+                id: command.id,
+                token: command.token.clone(),
+            },
+            mode,
+            deferred: AtomicBool::new(false),
+            responded: AtomicBool::new(false),
+        }
+    }
+
+    // Acknowledges the interaction if the command asked to defer. Does nothing otherwise.
+    pub async fn defer(&self) -> Result<(), Error> {
+        if let ResponseMode::Deferred { ephemeral } = self.mode {
+            self.chat
+                .respond_to_interaction(&self.interaction, InteractionReply::Defer { ephemeral })
+                .await
+                .with_context("Deferring command response")?;
+            self.deferred.store(true, Ordering::SeqCst);
+        }
+
+        Ok(())
+    }
+
+    // After a command fails, replaces a "thinking..." response nobody answered yet with a neutral
+    // note. Otherwise the error's followup would take its place, and with it its visibility, so a
+    // public deferral would show the error to the whole channel.
+    pub async fn clear_deferred(&self) -> Result<(), Error> {
+        if self.deferred.load(Ordering::SeqCst) && !self.responded.load(Ordering::SeqCst) {
+            self.edit(DEFERRED_FAILURE_TEXT).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn respond(&self, content: impl Into<String>) -> Result<(), Error> {
+        self.send(Reply {
+            content: Some(content.into()),
+            ..Default::default()
+        })
+        .await
+    }
+
+    // Only the user who ran the command sees this. In deferred mode, the first reply takes the
+    // visibility chosen when deferring instead.
+    pub async fn respond_ephemeral(&self, content: impl Into<String>) -> Result<(), Error> {
+        self.send(Reply {
+            content: Some(content.into()),
+            ephemeral: true,
+            ..Default::default()
+        })
+        .await
+    }
+
+    pub async fn respond_with_file(&self, filename: &str, data: &[u8]) -> Result<(), Error> {
+        self.send(Reply {
+            file: Some(Attachment { filename, data }),
+            ..Default::default()
+        })
+        .await
+    }
+
+    // Replaces the content of the original response, e.g. to update progress.
+    pub async fn edit(&self, content: impl Into<String>) -> Result<(), Error> {
+        self.chat
+            .edit_interaction_response(&self.interaction, &content.into())
+            .await
+            .with_context("Editing command response")?;
+
+        self.responded.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn send(&self, reply: Reply<'_>) -> Result<(), Error> {
+        let first = !self.responded.swap(true, Ordering::SeqCst);
+        if first && self.mode != ResponseMode::Immediate && reply.file.is_none() {
+            // Editing can't add files, so deferred files go through a followup below.
+            if let Some(content) = reply.content {
+                return self.edit(content).await;
+            }
+        }
+
+        if first && self.mode == ResponseMode::Immediate {
+            self.chat
+                .respond_to_interaction(&self.interaction, InteractionReply::Message(reply))
+                .await
+                .with_context("Creating command response")
+        } else {
+            // After deferring, a followup with a file also replaces the "thinking..." message.
+            self.chat
+                .send_followup(&self.interaction, reply)
+                .await
+                .with_context("Creating command followup")
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::HashSet;
+
+use log::info;
+
+use sqlx::migrate::Migrator;
+use sqlx::SqlitePool;
+
+use crate::error::{Error, InnerError, WithContext};
+
+// The migrations directory, compiled into the binary.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationMode {
+    // Refuse to start if any migrations haven't been applied.
+    Check,
+    // Apply any pending migrations.
+    Auto,
+}
+
+async fn applied_versions(db: &SqlitePool) -> Result<HashSet<i64>, Error> {
+    // A brand new database doesn't have the migrations table yet.
+    let has_table: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations'",
+    )
+    .fetch_one(db)
+    .await?;
+
+    if has_table == 0 {
+        return Ok(HashSet::new());
+    }
+
+    let versions: Vec<i64> =
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success = true")
+            .fetch_all(db)
+            .await?;
+
+    Ok(versions.into_iter().collect())
+}
+
+// Makes sure the database schema matches what this binary expects before anything queries it.
+pub async fn prepare(db: &SqlitePool, mode: MigrationMode) -> Result<(), Error> {
+    let applied = applied_versions(db)
+        .await
+        .with_context("Reading applied migrations")?;
+    let known: HashSet<i64> = MIGRATOR.iter().map(|migration| migration.version).collect();
+
+    // Migrations we don't know about came from a newer build. Running against that schema could
+    // corrupt data it depends on, so don't.
+    if let Some(newest) = applied.difference(&known).max() {
+        return Err(InnerError::SchemaTooNew(*newest).into());
+    }
+
+    let pending = known.difference(&applied).count();
+    if pending == 0 {
+        return Ok(());
+    }
+
+    match mode {
+        MigrationMode::Check => Err(InnerError::PendingMigrations(pending).into()),
+        MigrationMode::Auto => {
+            info!("Applying {} database migrations.", pending);
+            MIGRATOR.run(db).await.with_context("Applying migrations")?;
+            Ok(())
+        }
+    }
+}
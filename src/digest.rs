@@ -0,0 +1,470 @@
+use std::collections::HashMap;
+use std::env;
+
+use chrono::{DateTime, Datelike, Days, Local, NaiveDate, Timelike, Weekday};
+
+use log::info;
+use serenity::model::id::ChannelId;
+
+use sqlx::{Sqlite, SqlitePool, Transaction};
+
+use crate::error::Error;
+use crate::outbox::{self, OutboxMessage};
+use crate::ugocoin::account::Ugocoin;
+
+#[derive(Debug, Clone, Copy)]
+pub enum DigestPeriod {
+    Daily,
+    Weekly,
+}
+
+impl DigestPeriod {
+    // Set DIGEST_PERIOD=weekly to only post on Mondays. Snapshots are still taken daily either way.
+    pub fn from_env() -> DigestPeriod {
+        match env::var("DIGEST_PERIOD") {
+            Ok(period) if period.eq_ignore_ascii_case("weekly") => DigestPeriod::Weekly,
+            _ => DigestPeriod::Daily,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            DigestPeriod::Daily => "daily",
+            DigestPeriod::Weekly => "weekly",
+        }
+    }
+}
+
+pub struct BalanceSnapshot {
+    pub account_id: i64,
+    pub user_id: Option<i64>,
+    pub name: String,
+    pub balance: Ugocoin,
+    pub streak: i64,
+}
+
+pub fn date_to_snapshot_db_format(date: NaiveDate) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
+
+// Wait until after scrums force close at 4 PM, so the day's rewards are in.
+pub const DIGEST_HOUR: u32 = 12 + 5;
+
+fn is_past_digest_time(datetime: DateTime<Local>) -> bool {
+    datetime.hour() >= DIGEST_HOUR
+}
+
+pub async fn get_latest_snapshot_date(db: &SqlitePool) -> Result<Option<NaiveDate>, Error> {
+    let result = sqlx::query!(
+        "SELECT snapshot_date FROM ugocoin_balance_snapshots ORDER BY snapshot_date DESC LIMIT 1"
+    )
+    .fetch_optional(db)
+    .await?;
+
+    match result {
+        Some(row) => Ok(Some(NaiveDate::parse_from_str(
+            &row.snapshot_date,
+            "%Y-%m-%d",
+        )?)),
+        None => Ok(None),
+    }
+}
+
+pub fn should_run_digest(datetime: DateTime<Local>, latest_snapshot: Option<NaiveDate>) -> bool {
+    is_past_digest_time(datetime) && latest_snapshot != Some(datetime.date_naive())
+}
+
+async fn get_current_balances(db: &SqlitePool) -> Result<Vec<BalanceSnapshot>, Error> {
+    let rows = sqlx::query!(
+        r#"SELECT ugocoin_accounts.id, ugocoin_accounts.user_id, ugocoin_accounts.balance,
+            users.display_name as "display_name?", users.streak as "streak?"
+        FROM ugocoin_accounts
+        LEFT JOIN users ON users.id = ugocoin_accounts.user_id"#
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| BalanceSnapshot {
+            account_id: row.id,
+            user_id: row.user_id,
+            name: row
+                .display_name
+                .unwrap_or_else(|| String::from("UGOcoin Central Bank")),
+            balance: Ugocoin::from_ugocents(row.balance),
+            streak: row.streak.unwrap_or(0),
+        })
+        .collect())
+}
+
+pub async fn get_snapshot(db: &SqlitePool, date: NaiveDate) -> Result<Vec<BalanceSnapshot>, Error> {
+    let date_str = date_to_snapshot_db_format(date);
+
+    let rows = sqlx::query!(
+        r#"SELECT ugocoin_balance_snapshots.account_id, ugocoin_balance_snapshots.balance,
+            ugocoin_balance_snapshots.streak, ugocoin_accounts.user_id,
+            users.display_name as "display_name?"
+        FROM ugocoin_balance_snapshots
+        INNER JOIN ugocoin_accounts ON ugocoin_accounts.id = ugocoin_balance_snapshots.account_id
+        LEFT JOIN users ON users.id = ugocoin_accounts.user_id
+        WHERE ugocoin_balance_snapshots.snapshot_date = ?"#,
+        date_str
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| BalanceSnapshot {
+            account_id: row.account_id,
+            user_id: row.user_id,
+            name: row
+                .display_name
+                .unwrap_or_else(|| String::from("UGOcoin Central Bank")),
+            balance: Ugocoin::from_ugocents(row.balance),
+            streak: row.streak,
+        })
+        .collect())
+}
+
+// Finds the most recent snapshot on or before the given date.
+async fn get_snapshot_date_on_or_before(
+    db: &SqlitePool,
+    date: NaiveDate,
+) -> Result<Option<NaiveDate>, Error> {
+    let date_str = date_to_snapshot_db_format(date);
+
+    let result = sqlx::query!(
+        "SELECT snapshot_date FROM ugocoin_balance_snapshots
+        WHERE snapshot_date <= ? ORDER BY snapshot_date DESC LIMIT 1",
+        date_str
+    )
+    .fetch_optional(db)
+    .await?;
+
+    match result {
+        Some(row) => Ok(Some(NaiveDate::parse_from_str(
+            &row.snapshot_date,
+            "%Y-%m-%d",
+        )?)),
+        None => Ok(None),
+    }
+}
+
+async fn save_snapshot(
+    db_tx: &mut Transaction<'_, Sqlite>,
+    datetime: DateTime<Local>,
+    balances: &[BalanceSnapshot],
+) -> Result<(), Error> {
+    let date_str = date_to_snapshot_db_format(datetime.date_naive());
+    let now_unix = datetime.timestamp();
+
+    for snapshot in balances {
+        let balance = snapshot.balance.as_ugocents();
+        sqlx::query!(
+            "INSERT INTO ugocoin_balance_snapshots (snapshot_date, snapshot_time, account_id, balance, streak)
+            VALUES (?, ?, ?, ?, ?)",
+            date_str,
+            now_unix,
+            snapshot.account_id,
+            balance,
+            snapshot.streak
+        )
+        .execute(&mut *db_tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct AccountFlow {
+    earned: i64,
+    spent: i64,
+}
+
+// Sums up money flowing in and out of each account since a point in time.
+async fn get_account_flows(
+    db: &SqlitePool,
+    since_unix: i64,
+) -> Result<HashMap<i64, AccountFlow>, Error> {
+    let mut flows: HashMap<i64, AccountFlow> = HashMap::new();
+
+    let earned = sqlx::query!(
+        r#"SELECT to_account_id, SUM(amount) as "total!: i64" FROM ugocoin_tx_logs
+        WHERE tx_time >= ? GROUP BY to_account_id"#,
+        since_unix
+    )
+    .fetch_all(db)
+    .await?;
+
+    for row in earned {
+        flows.entry(row.to_account_id).or_default().earned += row.total;
+    }
+
+    let spent = sqlx::query!(
+        r#"SELECT from_account_id, SUM(amount) as "total!: i64" FROM ugocoin_tx_logs
+        WHERE tx_time >= ? GROUP BY from_account_id"#,
+        since_unix
+    )
+    .fetch_all(db)
+    .await?;
+
+    for row in spent {
+        flows.entry(row.from_account_id).or_default().spent += row.total;
+    }
+
+    Ok(flows)
+}
+
+// Ranks user accounts by balance, highest first. Ranks start at 1.
+fn rank_users(balances: &[BalanceSnapshot]) -> HashMap<i64, usize> {
+    let mut users: Vec<&BalanceSnapshot> =
+        balances.iter().filter(|b| b.user_id.is_some()).collect();
+    users.sort_by_key(|b| std::cmp::Reverse(b.balance));
+
+    users
+        .iter()
+        .enumerate()
+        .map(|(idx, b)| (b.account_id, idx + 1))
+        .collect()
+}
+
+fn format_delta(delta_ugocents: i64) -> String {
+    if delta_ugocents >= 0 {
+        format!("+{}", Ugocoin::from_ugocents(delta_ugocents))
+    } else {
+        format!("-{}", Ugocoin::from_ugocents(-delta_ugocents))
+    }
+}
+
+fn format_rank_change(previous: Option<usize>, current: usize) -> String {
+    match previous {
+        Some(previous) if previous > current => format!("▲{}", previous - current),
+        Some(previous) if previous < current => format!("▼{}", current - previous),
+        Some(_) => String::from("-"),
+        None => String::from("NEW"),
+    }
+}
+
+// Reward tiers go up every 7 days of streak, so those are the milestones worth announcing.
+const STREAK_MILESTONE_DAYS: i64 = 7;
+
+fn format_digest(
+    period: DigestPeriod,
+    date: NaiveDate,
+    current: &[BalanceSnapshot],
+    previous: &[BalanceSnapshot],
+    flows: &HashMap<i64, AccountFlow>,
+) -> String {
+    let current_ranks = rank_users(current);
+    let previous_ranks = rank_users(previous);
+    let previous_by_account: HashMap<i64, &BalanceSnapshot> =
+        previous.iter().map(|b| (b.account_id, b)).collect();
+
+    let mut users: Vec<&BalanceSnapshot> = current.iter().filter(|b| b.user_id.is_some()).collect();
+    users.sort_by_key(|b| current_ranks[&b.account_id]);
+
+    let max_name_width = users.iter().map(|b| b.name.len()).max().unwrap_or(0);
+    let max_coin_width = users
+        .iter()
+        .map(|b| format!("{}", b.balance).len())
+        .max()
+        .unwrap_or(0);
+
+    let mut table = String::new();
+    for b in &users {
+        let previous_balance = previous_by_account
+            .get(&b.account_id)
+            .map(|p| p.balance.as_ugocents())
+            .unwrap_or(0);
+        let delta = format_delta(b.balance.as_ugocents() - previous_balance);
+        let rank = current_ranks[&b.account_id];
+        let rank_change = format_rank_change(previous_ranks.get(&b.account_id).copied(), rank);
+
+        table += &format!(
+            "{:>2}. {:<max_name_width$} | {:<max_coin_width$} | {:>10} | {}\n",
+            rank, b.name, b.balance, delta, rank_change
+        );
+    }
+
+    let mut message = format!(
+        "UGOcoin {} digest for {}:\n\n```{}```",
+        period.name(),
+        date.format("%Y-%m-%d"),
+        table
+    );
+
+    let mut earners: Vec<(&str, i64)> = users
+        .iter()
+        .filter_map(|b| {
+            flows
+                .get(&b.account_id)
+                .map(|f| (b.name.as_str(), f.earned))
+        })
+        .filter(|(_, earned)| *earned > 0)
+        .collect();
+    earners.sort_by_key(|(_, earned)| std::cmp::Reverse(*earned));
+
+    if !earners.is_empty() {
+        message += "\nBiggest earners:\n";
+        for (name, earned) in earners.iter().take(3) {
+            message += &format!("{} ({})\n", name, format_delta(*earned));
+        }
+    }
+
+    let mut spenders: Vec<(&str, i64)> = users
+        .iter()
+        .filter_map(|b| flows.get(&b.account_id).map(|f| (b.name.as_str(), f.spent)))
+        .filter(|(_, spent)| *spent > 0)
+        .collect();
+    spenders.sort_by_key(|(_, spent)| std::cmp::Reverse(*spent));
+
+    if !spenders.is_empty() {
+        message += "\nBiggest spenders:\n";
+        for (name, spent) in spenders.iter().take(3) {
+            message += &format!("{} ({})\n", name, format_delta(-*spent));
+        }
+    }
+
+    let milestones: Vec<String> = users
+        .iter()
+        .filter_map(|b| {
+            let previous_streak = previous_by_account.get(&b.account_id)?.streak;
+            if b.streak / STREAK_MILESTONE_DAYS > previous_streak / STREAK_MILESTONE_DAYS {
+                Some(format!(
+                    "{} reached a {} day scrum streak!\n",
+                    b.name, b.streak
+                ))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    if !milestones.is_empty() {
+        message += "\nStreak milestones:\n";
+        for milestone in milestones {
+            message += &milestone;
+        }
+    }
+
+    message
+}
+
+// The digest compares against the snapshot from one period ago.
+fn comparison_date(period: DigestPeriod, today: NaiveDate) -> Option<NaiveDate> {
+    match period {
+        DigestPeriod::Daily => today.checked_sub_days(Days::new(1)),
+        DigestPeriod::Weekly => today.checked_sub_days(Days::new(7)),
+    }
+}
+
+fn should_post_digest(period: DigestPeriod, today: NaiveDate) -> bool {
+    match period {
+        DigestPeriod::Daily => true,
+        DigestPeriod::Weekly => today.weekday() == Weekday::Mon,
+    }
+}
+
+pub async fn run_digest(
+    db: &SqlitePool,
+    datetime: DateTime<Local>,
+    period: DigestPeriod,
+    channel_id: ChannelId,
+) -> Result<(), Error> {
+    let today = datetime.date_naive();
+
+    let previous_date = match comparison_date(period, today) {
+        Some(date) => get_snapshot_date_on_or_before(db, date).await?,
+        None => None,
+    };
+
+    let previous = match previous_date {
+        Some(date) => get_snapshot(db, date).await?,
+        None => Vec::new(),
+    };
+
+    let current = get_current_balances(db).await?;
+
+    let message = if should_post_digest(period, today) {
+        // Without a previous snapshot, there's no window to total up flows over.
+        let flows = match previous_date {
+            Some(date) => {
+                let since = get_snapshot_time(db, date).await?;
+                get_account_flows(db, since).await?
+            }
+            None => HashMap::new(),
+        };
+        Some(OutboxMessage::Send {
+            channel_id,
+            content: format_digest(period, today, &current, &previous, &flows),
+        })
+    } else {
+        None
+    };
+
+    // Queued with the snapshot, so a digest whose post fails is retried by the outbox instead of
+    // skipped for the day. The key keeps each day to one digest.
+    let mut db_tx = db.begin().await?;
+    save_snapshot(&mut db_tx, datetime, &current).await?;
+    if let Some(message) = message {
+        let dedupe_key = format!("digest {}", date_to_snapshot_db_format(today));
+        outbox::enqueue_with(&mut db_tx, datetime, &message, Some(&dedupe_key), None).await?;
+    }
+    db_tx.commit().await?;
+    info!("Saved balance snapshot for {}", today);
+
+    Ok(())
+}
+
+async fn get_snapshot_time(db: &SqlitePool, date: NaiveDate) -> Result<i64, Error> {
+    let date_str = date_to_snapshot_db_format(date);
+
+    let result = sqlx::query!(
+        r#"SELECT MIN(snapshot_time) as "snapshot_time!: i64" FROM ugocoin_balance_snapshots
+        WHERE snapshot_date = ?"#,
+        date_str
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(result.snapshot_time)
+}
+
+pub struct StreakHistory {
+    pub snapshot_date: NaiveDate,
+    pub user_id: i64,
+    pub streak: i64,
+}
+
+pub async fn get_streak_history_since(
+    db: &SqlitePool,
+    since: NaiveDate,
+) -> Result<Vec<StreakHistory>, Error> {
+    let since_str = date_to_snapshot_db_format(since);
+
+    let rows = sqlx::query!(
+        r#"SELECT ugocoin_balance_snapshots.snapshot_date, ugocoin_balance_snapshots.streak,
+            ugocoin_accounts.user_id as "user_id!"
+        FROM ugocoin_balance_snapshots
+        INNER JOIN ugocoin_accounts ON ugocoin_accounts.id = ugocoin_balance_snapshots.account_id
+        WHERE ugocoin_accounts.user_id IS NOT NULL AND ugocoin_balance_snapshots.snapshot_date >= ?
+        ORDER BY ugocoin_balance_snapshots.snapshot_date"#,
+        since_str
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut history = Vec::new();
+    for row in rows {
+        history.push(StreakHistory {
+            snapshot_date: NaiveDate::parse_from_str(&row.snapshot_date, "%Y-%m-%d")?,
+            user_id: row.user_id,
+            streak: row.streak,
+        });
+    }
+
+    Ok(history)
+}
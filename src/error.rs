@@ -1,63 +1,195 @@
+use std::borrow::Cow;
 use std::fmt::Display;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use chrono::Local;
+
+pub const HTTP_TOO_MANY_REQUESTS: u16 = 429;
+
+// SQLite result codes for another connection holding a lock we need.
+const SQLITE_BUSY: &str = "5";
+const SQLITE_LOCKED: &str = "6";
 
 #[derive(Debug)]
 pub enum InnerError {
-    DatabaseError(sqlx::Error),
-    DiscordError(serenity::Error),
+    // Boxed because they're much larger than the rest, and every Result we return carries one.
+    DatabaseError(Box<sqlx::Error>),
+    MigrationError(Box<sqlx::migrate::MigrateError>),
+    DiscordError(Box<serenity::Error>),
     DateTimeParseError(chrono::ParseError),
     IdParseError(std::num::ParseIntError),
+    JsonError(serde_json::Error),
     CommandNotFound(String),
+    InvalidArgument(String),
+    PermissionDenied,
+    ChartError(String),
     InsufficientFunds,
     NegativeTransfer,
+    TipLimitReached,
     UserNotFound,
+    AlreadyRegistered,
+    LinkRequestNotFound,
+    LinkRequestPending,
+    TransactionNotFound,
+    UnknownOutboxKind(String),
+    OutboxItemNotFound,
+    ScrumHasNoSlots(String),
+    SchemaTooNew(i64),
+    PendingMigrations(usize),
+    FileError(String),
+    ImportRejected(String),
+    BackupFailed(String),
+    HttpServerError(String),
+    MetricsError(String),
+    LoggingError(String),
 }
 
-#[derive(Debug)]
-pub struct Error {
-    pub error: InnerError,
-    pub ctx: &'static str,
-}
-
-impl From<sqlx::Error> for Error {
-    fn from(err: sqlx::Error) -> Self {
-        Self {
-            error: InnerError::DatabaseError(err),
-            ctx: "",
+impl InnerError {
+    // The variant name, for grouping errors without caring about their details.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            InnerError::DatabaseError(_) => "DatabaseError",
+            InnerError::MigrationError(_) => "MigrationError",
+            InnerError::DiscordError(_) => "DiscordError",
+            InnerError::DateTimeParseError(_) => "DateTimeParseError",
+            InnerError::IdParseError(_) => "IdParseError",
+            InnerError::JsonError(_) => "JsonError",
+            InnerError::CommandNotFound(_) => "CommandNotFound",
+            InnerError::InvalidArgument(_) => "InvalidArgument",
+            InnerError::ChartError(_) => "ChartError",
+            InnerError::PermissionDenied => "PermissionDenied",
+            InnerError::InsufficientFunds => "InsufficientFunds",
+            InnerError::NegativeTransfer => "NegativeTransfer",
+            InnerError::TipLimitReached => "TipLimitReached",
+            InnerError::UserNotFound => "UserNotFound",
+            InnerError::AlreadyRegistered => "AlreadyRegistered",
+            InnerError::LinkRequestNotFound => "LinkRequestNotFound",
+            InnerError::LinkRequestPending => "LinkRequestPending",
+            InnerError::TransactionNotFound => "TransactionNotFound",
+            InnerError::UnknownOutboxKind(_) => "UnknownOutboxKind",
+            InnerError::OutboxItemNotFound => "OutboxItemNotFound",
+            InnerError::ScrumHasNoSlots(_) => "ScrumHasNoSlots",
+            InnerError::SchemaTooNew(_) => "SchemaTooNew",
+            InnerError::PendingMigrations(_) => "PendingMigrations",
+            InnerError::FileError(_) => "FileError",
+            InnerError::ImportRejected(_) => "ImportRejected",
+            InnerError::BackupFailed(_) => "BackupFailed",
+            InnerError::HttpServerError(_) => "HttpServerError",
+            InnerError::MetricsError(_) => "MetricsError",
+            InnerError::LoggingError(_) => "LoggingError",
         }
     }
-}
 
-impl From<serenity::Error> for Error {
-    fn from(err: serenity::Error) -> Self {
-        Self {
-            error: InnerError::DiscordError(err),
-            ctx: "",
+    // Errors caused by what the user asked for get a friendly explanation. Anything else is on us,
+    // and returns None.
+    pub fn user_message(&self) -> Option<String> {
+        match self {
+            InnerError::InsufficientFunds => Some("You don't have enough UGOcoin for that.".into()),
+            InnerError::NegativeTransfer => {
+                Some("You can't transfer a negative amount of UGOcoin.".into())
+            }
+            InnerError::UserNotFound => {
+                Some("That user isn't registered with UGO. Use /register to sign up.".into())
+            }
+            InnerError::TipLimitReached => Some("You've hit your tip limit for today.".into()),
+            InnerError::PermissionDenied => Some("You don't have permission to do that.".into()),
+            InnerError::AlreadyRegistered => {
+                Some("That Discord account is already registered.".into())
+            }
+            InnerError::LinkRequestNotFound => {
+                Some("There's no pending link request with that number.".into())
+            }
+            InnerError::LinkRequestPending => {
+                Some("That Discord account already has a link request waiting for approval.".into())
+            }
+            InnerError::TransactionNotFound => {
+                Some("There's no UGOcoin transaction with that number.".into())
+            }
+            InnerError::OutboxItemNotFound => {
+                Some("There's no failed outbox item with that number.".into())
+            }
+            InnerError::InvalidArgument(message) => Some(message.clone()),
+            InnerError::DatabaseError(_)
+            | InnerError::MigrationError(_)
+            | InnerError::SchemaTooNew(_)
+            | InnerError::PendingMigrations(_)
+            | InnerError::FileError(_)
+            | InnerError::ImportRejected(_)
+            | InnerError::BackupFailed(_)
+            | InnerError::HttpServerError(_)
+            | InnerError::MetricsError(_)
+            | InnerError::LoggingError(_)
+            | InnerError::DiscordError(_)
+            | InnerError::DateTimeParseError(_)
+            | InnerError::IdParseError(_)
+            | InnerError::JsonError(_)
+            | InnerError::CommandNotFound(_)
+            | InnerError::UnknownOutboxKind(_)
+            | InnerError::ScrumHasNoSlots(_)
+            | InnerError::ChartError(_) => None,
         }
     }
-}
 
-impl From<chrono::ParseError> for Error {
-    fn from(err: chrono::ParseError) -> Self {
-        Self {
-            error: InnerError::DateTimeParseError(err),
-            ctx: "",
+    // Whether trying the same thing again later might work: lost connections, a busy database,
+    // Discord rate limits and server errors. Everything else will fail the same way again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            InnerError::DatabaseError(db_err) => match db_err.as_ref() {
+                sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut => true,
+                sqlx::Error::Database(db_err) => matches!(
+                    db_err.code().as_deref(),
+                    Some(SQLITE_BUSY) | Some(SQLITE_LOCKED)
+                ),
+                _ => false,
+            },
+            InnerError::DiscordError(discord_err) => match discord_err.as_ref() {
+                serenity::Error::Http(http_err) => match http_err.status_code() {
+                    Some(status) => {
+                        status.as_u16() == HTTP_TOO_MANY_REQUESTS || status.is_server_error()
+                    }
+                    // The request never got an answer.
+                    None => true,
+                },
+                serenity::Error::Gateway(_)
+                | serenity::Error::Tungstenite(_)
+                | serenity::Error::Io(_) => true,
+                _ => false,
+            },
+            _ => false,
         }
     }
 }
 
-impl From<InnerError> for Error {
-    fn from(inner: InnerError) -> Self {
-        Self {
-            error: inner,
-            ctx: "",
-        }
-    }
+static INCIDENT_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+// A short ID to hand to users when something breaks, so the matching log line can be found.
+pub fn new_incident_id() -> String {
+    let millis = Local::now().timestamp_millis() as u32;
+    let count = INCIDENT_COUNTER.fetch_add(1, Ordering::Relaxed) % 256;
+    format!("{:08X}{:02X}", millis, count)
 }
 
-impl Display for Error {
+impl Display for InnerError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let inner_error_str = match &self.error {
+        let message = match self {
             InnerError::DatabaseError(db_err) => format!("Database error: {}", db_err),
+            InnerError::MigrationError(migrate_err) => {
+                format!("Migration error: {}", migrate_err)
+            }
+            InnerError::SchemaTooNew(version) => format!(
+                "Database has migration {}, which this build doesn't know about. Run a newer build.",
+                version
+            ),
+            InnerError::PendingMigrations(count) => format!(
+                "Database is missing {} migrations. Run with --migrate or AUTO_MIGRATE=1 to apply them.",
+                count
+            ),
+            InnerError::FileError(message) => format!("File error: {}", message),
+            InnerError::ImportRejected(message) => format!("Import rejected: {}", message),
+            InnerError::BackupFailed(message) => format!("Backup failed: {}", message),
+            InnerError::HttpServerError(message) => format!("HTTP server error: {}", message),
+            InnerError::MetricsError(message) => format!("Error encoding metrics: {}", message),
+            InnerError::LoggingError(message) => format!("Error setting up logging: {}", message),
             InnerError::DiscordError(discord_err) => {
                 format!("Discord error: {}", discord_err)
             }
@@ -67,30 +199,151 @@ impl Display for Error {
             InnerError::IdParseError(int_parse_err) => {
                 format!("Error parsing ID from string: {}", int_parse_err)
             }
+            InnerError::JsonError(json_err) => format!("JSON error: {}", json_err),
             InnerError::UserNotFound => "User not found.".to_string(),
+            InnerError::AlreadyRegistered => "Discord user is already registered.".to_string(),
+            InnerError::LinkRequestNotFound => "Pending link request not found.".to_string(),
+            InnerError::LinkRequestPending => "Link request already pending.".to_string(),
+            InnerError::TransactionNotFound => "Transaction not found.".to_string(),
+            InnerError::UnknownOutboxKind(kind) => format!("Unknown outbox message kind {}.", kind),
+            InnerError::OutboxItemNotFound => "Failed outbox item not found.".to_string(),
+            InnerError::ScrumHasNoSlots(scrum_date) => {
+                format!("Slot poll for scrum {} has no slots.", scrum_date)
+            }
+            InnerError::PermissionDenied => "Permission denied.".to_string(),
             InnerError::CommandNotFound(command) => format!("Command {} not found.", command),
+            InnerError::InvalidArgument(message) => format!("Invalid argument: {}", message),
+            InnerError::ChartError(chart_err) => format!("Error rendering chart: {}", chart_err),
             InnerError::InsufficientFunds => "Insufficent funds for transfer!".to_string(),
             InnerError::NegativeTransfer => "Attempted to transfer a negative amount!".to_string(),
+            InnerError::TipLimitReached => "Daily tip limit reached!".to_string(),
         };
 
-        f.write_fmt(format_args!("{} failed! ({})", self.ctx, inner_error_str))
+        f.write_str(&message)
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for InnerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            InnerError::DatabaseError(err) => Some(err.as_ref()),
+            InnerError::MigrationError(err) => Some(err.as_ref()),
+            InnerError::DiscordError(err) => Some(err.as_ref()),
+            InnerError::DateTimeParseError(err) => Some(err),
+            InnerError::IdParseError(err) => Some(err),
+            InnerError::JsonError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub error: InnerError,
+    // What we were doing when it failed, innermost first. Each `with_context` adds a frame.
+    pub context: Vec<Cow<'static, str>>,
+}
+
+impl Error {
+    // The context frames outermost first, like "Running job scrum: Closing scrum 2024-05-01".
+    pub fn context(&self) -> String {
+        let frames: Vec<&str> = self
+            .context
+            .iter()
+            .rev()
+            .map(|frame| frame.as_ref())
+            .collect();
+        frames.join(": ")
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.error.is_retryable()
+    }
+}
+
+impl From<InnerError> for Error {
+    fn from(inner: InnerError) -> Self {
+        Self {
+            error: inner,
+            context: Vec::new(),
+        }
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(err: sqlx::Error) -> Self {
+        InnerError::DatabaseError(Box::new(err)).into()
+    }
+}
+
+impl From<sqlx::migrate::MigrateError> for Error {
+    fn from(err: sqlx::migrate::MigrateError) -> Self {
+        InnerError::MigrationError(Box::new(err)).into()
+    }
+}
+
+// Export files and backups are the only file IO we do, so these all land in one variant.
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        InnerError::FileError(err.to_string()).into()
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        InnerError::JsonError(err).into()
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(err: csv::Error) -> Self {
+        InnerError::FileError(err.to_string()).into()
+    }
+}
+
+impl From<serenity::Error> for Error {
+    fn from(err: serenity::Error) -> Self {
+        InnerError::DiscordError(Box::new(err)).into()
+    }
+}
+
+impl From<chrono::ParseError> for Error {
+    fn from(err: chrono::ParseError) -> Self {
+        InnerError::DateTimeParseError(err).into()
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.context.is_empty() {
+            write!(f, "{}", self.error)
+        } else {
+            write!(f, "{} failed! ({})", self.context(), self.error)
+        }
+    }
+}
+
+// The message already includes the inner error, so the chain starts at what it wraps.
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        std::error::Error::source(&self.error)
+    }
+}
 
 pub trait WithContext<T> {
-    fn with_context(self, ctx: &'static str) -> Result<T, Error>;
+    // Takes a plain string, or a formatted one to say which user or scrum it was about.
+    fn with_context(self, ctx: impl Into<Cow<'static, str>>) -> Result<T, Error>;
 }
 
 impl<T, E> WithContext<T> for Result<T, E>
 where
     Error: From<E>,
 {
-    fn with_context(self, ctx: &'static str) -> Result<T, Error> {
-        self.map_err(|err| Error {
-            error: Error::from(err).error,
-            ctx: ctx,
+    fn with_context(self, ctx: impl Into<Cow<'static, str>>) -> Result<T, Error> {
+        self.map_err(|err| {
+            let mut err = Error::from(err);
+            err.context.push(ctx.into());
+            err
         })
     }
 }
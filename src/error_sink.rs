@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Local};
+
+use log::error;
+use serenity::model::id::ChannelId;
+
+use crate::chat::Chat;
+use crate::error::Error;
+
+// Identical errors are only re-posted this often while they keep happening.
+const REPEAT_INTERVAL_MINUTES: i64 = 60;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ErrorKey {
+    source: &'static str,
+    kind: &'static str,
+    ctx: String,
+}
+
+struct ErrorState {
+    first_seen: DateTime<Local>,
+    last_posted: DateTime<Local>,
+    count: u64,
+}
+
+// Posts errors from background work to the admin channel. Errors are grouped by where they came
+// from, their variant and their context, so a job failing every minute doesn't flood the channel.
+pub struct ErrorSink {
+    channel_id: ChannelId,
+    active: Mutex<HashMap<ErrorKey, ErrorState>>,
+}
+
+impl ErrorSink {
+    pub fn new(channel_id: ChannelId) -> ErrorSink {
+        ErrorSink {
+            channel_id,
+            active: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn report(
+        &self,
+        chat: &dyn Chat,
+        source: &'static str,
+        err: &Error,
+        now: DateTime<Local>,
+    ) {
+        let key = ErrorKey {
+            source,
+            kind: err.error.kind(),
+            ctx: err.context(),
+        };
+
+        // Decide what to post while holding the lock, but don't hold it across the send.
+        let message = {
+            let mut active = self.active.lock().unwrap();
+            match active.get_mut(&key) {
+                None => {
+                    active.insert(
+                        key,
+                        ErrorState {
+                            first_seen: now,
+                            last_posted: now,
+                            count: 1,
+                        },
+                    );
+                    Some(format!("⚠️ `{}` failed: {}", source, err))
+                }
+                Some(state) => {
+                    state.count += 1;
+                    if now - state.last_posted >= Duration::minutes(REPEAT_INTERVAL_MINUTES) {
+                        state.last_posted = now;
+                        Some(format!(
+                            "⚠️ `{}` is still failing ({} times since <t:{}:f>): {}",
+                            source,
+                            state.count,
+                            state.first_seen.timestamp(),
+                            err
+                        ))
+                    } else {
+                        None
+                    }
+                }
+            }
+        };
+
+        if let Some(message) = message {
+            self.post(chat, message).await;
+        }
+    }
+
+    // Call when a source succeeds, to clear its errors and announce that it recovered.
+    pub async fn resolve(&self, chat: &dyn Chat, source: &'static str) {
+        let resolved: Vec<(ErrorKey, ErrorState)> = {
+            let mut active = self.active.lock().unwrap();
+            let keys: Vec<ErrorKey> = active
+                .keys()
+                .filter(|key| key.source == source)
+                .cloned()
+                .collect();
+            keys.into_iter()
+                .filter_map(|key| active.remove_entry(&key))
+                .collect()
+        };
+
+        for (key, state) in resolved {
+            let message = format!(
+                "✅ `{}` recovered from {} after {} failure(s) since <t:{}:f>.",
+                source,
+                key.kind,
+                state.count,
+                state.first_seen.timestamp()
+            );
+            self.post(chat, message).await;
+        }
+    }
+
+    async fn post(&self, chat: &dyn Chat, message: String) {
+        // Reporting can't report its own failures, so just log them.
+        if let Err(why) = chat.send_message(self.channel_id, &message).await {
+            error!("Failed to post to error channel: {}", why);
+        }
+    }
+}
@@ -0,0 +1,622 @@
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Local};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use sqlx::SqlitePool;
+
+use crate::db::MIGRATOR;
+use crate::error::{Error, InnerError};
+use crate::ugocoin::account::Ugocoin;
+use crate::ugocoin::audit::audit_ledger_in_tx;
+
+// Bump this whenever the shape of an export changes, so old files aren't misread.
+pub const EXPORT_VERSION: i64 = 2;
+
+// A dump of everything that matters about the bot's state, one list per table.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Export {
+    pub version: i64,
+    // The newest migration the exporting build knows. Its database is checked to match on startup.
+    pub schema_version: i64,
+    pub exported_at: i64,
+    // The sum of all account balances in ugocents, so a damaged file can't import quietly.
+    pub total_supply: i64,
+    pub users: Vec<ExportUser>,
+    pub discord_ids: Vec<ExportDiscordId>,
+    pub admins: Vec<ExportAdmin>,
+    pub accounts: Vec<ExportAccount>,
+    pub transactions: Vec<ExportTransaction>,
+    pub tips: Vec<ExportTip>,
+    pub balance_snapshots: Vec<ExportBalanceSnapshot>,
+    pub link_requests: Vec<ExportLinkRequest>,
+    pub scrums: Vec<ExportScrum>,
+    pub attendance: Vec<ExportAttendance>,
+    pub scrum_rewards: Vec<ExportScrumReward>,
+    pub scrum_slots: Vec<ExportScrumSlot>,
+    pub scrum_slot_answers: Vec<ExportScrumSlotAnswer>,
+    pub scrum_slot_picks: Vec<ExportScrumSlotPick>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportUser {
+    pub id: i64,
+    pub display_name: String,
+    pub streak: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportDiscordId {
+    pub discord_id: String,
+    pub user_id: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportAdmin {
+    pub user_id: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportAccount {
+    pub id: i64,
+    pub user_id: Option<i64>,
+    pub balance: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportTransaction {
+    pub id: i64,
+    pub tx_time: i64,
+    pub from_account_id: i64,
+    pub to_account_id: i64,
+    pub amount: i64,
+    pub memo: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportTip {
+    pub id: i64,
+    pub tip_time: i64,
+    pub tip_date: String,
+    pub message_id: String,
+    pub from_user_id: i64,
+    pub to_user_id: i64,
+    pub amount: i64,
+    pub refunded: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportBalanceSnapshot {
+    pub id: i64,
+    pub snapshot_date: String,
+    pub snapshot_time: i64,
+    pub account_id: i64,
+    pub balance: i64,
+    pub streak: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportLinkRequest {
+    pub id: i64,
+    pub discord_id: String,
+    pub user_id: i64,
+    pub request_time: i64,
+    pub status: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportScrum {
+    pub id: i64,
+    pub scrum_date: String,
+    pub is_open: bool,
+    pub message_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportAttendance {
+    pub scrum_id: i64,
+    pub user_id: i64,
+    pub response: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportScrumReward {
+    pub scrum_id: i64,
+    pub user_id: i64,
+    pub amount: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportScrumSlot {
+    pub scrum_id: i64,
+    pub slot: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportScrumSlotAnswer {
+    pub scrum_id: i64,
+    pub user_id: i64,
+    pub answered_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportScrumSlotPick {
+    pub scrum_id: i64,
+    pub user_id: i64,
+    pub slot: String,
+}
+
+pub async fn export(db: &SqlitePool, now: DateTime<Local>) -> Result<Export, Error> {
+    let users = sqlx::query_as!(
+        ExportUser,
+        "SELECT id, display_name, streak FROM users ORDER BY id"
+    )
+    .fetch_all(db)
+    .await?;
+
+    let discord_ids = sqlx::query_as!(
+        ExportDiscordId,
+        r#"SELECT discord_id, user_id as "user_id!" FROM users_discord_ids ORDER BY id"#
+    )
+    .fetch_all(db)
+    .await?;
+
+    let admins = sqlx::query_as!(ExportAdmin, "SELECT user_id FROM admins ORDER BY id")
+        .fetch_all(db)
+        .await?;
+
+    let accounts = sqlx::query_as!(
+        ExportAccount,
+        "SELECT id, user_id, balance FROM ugocoin_accounts ORDER BY id"
+    )
+    .fetch_all(db)
+    .await?;
+
+    let transactions = sqlx::query_as!(
+        ExportTransaction,
+        "SELECT id, tx_time, from_account_id, to_account_id, amount, memo
+        FROM ugocoin_tx_logs ORDER BY id"
+    )
+    .fetch_all(db)
+    .await?;
+
+    let tips = sqlx::query_as!(
+        ExportTip,
+        "SELECT id, tip_time, tip_date, message_id, from_user_id, to_user_id, amount, refunded
+        FROM ugocoin_tips ORDER BY id"
+    )
+    .fetch_all(db)
+    .await?;
+
+    let balance_snapshots = sqlx::query_as!(
+        ExportBalanceSnapshot,
+        "SELECT id, snapshot_date, snapshot_time, account_id, balance, streak
+        FROM ugocoin_balance_snapshots ORDER BY id"
+    )
+    .fetch_all(db)
+    .await?;
+
+    let link_requests = sqlx::query_as!(
+        ExportLinkRequest,
+        "SELECT id, discord_id, user_id, request_time, status FROM link_requests ORDER BY id"
+    )
+    .fetch_all(db)
+    .await?;
+
+    let scrums = sqlx::query_as!(
+        ExportScrum,
+        "SELECT id, scrum_date, is_open, message_id FROM scrums ORDER BY id"
+    )
+    .fetch_all(db)
+    .await?;
+
+    let attendance = sqlx::query_as!(
+        ExportAttendance,
+        "SELECT scrum_id, user_id, response FROM scrum_attendance ORDER BY id"
+    )
+    .fetch_all(db)
+    .await?;
+
+    let scrum_rewards = sqlx::query_as!(
+        ExportScrumReward,
+        "SELECT scrum_id, user_id, amount FROM scrum_rewards ORDER BY id"
+    )
+    .fetch_all(db)
+    .await?;
+
+    let scrum_slots = sqlx::query_as!(
+        ExportScrumSlot,
+        "SELECT scrum_id, slot FROM scrum_slots ORDER BY scrum_id, slot"
+    )
+    .fetch_all(db)
+    .await?;
+
+    let scrum_slot_answers = sqlx::query_as!(
+        ExportScrumSlotAnswer,
+        "SELECT scrum_id, user_id, answered_at FROM scrum_slot_answers ORDER BY scrum_id, user_id"
+    )
+    .fetch_all(db)
+    .await?;
+
+    let scrum_slot_picks = sqlx::query_as!(
+        ExportScrumSlotPick,
+        "SELECT scrum_id, user_id, slot FROM scrum_slot_picks ORDER BY scrum_id, user_id, slot"
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(Export {
+        version: EXPORT_VERSION,
+        schema_version: newest_schema_version(),
+        exported_at: now.timestamp(),
+        total_supply: accounts.iter().map(|account| account.balance).sum(),
+        users,
+        discord_ids,
+        admins,
+        accounts,
+        transactions,
+        tips,
+        balance_snapshots,
+        link_requests,
+        scrums,
+        attendance,
+        scrum_rewards,
+        scrum_slots,
+        scrum_slot_answers,
+        scrum_slot_picks,
+    })
+}
+
+fn newest_schema_version() -> i64 {
+    MIGRATOR
+        .iter()
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or_default()
+}
+
+// The export's top-level fields, which get their own file in a CSV export.
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportMetadata {
+    version: i64,
+    schema_version: i64,
+    exported_at: i64,
+    total_supply: i64,
+}
+
+// Writes an export as a directory with one CSV file per table, for loading into a spreadsheet.
+pub fn write_csv(export: &Export, dir: &Path) -> Result<(), Error> {
+    fs::create_dir_all(dir)?;
+
+    write_csv_table(
+        &dir.join("metadata.csv"),
+        &[ExportMetadata {
+            version: export.version,
+            schema_version: export.schema_version,
+            exported_at: export.exported_at,
+            total_supply: export.total_supply,
+        }],
+    )?;
+    write_csv_table(&dir.join("users.csv"), &export.users)?;
+    write_csv_table(&dir.join("discord_ids.csv"), &export.discord_ids)?;
+    write_csv_table(&dir.join("admins.csv"), &export.admins)?;
+    write_csv_table(&dir.join("accounts.csv"), &export.accounts)?;
+    write_csv_table(&dir.join("transactions.csv"), &export.transactions)?;
+    write_csv_table(&dir.join("tips.csv"), &export.tips)?;
+    write_csv_table(
+        &dir.join("balance_snapshots.csv"),
+        &export.balance_snapshots,
+    )?;
+    write_csv_table(&dir.join("link_requests.csv"), &export.link_requests)?;
+    write_csv_table(&dir.join("scrums.csv"), &export.scrums)?;
+    write_csv_table(&dir.join("attendance.csv"), &export.attendance)?;
+    write_csv_table(&dir.join("scrum_rewards.csv"), &export.scrum_rewards)?;
+    write_csv_table(&dir.join("scrum_slots.csv"), &export.scrum_slots)?;
+    write_csv_table(
+        &dir.join("scrum_slot_answers.csv"),
+        &export.scrum_slot_answers,
+    )?;
+    write_csv_table(&dir.join("scrum_slot_picks.csv"), &export.scrum_slot_picks)?;
+
+    Ok(())
+}
+
+fn write_csv_table<T: Serialize>(path: &Path, rows: &[T]) -> Result<(), Error> {
+    let mut writer = csv::Writer::from_path(path)?;
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+// Reads back a directory written by write_csv.
+pub fn read_csv(dir: &Path) -> Result<Export, Error> {
+    let mut metadata: Vec<ExportMetadata> = read_csv_table(&dir.join("metadata.csv"))?;
+    let metadata = match (metadata.pop(), metadata.is_empty()) {
+        (Some(metadata), true) => metadata,
+        _ => {
+            return Err(InnerError::FileError(String::from(
+                "metadata.csv should have exactly one row",
+            ))
+            .into())
+        }
+    };
+
+    Ok(Export {
+        version: metadata.version,
+        schema_version: metadata.schema_version,
+        exported_at: metadata.exported_at,
+        total_supply: metadata.total_supply,
+        users: read_csv_table(&dir.join("users.csv"))?,
+        discord_ids: read_csv_table(&dir.join("discord_ids.csv"))?,
+        admins: read_csv_table(&dir.join("admins.csv"))?,
+        accounts: read_csv_table(&dir.join("accounts.csv"))?,
+        transactions: read_csv_table(&dir.join("transactions.csv"))?,
+        tips: read_csv_table(&dir.join("tips.csv"))?,
+        balance_snapshots: read_csv_table(&dir.join("balance_snapshots.csv"))?,
+        link_requests: read_csv_table(&dir.join("link_requests.csv"))?,
+        scrums: read_csv_table(&dir.join("scrums.csv"))?,
+        attendance: read_csv_table(&dir.join("attendance.csv"))?,
+        scrum_rewards: read_csv_table(&dir.join("scrum_rewards.csv"))?,
+        scrum_slots: read_csv_table(&dir.join("scrum_slots.csv"))?,
+        scrum_slot_answers: read_csv_table(&dir.join("scrum_slot_answers.csv"))?,
+        scrum_slot_picks: read_csv_table(&dir.join("scrum_slot_picks.csv"))?,
+    })
+}
+
+fn read_csv_table<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>, Error> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let mut rows = Vec::new();
+    for row in reader.deserialize() {
+        rows.push(row?);
+    }
+
+    Ok(rows)
+}
+
+#[derive(Debug)]
+pub struct ImportSummary {
+    pub users: usize,
+    pub transactions: usize,
+    pub scrums: usize,
+    pub total_supply: Ugocoin,
+}
+
+fn reject(message: String) -> Error {
+    InnerError::ImportRejected(message).into()
+}
+
+// Rebuilds a database from an export. The database must be brand new: it gets migrated here, and
+// nothing is committed unless the imported ledger adds up.
+pub async fn import(db: &SqlitePool, export: &Export) -> Result<ImportSummary, Error> {
+    if export.version != EXPORT_VERSION {
+        return Err(reject(format!(
+            "this is a version {} export, but only version {} can be imported",
+            export.version, EXPORT_VERSION
+        )));
+    }
+
+    if export.schema_version > newest_schema_version() {
+        return Err(InnerError::SchemaTooNew(export.schema_version).into());
+    }
+
+    let tables =
+        sqlx::query!(r#"SELECT COUNT(*) as "count!: i64" FROM sqlite_master WHERE type = 'table'"#)
+            .fetch_one(db)
+            .await?;
+    if tables.count > 0 {
+        return Err(reject(String::from(
+            "the target database already has tables. Import into a new file",
+        )));
+    }
+
+    MIGRATOR.run(db).await?;
+
+    let mut db_tx = db.begin().await?;
+
+    // The migrations seed a few users and accounts. The export has the real ones.
+    sqlx::query!("DELETE FROM users_discord_ids")
+        .execute(&mut db_tx)
+        .await?;
+    sqlx::query!("DELETE FROM ugocoin_accounts")
+        .execute(&mut db_tx)
+        .await?;
+    sqlx::query!("DELETE FROM users")
+        .execute(&mut db_tx)
+        .await?;
+
+    for user in &export.users {
+        sqlx::query!(
+            "INSERT INTO users (id, display_name, streak) VALUES (?, ?, ?)",
+            user.id,
+            user.display_name,
+            user.streak
+        )
+        .execute(&mut db_tx)
+        .await?;
+    }
+
+    for discord_id in &export.discord_ids {
+        sqlx::query!(
+            "INSERT INTO users_discord_ids (discord_id, user_id) VALUES (?, ?)",
+            discord_id.discord_id,
+            discord_id.user_id
+        )
+        .execute(&mut db_tx)
+        .await?;
+    }
+
+    for admin in &export.admins {
+        sqlx::query!("INSERT INTO admins (user_id) VALUES (?)", admin.user_id)
+            .execute(&mut db_tx)
+            .await?;
+    }
+
+    for account in &export.accounts {
+        sqlx::query!(
+            "INSERT INTO ugocoin_accounts (id, user_id, balance) VALUES (?, ?, ?)",
+            account.id,
+            account.user_id,
+            account.balance
+        )
+        .execute(&mut db_tx)
+        .await?;
+    }
+
+    for transaction in &export.transactions {
+        sqlx::query!(
+            "INSERT INTO ugocoin_tx_logs (id, tx_time, from_account_id, to_account_id, amount, memo)
+            VALUES (?, ?, ?, ?, ?, ?)",
+            transaction.id,
+            transaction.tx_time,
+            transaction.from_account_id,
+            transaction.to_account_id,
+            transaction.amount,
+            transaction.memo
+        )
+        .execute(&mut db_tx)
+        .await?;
+    }
+
+    for tip in &export.tips {
+        sqlx::query!(
+            "INSERT INTO ugocoin_tips (id, tip_time, tip_date, message_id, from_user_id, to_user_id,
+                amount, refunded)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            tip.id,
+            tip.tip_time,
+            tip.tip_date,
+            tip.message_id,
+            tip.from_user_id,
+            tip.to_user_id,
+            tip.amount,
+            tip.refunded
+        )
+        .execute(&mut db_tx)
+        .await?;
+    }
+
+    for snapshot in &export.balance_snapshots {
+        sqlx::query!(
+            "INSERT INTO ugocoin_balance_snapshots (id, snapshot_date, snapshot_time, account_id,
+                balance, streak)
+            VALUES (?, ?, ?, ?, ?, ?)",
+            snapshot.id,
+            snapshot.snapshot_date,
+            snapshot.snapshot_time,
+            snapshot.account_id,
+            snapshot.balance,
+            snapshot.streak
+        )
+        .execute(&mut db_tx)
+        .await?;
+    }
+
+    for request in &export.link_requests {
+        sqlx::query!(
+            "INSERT INTO link_requests (id, discord_id, user_id, request_time, status)
+            VALUES (?, ?, ?, ?, ?)",
+            request.id,
+            request.discord_id,
+            request.user_id,
+            request.request_time,
+            request.status
+        )
+        .execute(&mut db_tx)
+        .await?;
+    }
+
+    for scrum in &export.scrums {
+        sqlx::query!(
+            "INSERT INTO scrums (id, scrum_date, is_open, message_id) VALUES (?, ?, ?, ?)",
+            scrum.id,
+            scrum.scrum_date,
+            scrum.is_open,
+            scrum.message_id
+        )
+        .execute(&mut db_tx)
+        .await?;
+    }
+
+    for attendance in &export.attendance {
+        sqlx::query!(
+            "INSERT INTO scrum_attendance (scrum_id, user_id, response) VALUES (?, ?, ?)",
+            attendance.scrum_id,
+            attendance.user_id,
+            attendance.response
+        )
+        .execute(&mut db_tx)
+        .await?;
+    }
+
+    for reward in &export.scrum_rewards {
+        sqlx::query!(
+            "INSERT INTO scrum_rewards (scrum_id, user_id, amount) VALUES (?, ?, ?)",
+            reward.scrum_id,
+            reward.user_id,
+            reward.amount
+        )
+        .execute(&mut db_tx)
+        .await?;
+    }
+
+    for slot in &export.scrum_slots {
+        sqlx::query!(
+            "INSERT INTO scrum_slots (scrum_id, slot) VALUES (?, ?)",
+            slot.scrum_id,
+            slot.slot
+        )
+        .execute(&mut db_tx)
+        .await?;
+    }
+
+    for answer in &export.scrum_slot_answers {
+        sqlx::query!(
+            "INSERT INTO scrum_slot_answers (scrum_id, user_id, answered_at) VALUES (?, ?, ?)",
+            answer.scrum_id,
+            answer.user_id,
+            answer.answered_at
+        )
+        .execute(&mut db_tx)
+        .await?;
+    }
+
+    for pick in &export.scrum_slot_picks {
+        sqlx::query!(
+            "INSERT INTO scrum_slot_picks (scrum_id, user_id, slot) VALUES (?, ?, ?)",
+            pick.scrum_id,
+            pick.user_id,
+            pick.slot
+        )
+        .execute(&mut db_tx)
+        .await?;
+    }
+
+    let report = audit_ledger_in_tx(&mut db_tx).await?;
+    if !report.is_clean() {
+        return Err(reject(report.problems.join(" ")));
+    }
+
+    let total_supply = report.total_supply();
+    if total_supply.as_ugocents() != export.total_supply {
+        return Err(reject(format!(
+            "the accounts hold {} ugocents, but the export says {}",
+            total_supply.as_ugocents(),
+            export.total_supply
+        )));
+    }
+
+    db_tx.commit().await?;
+
+    Ok(ImportSummary {
+        users: export.users.len(),
+        transactions: export.transactions.len(),
+        scrums: export.scrums.len(),
+        total_supply,
+    })
+}
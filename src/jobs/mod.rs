@@ -0,0 +1,218 @@
+use chrono::{DateTime, Local};
+
+use log::info;
+use serenity::async_trait;
+use serenity::model::id::ChannelId;
+
+use sqlx::SqlitePool;
+
+use crate::backup;
+use crate::chat::Chat;
+use crate::digest;
+use crate::error::{Error, WithContext};
+use crate::outbox;
+use crate::scrum;
+
+pub mod scheduler;
+pub use scheduler::{Scheduler, Trigger};
+
+pub type BoxedJob = Box<dyn Job + 'static + Send + Sync>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct JobChannels {
+    pub scrum: ChannelId,
+    pub digest: ChannelId,
+}
+
+pub struct JobRun {
+    pub now: DateTime<Local>,
+    // The trigger time this run is for. Later than that if the bot was down when it came due.
+    pub scheduled_for: DateTime<Local>,
+}
+
+// Work that happens on a schedule rather than in response to Discord. The scheduler runs each job
+// once per trigger time.
+#[async_trait]
+pub trait Job: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn trigger(&self) -> Trigger;
+    async fn run(
+        &self,
+        db: &SqlitePool,
+        chat: &dyn Chat,
+        run: &JobRun,
+        channels: &JobChannels,
+    ) -> Result<(), Error>;
+}
+
+struct OpenScrumJob {}
+
+#[async_trait]
+impl Job for OpenScrumJob {
+    fn name(&self) -> &'static str {
+        "open_scrum"
+    }
+
+    fn trigger(&self) -> Trigger {
+        Trigger::DailyAt {
+            hour: scrum::SCRUM_OPEN_HOUR,
+            minute: 0,
+        }
+    }
+
+    async fn run(
+        &self,
+        db: &SqlitePool,
+        chat: &dyn Chat,
+        run: &JobRun,
+        channels: &JobChannels,
+    ) -> Result<(), Error> {
+        scrum::open_scrum(
+            db,
+            chat,
+            run.now,
+            channels.scrum,
+            &scrum::ScrumPoll::from_env(),
+        )
+        .await
+    }
+}
+
+struct CloseScrumJob {}
+
+#[async_trait]
+impl Job for CloseScrumJob {
+    fn name(&self) -> &'static str {
+        "close_scrum"
+    }
+
+    fn trigger(&self) -> Trigger {
+        Trigger::DailyAt {
+            hour: scrum::SCRUM_CLOSE_HOUR,
+            minute: 0,
+        }
+    }
+
+    async fn run(
+        &self,
+        db: &SqlitePool,
+        chat: &dyn Chat,
+        run: &JobRun,
+        channels: &JobChannels,
+    ) -> Result<(), Error> {
+        scrum::force_close_scrum(db, chat, run.now, run.scheduled_for, channels.scrum).await
+    }
+}
+
+struct DigestJob {}
+
+#[async_trait]
+impl Job for DigestJob {
+    fn name(&self) -> &'static str {
+        "digest"
+    }
+
+    fn trigger(&self) -> Trigger {
+        Trigger::DailyAt {
+            hour: digest::DIGEST_HOUR,
+            minute: 0,
+        }
+    }
+
+    async fn run(
+        &self,
+        db: &SqlitePool,
+        _chat: &dyn Chat,
+        run: &JobRun,
+        channels: &JobChannels,
+    ) -> Result<(), Error> {
+        let latest_snapshot = digest::get_latest_snapshot_date(db)
+            .await
+            .with_context("Getting latest balance snapshot")?;
+
+        // A digest caught up after midnight would be for the wrong day, so skip it.
+        if !digest::should_run_digest(run.now, latest_snapshot) {
+            return Ok(());
+        }
+
+        info!("Running UGOcoin digest.");
+        digest::run_digest(
+            db,
+            run.now,
+            digest::DigestPeriod::from_env(),
+            channels.digest,
+        )
+        .await
+        .with_context("Running UGOcoin digest")
+    }
+}
+
+struct OutboxJob {}
+
+#[async_trait]
+impl Job for OutboxJob {
+    fn name(&self) -> &'static str {
+        "outbox"
+    }
+
+    fn trigger(&self) -> Trigger {
+        Trigger::EveryMinutes(1)
+    }
+
+    async fn run(
+        &self,
+        db: &SqlitePool,
+        chat: &dyn Chat,
+        run: &JobRun,
+        _channels: &JobChannels,
+    ) -> Result<(), Error> {
+        outbox::flush(db, chat, run.now).await
+    }
+}
+
+struct BackupJob {}
+
+#[async_trait]
+impl Job for BackupJob {
+    fn name(&self) -> &'static str {
+        "backup"
+    }
+
+    fn trigger(&self) -> Trigger {
+        Trigger::DailyAt {
+            hour: backup::BACKUP_HOUR,
+            minute: 0,
+        }
+    }
+
+    async fn run(
+        &self,
+        db: &SqlitePool,
+        _chat: &dyn Chat,
+        run: &JobRun,
+        _channels: &JobChannels,
+    ) -> Result<(), Error> {
+        let config = match backup::BackupConfig::from_env() {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+
+        backup::run_backup(db, run.now, &config).await?;
+        Ok(())
+    }
+}
+
+lazy_static! {
+    // Jobs that come due on the same tick run in this order.
+    static ref JOBS: Vec<BoxedJob> = vec![
+        Box::new(OpenScrumJob {}),
+        Box::new(CloseScrumJob {}),
+        Box::new(DigestJob {}),
+        Box::new(OutboxJob {}),
+        Box::new(BackupJob {}),
+    ];
+}
+
+pub fn jobs() -> &'static [BoxedJob] {
+    &JOBS
+}
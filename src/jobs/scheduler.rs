@@ -0,0 +1,421 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Duration, Local, NaiveDate, TimeZone};
+
+use log::{error, info};
+
+use sqlx::SqlitePool;
+
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+use tracing::{info_span, Instrument};
+
+use super::{jobs, JobChannels, JobRun};
+use crate::chat::Chat;
+use crate::clock::Clock;
+use crate::error::{Error, WithContext};
+use crate::error_sink::ErrorSink;
+use crate::metrics;
+
+const TICK_SECONDS: u64 = 60;
+
+// Long enough to survive a slow tick, short enough that a standby takes over soon after the
+// holder dies.
+const LEASE_MINUTES: i64 = 3;
+
+const SCHEDULER_SOURCE: &str = "scheduler";
+
+// A failed run is tried again after this long, doubling each time up to the cap, until the job's
+// next trigger time takes over.
+const RETRY_BASE_SECONDS: i64 = 60;
+const RETRY_MAX_SECONDS: i64 = 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    // Every so many minutes, lined up with the Unix epoch.
+    EveryMinutes(i64),
+    // Once a day at this local time.
+    DailyAt { hour: u32, minute: u32 },
+}
+
+fn local_time_on(date: NaiveDate, hour: u32, minute: u32) -> DateTime<Local> {
+    let naive = date
+        .and_hms_opt(hour, minute, 0)
+        .expect("Invalid trigger time");
+
+    // If DST skips this time, fire when the clocks jump forward instead. An hour is as long as
+    // any DST gap we care about.
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .or_else(|| {
+            Local
+                .from_local_datetime(&(naive + Duration::hours(1)))
+                .earliest()
+        })
+        .expect("Trigger time doesn't exist")
+}
+
+impl Trigger {
+    // The last time this trigger fired, at or before `now`.
+    pub fn latest(&self, now: DateTime<Local>) -> DateTime<Local> {
+        match *self {
+            Trigger::EveryMinutes(minutes) => {
+                let timestamp = now.timestamp();
+                let start = timestamp - timestamp.rem_euclid(minutes * 60);
+                Local.timestamp_opt(start, 0).unwrap()
+            }
+            Trigger::DailyAt { hour, minute } => {
+                let today = local_time_on(now.date_naive(), hour, minute);
+                if today <= now {
+                    today
+                } else {
+                    let yesterday = now.date_naive().pred_opt().unwrap();
+                    local_time_on(yesterday, hour, minute)
+                }
+            }
+        }
+    }
+
+    // The next time this trigger fires, after `now`.
+    pub fn next(&self, now: DateTime<Local>) -> DateTime<Local> {
+        match *self {
+            Trigger::EveryMinutes(minutes) => self.latest(now) + Duration::minutes(minutes),
+            Trigger::DailyAt { hour, minute } => {
+                let today = local_time_on(now.date_naive(), hour, minute);
+                if today > now {
+                    today
+                } else {
+                    let tomorrow = now.date_naive().succ_opt().unwrap();
+                    local_time_on(tomorrow, hour, minute)
+                }
+            }
+        }
+    }
+}
+
+impl Display for Trigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Trigger::EveryMinutes(1) => write!(f, "every minute"),
+            Trigger::EveryMinutes(minutes) => write!(f, "every {} minutes", minutes),
+            Trigger::DailyAt { hour, minute } => write!(f, "daily at {:02}:{:02}", hour, minute),
+        }
+    }
+}
+
+pub struct JobOutcome {
+    pub job: &'static str,
+    pub result: Result<(), Error>,
+}
+
+pub struct LastRun {
+    pub scheduled_for: i64,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub succeeded: Option<bool>,
+    pub error: Option<String>,
+    pub attempts: i64,
+}
+
+pub async fn get_last_run(db: &SqlitePool, job_name: &str) -> Result<Option<LastRun>, Error> {
+    Ok(sqlx::query_as!(
+        LastRun,
+        r#"SELECT scheduled_for as "scheduled_for!", started_at as "started_at!",
+            finished_at, succeeded as "succeeded?: bool", error, attempts
+        FROM job_runs
+        WHERE job_name = ?
+        ORDER BY scheduled_for DESC
+        LIMIT 1"#,
+        job_name
+    )
+    .fetch_optional(db)
+    .await?)
+}
+
+// Records that a job is running for a trigger time. Returns false if that time was already claimed,
+// by this instance or an earlier one, so each trigger time runs at most once even across restarts.
+async fn claim_run(
+    db: &SqlitePool,
+    job_name: &str,
+    scheduled_for: DateTime<Local>,
+    now: DateTime<Local>,
+) -> Result<bool, Error> {
+    let scheduled_for = scheduled_for.timestamp();
+    let started_at = now.timestamp();
+
+    let result = sqlx::query!(
+        "INSERT OR IGNORE INTO job_runs (job_name, scheduled_for, started_at) VALUES (?, ?, ?)",
+        job_name,
+        scheduled_for,
+        started_at
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+// Claims a failed run again once its backoff is up. Returns false if the run didn't fail, isn't due
+// yet, or another instance got to it first. A run that never finished, because the bot died
+// halfway, isn't retried: we can't tell how far it got.
+async fn reclaim_failed_run(
+    db: &SqlitePool,
+    job_name: &str,
+    scheduled_for: DateTime<Local>,
+    now: DateTime<Local>,
+) -> Result<bool, Error> {
+    let scheduled_for = scheduled_for.timestamp();
+    let now = now.timestamp();
+
+    let result = sqlx::query!(
+        "UPDATE job_runs SET attempts = attempts + 1, started_at = ?, finished_at = NULL,
+            succeeded = NULL
+        WHERE job_name = ? AND scheduled_for = ? AND succeeded = false
+            AND finished_at + MIN(? << MIN(attempts - 1, 16), ?) <= ?",
+        now,
+        job_name,
+        scheduled_for,
+        RETRY_BASE_SECONDS,
+        RETRY_MAX_SECONDS,
+        now
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+async fn finish_run(
+    db: &SqlitePool,
+    job_name: &str,
+    scheduled_for: DateTime<Local>,
+    now: DateTime<Local>,
+    result: &Result<(), Error>,
+) -> Result<(), Error> {
+    let scheduled_for = scheduled_for.timestamp();
+    let finished_at = now.timestamp();
+    let succeeded = result.is_ok();
+    let error = result.as_ref().err().map(|err| err.to_string());
+
+    sqlx::query!(
+        "UPDATE job_runs SET finished_at = ?, succeeded = ?, error = ?
+        WHERE job_name = ? AND scheduled_for = ?",
+        finished_at,
+        succeeded,
+        error,
+        job_name,
+        scheduled_for
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+// Who holds the scheduler lease right now, if anyone. A holder means a bot is running against this
+// database.
+pub async fn lease_holder(db: &SqlitePool, now: DateTime<Local>) -> Result<Option<String>, Error> {
+    let now = now.timestamp();
+    let holder = sqlx::query!(
+        "SELECT owner FROM scheduler_lease WHERE expires_at >= ?",
+        now
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(holder.map(|holder| holder.owner))
+}
+
+pub struct Scheduler {
+    // Identifies this process in the lease, so a second copy of the bot stays idle.
+    instance_id: String,
+    started: AtomicBool,
+    stopping: Notify,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+
+        Scheduler {
+            instance_id: format!("{}-{}", std::process::id(), started_at),
+            started: AtomicBool::new(false),
+            stopping: Notify::new(),
+            task: Mutex::new(None),
+        }
+    }
+
+    // Takes or renews the lease. Returns false if another instance holds it.
+    async fn hold_lease(&self, db: &SqlitePool, now: DateTime<Local>) -> Result<bool, Error> {
+        let expires_at = (now + Duration::minutes(LEASE_MINUTES)).timestamp();
+        let now = now.timestamp();
+
+        let result = sqlx::query!(
+            "INSERT INTO scheduler_lease (id, owner, expires_at) VALUES (1, ?, ?)
+            ON CONFLICT (id) DO UPDATE SET owner = excluded.owner, expires_at = excluded.expires_at
+            WHERE scheduler_lease.owner = excluded.owner OR scheduler_lease.expires_at < ?",
+            self.instance_id,
+            expires_at,
+            now
+        )
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    // Gives up the lease so a standby can take over straight away instead of waiting for it to
+    // expire.
+    async fn release_lease(&self, db: &SqlitePool) -> Result<(), Error> {
+        sqlx::query!(
+            "DELETE FROM scheduler_lease WHERE owner = ?",
+            self.instance_id
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    // Runs every job whose latest trigger time hasn't been run yet, and retries ones that failed.
+    pub async fn tick(
+        &self,
+        db: &SqlitePool,
+        chat: &dyn Chat,
+        now: DateTime<Local>,
+        channels: &JobChannels,
+    ) -> Result<Vec<JobOutcome>, Error> {
+        if !self
+            .hold_lease(db, now)
+            .await
+            .with_context("Taking scheduler lease")?
+        {
+            return Ok(Vec::new());
+        }
+
+        let mut outcomes = Vec::new();
+        for job in jobs() {
+            let scheduled_for = job.trigger().latest(now);
+            let claimed = claim_run(db, job.name(), scheduled_for, now)
+                .await
+                .with_context("Claiming job run")?
+                || reclaim_failed_run(db, job.name(), scheduled_for, now)
+                    .await
+                    .with_context("Claiming failed job run")?;
+            if !claimed {
+                continue;
+            }
+
+            info!(
+                "Running job {} scheduled for {}.",
+                job.name(),
+                scheduled_for
+            );
+            let run = JobRun { now, scheduled_for };
+            let span = info_span!("job", job = job.name(), scheduled_for = %scheduled_for);
+            let started = Instant::now();
+            let result = job.run(db, chat, &run, channels).instrument(span).await;
+            metrics::record_job_run(job.name(), &result, started.elapsed());
+
+            finish_run(db, job.name(), scheduled_for, now, &result)
+                .await
+                .with_context("Recording job result")?;
+
+            outcomes.push(JobOutcome {
+                job: job.name(),
+                result,
+            });
+        }
+
+        Ok(outcomes)
+    }
+
+    // Spawns the loop that ticks the scheduler every minute. Discord fires `ready` again on every
+    // reconnect, so only the first call does anything. Returns whether the loop was started.
+    // Once stopped, the loop is never started again.
+    pub fn start<C: Chat + 'static>(
+        self: Arc<Self>,
+        db: SqlitePool,
+        chat: C,
+        clock: Arc<dyn Clock>,
+        error_sink: Arc<ErrorSink>,
+        channels: JobChannels,
+    ) -> bool {
+        if self.started.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+
+        let scheduler = self.clone();
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(TICK_SECONDS));
+            loop {
+                // Only wait for the stop signal between ticks, so a job is never cut off halfway.
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = self.stopping.notified() => break,
+                }
+                let now = clock.now();
+
+                let outcomes = match self.tick(&db, &chat, now, &channels).await {
+                    Ok(outcomes) => {
+                        error_sink.resolve(&chat, SCHEDULER_SOURCE).await;
+                        outcomes
+                    }
+                    Err(why) => {
+                        error!("{}", why);
+                        error_sink.report(&chat, SCHEDULER_SOURCE, &why, now).await;
+                        continue;
+                    }
+                };
+
+                for outcome in outcomes {
+                    match outcome.result {
+                        Ok(()) => error_sink.resolve(&chat, outcome.job).await,
+                        Err(why) => {
+                            error!("Job {} failed: {}", outcome.job, why);
+                            error_sink.report(&chat, outcome.job, &why, now).await;
+                        }
+                    }
+                }
+            }
+
+            if let Err(why) = self.release_lease(&db).await {
+                error!("Failed to release scheduler lease: {}", why);
+            }
+            info!("Job scheduler stopped.");
+        });
+
+        *scheduler.task.lock().unwrap() = Some(task);
+        true
+    }
+
+    // Stops the loop after any tick in progress and waits up to `timeout` for it to finish.
+    // Returns false if it didn't finish in time.
+    pub async fn stop(&self, timeout: std::time::Duration) -> bool {
+        // Keeps `start` from spawning the loop if we stop before the gateway is ever ready.
+        self.started.store(true, Ordering::SeqCst);
+        // Stored as a permit if the loop is busy ticking, so it stops right after.
+        self.stopping.notify_one();
+
+        let task = self.task.lock().unwrap().take();
+        match task {
+            Some(task) => tokio::time::timeout(timeout, task).await.is_ok(),
+            None => true,
+        }
+    }
+}
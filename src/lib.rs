@@ -0,0 +1,23 @@
+#[macro_use]
+extern crate lazy_static;
+
+pub mod admin_api;
+pub mod backup;
+pub mod chart;
+pub mod chat;
+pub mod clock;
+pub mod command;
+pub mod db;
+pub mod digest;
+pub mod error;
+pub mod error_sink;
+pub mod export;
+pub mod jobs;
+pub mod logging;
+pub mod metrics;
+pub mod outbox;
+pub mod permission;
+pub mod scrum;
+pub mod shutdown;
+pub mod ugocoin;
+pub mod user;
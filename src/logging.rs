@@ -0,0 +1,132 @@
+// Log setup. Lines carry the spans they happened in, like the interaction or job being handled.
+// The `log` macros used around the codebase are picked up too.
+use std::env;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use rolling_file::{BasicRollingFileAppender, RollingConditionBasic};
+
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use crate::error::{Error, InnerError};
+
+const DEFAULT_LOG_FILE: &str = "ugo-ii-bot.log";
+const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_KEEP: usize = 7;
+
+pub enum LogFormat {
+    Text,
+    // One JSON object per line, with the current span and its parents.
+    Json,
+}
+
+pub enum Rotation {
+    Hourly,
+    Daily,
+    // Only rotate when the file gets too big.
+    Never,
+}
+
+pub struct LogConfig {
+    pub format: LogFormat,
+    // None logs to the terminal instead.
+    pub file: Option<PathBuf>,
+    pub rotation: Rotation,
+    pub max_bytes: u64,
+    // How many rotated files to keep, as ugo-ii-bot.log.1 and so on.
+    pub keep: usize,
+}
+
+impl LogConfig {
+    // LOG_FORMAT=json, LOG_FILE, LOG_ROTATE=hourly|daily|never, LOG_MAX_BYTES and LOG_KEEP. In dev
+    // we log to the terminal. RUST_LOG filters as usual.
+    pub fn from_env(dev: bool) -> LogConfig {
+        let format = match env::var("LOG_FORMAT") {
+            Ok(format) if format.eq_ignore_ascii_case("json") => LogFormat::Json,
+            _ => LogFormat::Text,
+        };
+
+        let file = if dev {
+            None
+        } else {
+            Some(PathBuf::from(
+                env::var("LOG_FILE").unwrap_or_else(|_| String::from(DEFAULT_LOG_FILE)),
+            ))
+        };
+
+        let rotation = match env::var("LOG_ROTATE") {
+            Ok(rotation) if rotation.eq_ignore_ascii_case("hourly") => Rotation::Hourly,
+            Ok(rotation) if rotation.eq_ignore_ascii_case("never") => Rotation::Never,
+            _ => Rotation::Daily,
+        };
+
+        LogConfig {
+            format,
+            file,
+            rotation,
+            max_bytes: env_number("LOG_MAX_BYTES").unwrap_or(DEFAULT_MAX_BYTES),
+            keep: env_number("LOG_KEEP").unwrap_or(DEFAULT_KEEP),
+        }
+    }
+}
+
+fn env_number<T: std::str::FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok()?.parse().ok()
+}
+
+// The appender buffers, so push every line out straight away in case we crash.
+struct FlushingWriter(BasicRollingFileAppender);
+
+impl Write for FlushingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.0.write(buf)?;
+        self.0.flush()?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+pub fn init(config: &LogConfig) -> Result<(), Error> {
+    let writer = match &config.file {
+        Some(path) => {
+            let condition = match config.rotation {
+                Rotation::Hourly => RollingConditionBasic::new().hourly(),
+                Rotation::Daily => RollingConditionBasic::new().daily(),
+                Rotation::Never => RollingConditionBasic::new(),
+            }
+            .max_size(config.max_bytes);
+
+            // Appends, so restarting keeps what was already logged.
+            let appender = BasicRollingFileAppender::new(path, condition, config.keep)?;
+            BoxMakeWriter::new(Mutex::new(FlushingWriter(appender)))
+        }
+        None => BoxMakeWriter::new(io::stdout),
+    };
+
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(config.file.is_none());
+    let layer = match config.format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+    };
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    tracing_subscriber::registry()
+        .with(layer)
+        .with(filter)
+        .try_init()
+        .map_err(|err| InnerError::LoggingError(err.to_string()).into())
+}
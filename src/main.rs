@@ -1,179 +1,196 @@
 extern crate dotenv;
 
 use std::env;
-use std::fs::File;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
 use dotenv::dotenv;
 
 use log::error;
 use log::info;
-use log::LevelFilter;
-
-#[macro_use]
-extern crate lazy_static;
 
+use serenity::client::bridge::gateway::event::ShardStageUpdateEvent;
+use serenity::gateway::ConnectionStage;
 use serenity::model::application::interaction::Interaction;
 use serenity::model::channel::Reaction;
 use serenity::model::gateway::Ready;
 use serenity::model::id::{ChannelId, GuildId};
 use serenity::{async_trait, prelude::*};
 
-use simplelog::ColorChoice;
-use simplelog::Config;
-use simplelog::TermLogger;
-use simplelog::TerminalMode;
-use simplelog::WriteLogger;
-
 use sqlx::sqlite::SqliteConnectOptions;
 use sqlx::sqlite::SqlitePoolOptions;
 use sqlx::SqlitePool;
 
 use chrono::prelude::*;
 
-mod error;
-use error::WithContext;
+use tracing::{info_span, Instrument};
 
-mod command;
-mod scrum;
-mod ugocoin;
-mod user;
+use ugo_ii_bot::admin_api::{self, ApiState, BotStatus};
+use ugo_ii_bot::chat::{DiscordChat, InteractionHandle};
+use ugo_ii_bot::clock::{Clock, SystemClock};
+use ugo_ii_bot::db::{self, MigrationMode};
+use ugo_ii_bot::error::{self, WithContext};
+use ugo_ii_bot::error_sink::ErrorSink;
+use ugo_ii_bot::jobs::{JobChannels, Scheduler};
+use ugo_ii_bot::logging::{self, LogConfig};
+use ugo_ii_bot::shutdown::{self, Shutdown};
+use ugo_ii_bot::{command, scrum, ugocoin};
 
 struct Handler {
     db: SqlitePool,
+    clock: Arc<dyn Clock>,
+    error_sink: Arc<ErrorSink>,
+    scheduler: Arc<Scheduler>,
+    status: Arc<BotStatus>,
+    shutdown: Arc<Shutdown>,
 }
 
 const GENERAL_CHANNEL_ID: u64 = 822531930384891948;
 const BOT_CHANNEL_ID: u64 = 1044762069070774332;
 
-async fn job_poll_fn(db: &SqlitePool, ctx: Context) -> Result<(), error::Error> {
-    let now = Local::now();
-
-    let today_scrum = scrum::get_scrum_for_date(db, now)
-        .await
-        .with_context("Getting today's scrum")?;
-
-    if scrum::should_create_scrum(now, today_scrum.as_ref()) {
-        info!("Creating new scrum.");
-        let channel_id = ChannelId(GENERAL_CHANNEL_ID);
-        scrum::notify_scrum(db, now, &ctx, channel_id)
-            .await
-            .with_context("Notifying scrum")?;
-    }
-
-    if let Some(to_close) = scrum::should_force_close_scrum(now, today_scrum.as_ref()) {
-        info!("Force closing scrum.");
-        let channel_id = ChannelId(GENERAL_CHANNEL_ID);
-
-        let message_id = to_close
-            .message_id()
-            .with_context("Parsing today's scrum message ID")?;
-
-        let message = channel_id
-            .message(&ctx.http, message_id)
-            .await
-            .with_context("Fetching message from today's scrum")?;
+// How long to wait for running jobs and commands when asked to stop.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
 
-        let reactions = scrum::parse_scrum_reactions(db, &ctx, &message)
-            .await
-            .with_context("Parsing scrum reactions")?;
-
-        info!(
-            "Reactions parsed. {} available. {} unavailable. {} unknown.",
-            reactions.num_available, reactions.num_unavailable, reactions.num_unknown
-        );
-
-        let scrum_status = scrum::scrum_status(&reactions);
-        info!("Scrum status {:?}", scrum_status);
-
-        scrum::close_scrum(db, &ctx, &to_close, &reactions, channel_id, scrum_status)
-            .await
-            .with_context("Force closing scrum")?;
-    }
-
-    Ok(())
-}
+const JOB_CHANNELS: JobChannels = JobChannels {
+    scrum: ChannelId(GENERAL_CHANNEL_ID),
+    digest: ChannelId(BOT_CHANNEL_ID),
+};
 
 // We need to separately declare these event functions so we can return a Result.
 // I'd make a function that takes a closure to clean this up, but async closures are unstable :(
 async fn interaction_create(
     db: &SqlitePool,
+    now: DateTime<Local>,
     ctx: &Context,
     interaction: Interaction,
 ) -> Result<(), error::Error> {
-    if let Interaction::ApplicationCommand(command) = interaction {
-        info!("Executing command {}.", command.data.name);
-        command::run_command(db, ctx, &command)
-            .await
-            .with_context("executing command.")?;
+    match interaction {
+        Interaction::ApplicationCommand(command) => {
+            info!("Executing command {}.", command.data.name);
+            let chat = DiscordChat::new(ctx.http.clone());
+            let result = command::run_command(db, &chat, now, &command)
+                .await
+                .with_context(format!("Executing /{}", command.data.name));
+
+            // Every interaction needs an answer, or the user just sees "The application did not respond".
+            if let Err(err) = result {
+                command::reply_with_error(&chat, &command, &err)
+                    .await
+                    .with_context("Replying with command error")?;
+            }
+        }
+        Interaction::Autocomplete(autocomplete) => {
+            command::run_autocomplete(db, ctx, &autocomplete)
+                .await
+                .with_context("Answering autocomplete")?;
+        }
+        Interaction::MessageComponent(component)
+            if component.data.custom_id == scrum::SCRUM_SLOT_MENU_ID =>
+        {
+            let chat = DiscordChat::new(ctx.http.clone());
+            let pick = scrum::SlotPick {
+                interaction: InteractionHandle {
+                    id: component.id,
+                    token: component.token,
+                },
+                channel_id: component.channel_id,
+                message_id: component.message.id,
+                picker_id: component.user.id,
+                values: component.data.values,
+            };
+            scrum::on_slot_pick(db, &chat, now, &pick)
+                .await
+                .with_context("Recording scrum slot pick")?;
+        }
+        _ => {}
     };
 
     Ok(())
 }
 
-async fn on_react(db: &SqlitePool, ctx: &Context, react: Reaction) -> Result<(), error::Error> {
-    let now = Local::now();
+async fn on_react(
+    db: &SqlitePool,
+    now: DateTime<Local>,
+    ctx: &Context,
+    react: Reaction,
+) -> Result<(), error::Error> {
+    let chat = DiscordChat::new(ctx.http.clone());
+    scrum::on_scrum_react(db, &chat, now, react.channel_id, react.message_id).await
+}
 
-    let scrum = match scrum::get_scrum_from_message(&db, react.message_id)
-        .await
-        .with_context("Fetching scrum from message")?
-    {
-        Some(scrum) => scrum,
+async fn on_tip_add(
+    db: &SqlitePool,
+    now: DateTime<Local>,
+    ctx: &Context,
+    react: Reaction,
+) -> Result<(), error::Error> {
+    let reactor_id = match react.user_id {
+        Some(user_id) => user_id,
         None => return Ok(()),
     };
-    info!("Reaction changed for scrum {}", scrum.scrum_date);
-
-    // If this is a closed scrum, ignore it.
-    if !scrum.is_open {
-        info!("Scrum closed. Ignoring.");
-        return Ok(());
-    }
 
-    // If this isn't today's scrum, ignore it.
-    if scrum.date()? != now.date_naive() {
-        info!("Scrum for previous date. Ignoring.");
-        return Ok(());
-    }
-
-    let discord_message = react
-        .message(&ctx.http)
-        .await
-        .with_context("Finding Discord message for react")?;
+    let chat = DiscordChat::new(ctx.http.clone());
+    ugocoin::tip::on_tip_add(
+        db,
+        &chat,
+        now,
+        react.channel_id,
+        react.message_id,
+        reactor_id,
+    )
+    .await
+}
 
-    let reactions = scrum::parse_scrum_reactions(&db, &ctx, &discord_message)
-        .await
-        .with_context("Parsing scrum reactions")?;
-
-    info!(
-        "Reactions parsed. {} available. {} unavailable. {} unknown.",
-        reactions.num_available, reactions.num_unavailable, reactions.num_unknown
-    );
-
-    // Only close on react if all the votes are in
-    if reactions.num_unknown == 0 {
-        info!("Closing scrum.");
-        let scrum_status = scrum::scrum_status(&reactions);
-        info!("Scrum status: {:?}", scrum_status);
-        scrum::close_scrum(
-            db,
-            ctx,
-            &scrum,
-            &reactions,
-            ChannelId(GENERAL_CHANNEL_ID),
-            scrum_status,
-        )
-        .await
-        .with_context("Closing scrum")?;
-    }
+async fn on_tip_remove(
+    db: &SqlitePool,
+    now: DateTime<Local>,
+    react: Reaction,
+) -> Result<(), error::Error> {
+    let reactor_id = match react.user_id {
+        Some(user_id) => user_id,
+        None => return Ok(()),
+    };
 
-    Ok(())
+    ugocoin::tip::on_tip_remove(db, now, react.message_id, reactor_id).await
 }
 
 #[async_trait]
 impl EventHandler for Handler {
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
-        let result = interaction_create(&self.db, &ctx, interaction).await;
+        let _in_flight = match self.shutdown.enter() {
+            Some(in_flight) => in_flight,
+            None => return,
+        };
+
+        let span = match &interaction {
+            Interaction::ApplicationCommand(command) => info_span!(
+                "interaction",
+                kind = "command",
+                command = %command.data.name,
+                user_id = %command.user.id,
+                user = %command.user.name
+            ),
+            Interaction::Autocomplete(autocomplete) => info_span!(
+                "interaction",
+                kind = "autocomplete",
+                command = %autocomplete.data.name,
+                user_id = %autocomplete.user.id,
+                user = %autocomplete.user.name
+            ),
+            Interaction::MessageComponent(component) => info_span!(
+                "interaction",
+                kind = "component",
+                command = %component.data.custom_id,
+                user_id = %component.user.id,
+                user = %component.user.name
+            ),
+            _ => info_span!("interaction", kind = "other"),
+        };
+
+        let result = interaction_create(&self.db, self.clock.now(), &ctx, interaction)
+            .instrument(span)
+            .await;
 
         if let Err(why) = result {
             error!("{}", why);
@@ -181,7 +198,16 @@ impl EventHandler for Handler {
     }
 
     async fn reaction_add(&self, ctx: Context, added: Reaction) {
-        let result = on_react(&self.db, &ctx, added).await;
+        let _in_flight = match self.shutdown.enter() {
+            Some(in_flight) => in_flight,
+            None => return,
+        };
+
+        let result = if ugocoin::tip::is_tip_reaction(&added.emoji) {
+            on_tip_add(&self.db, self.clock.now(), &ctx, added).await
+        } else {
+            on_react(&self.db, self.clock.now(), &ctx, added).await
+        };
 
         if let Err(why) = result {
             error!("{}", why);
@@ -189,14 +215,35 @@ impl EventHandler for Handler {
     }
 
     async fn reaction_remove(&self, ctx: Context, removed: Reaction) {
-        let result = on_react(&self.db, &ctx, removed).await;
+        let _in_flight = match self.shutdown.enter() {
+            Some(in_flight) => in_flight,
+            None => return,
+        };
+
+        let result = if ugocoin::tip::is_tip_reaction(&removed.emoji) {
+            on_tip_remove(&self.db, self.clock.now(), removed).await
+        } else {
+            on_react(&self.db, self.clock.now(), &ctx, removed).await
+        };
 
         if let Err(why) = result {
             error!("{}", why);
         }
     }
 
+    async fn shard_stage_update(&self, _ctx: Context, update: ShardStageUpdateEvent) {
+        self.status
+            .set_gateway_connected(update.new == ConnectionStage::Connected);
+    }
+
     async fn ready(&self, ctx: Context, _ready: Ready) {
+        self.status.set_gateway_connected(true);
+
+        // The gateway can reconnect while we're shutting down. Don't start anything new.
+        if self.shutdown.is_stopping() {
+            return;
+        }
+
         let guild_id = GuildId(
             env::var("GUILD_ID")
                 .expect("No GUILD_ID variable in environment!")
@@ -208,22 +255,21 @@ impl EventHandler for Handler {
             .await
             .expect("Failed to create commands!");
 
-        let db = self.db.clone();
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(Duration::from_secs(60));
-            loop {
-                interval.tick().await;
-                let result = job_poll_fn(&db, ctx.clone()).await;
-                if let Err(why) = result {
-                    error!("{}", why);
-                }
-            }
-        });
+        let chat = DiscordChat::new(ctx.http.clone());
+        let started = self.scheduler.clone().start(
+            self.db.clone(),
+            chat,
+            self.clock.clone(),
+            self.error_sink.clone(),
+            JOB_CHANNELS,
+        );
+
+        if !started {
+            info!("Reconnected. Job scheduler is already running.");
+        }
     }
 }
 
-const LOG_FILE_PATH: &str = "ugo-ii-bot.log";
-
 #[tokio::main]
 async fn main() {
     dotenv().ok();
@@ -235,22 +281,7 @@ async fn main() {
         env::var("DATABASE_URL").expect("NO DATABASE_URL found in environment variables!");
 
     let dev = env::var("DEV").is_ok();
-    if dev {
-        TermLogger::init(
-            LevelFilter::Info,
-            Config::default(),
-            TerminalMode::Mixed,
-            ColorChoice::Auto,
-        )
-        .expect("Failed to create terminal logger.");
-    } else {
-        WriteLogger::init(
-            LevelFilter::Info,
-            Config::default(),
-            File::create(LOG_FILE_PATH).expect("Failed to create log file."),
-        )
-        .expect("Failed to create file logger.")
-    }
+    logging::init(&LogConfig::from_env(dev)).expect("Failed to set up logging.");
 
     let connect_options = SqliteConnectOptions::from_str(&database_url)
         .expect("Failed to parse DATABASE_URL!")
@@ -262,16 +293,73 @@ async fn main() {
         .await
         .expect("Failed to connect to database.");
 
-    let handler = Handler { db: database };
+    // Migrations only run when asked to, so a deploy never changes the schema by surprise. That
+    // includes AUTO_MIGRATE=0 or an empty AUTO_MIGRATE, so only 1 and true turn it on.
+    let auto_migrate = matches!(env::var("AUTO_MIGRATE").as_deref(), Ok("1" | "true"));
+    let migration_mode = if env::args().any(|arg| arg == "--migrate") || auto_migrate {
+        MigrationMode::Auto
+    } else {
+        MigrationMode::Check
+    };
+
+    if let Err(why) = db::prepare(&database, migration_mode).await {
+        error!("{}", why);
+        panic!("Database isn't ready: {}", why);
+    }
+
+    let status = Arc::new(BotStatus::new());
+    let scheduler = Arc::new(Scheduler::new());
+    let shutdown = Arc::new(Shutdown::new());
+
+    if let Some(port) = admin_api::port_from_env() {
+        let state = ApiState {
+            db: database.clone(),
+            status: status.clone(),
+        };
+        tokio::spawn(async move {
+            if let Err(why) = admin_api::serve(port, state).await {
+                error!("{}", why);
+            }
+        });
+    }
+
+    let handler = Handler {
+        db: database.clone(),
+        clock: Arc::new(SystemClock),
+        error_sink: Arc::new(ErrorSink::new(ChannelId(BOT_CHANNEL_ID))),
+        scheduler: scheduler.clone(),
+        status,
+        shutdown: shutdown.clone(),
+    };
 
-    let intents = GatewayIntents::GUILD_MESSAGE_REACTIONS;
+    let intents = GatewayIntents::GUILD_MESSAGE_REACTIONS | GatewayIntents::GUILD_MESSAGES;
 
     let mut client = Client::builder(token, intents)
         .event_handler(handler)
         .await
         .expect("Failed to create Discord client!");
 
+    let shard_manager = client.shard_manager.clone();
+    tokio::spawn(async move {
+        shutdown::signal().await;
+        info!("Shutting down.");
+
+        // Jobs first, so nothing new gets posted while the last commands finish.
+        if !scheduler.stop(SHUTDOWN_TIMEOUT).await {
+            error!("Scheduled jobs didn't finish in time.");
+        }
+        if !shutdown.drain(SHUTDOWN_TIMEOUT).await {
+            error!("Some commands didn't finish in time.");
+        }
+
+        shard_manager.lock().await.shutdown_all().await;
+    });
+
     if let Err(why) = client.start().await {
         error!("Failed to start client {:?}", why);
     }
+
+    // Checkpoints the WAL so the database file is complete on its own.
+    database.close().await;
+    info!("Stopped.");
 }
@@ -0,0 +1,293 @@
+// Prometheus metrics. Counters and histograms are updated as things happen. Everything the database
+// already knows, like the money supply, is read fresh on each scrape so it survives restarts.
+use std::time::Duration;
+
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+
+use sqlx::SqlitePool;
+
+use crate::error::{Error, InnerError};
+use crate::jobs::{self, scheduler};
+use crate::outbox;
+use crate::scrum::{ScrumReact, ScrumStatus};
+
+fn counter_vec(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let counter = IntCounterVec::new(Opts::new(name, help), labels).unwrap();
+    registry.register(Box::new(counter.clone())).unwrap();
+    counter
+}
+
+fn counter(registry: &Registry, name: &str, help: &str) -> IntCounter {
+    let counter = IntCounter::new(name, help).unwrap();
+    registry.register(Box::new(counter.clone())).unwrap();
+    counter
+}
+
+fn histogram_vec(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> HistogramVec {
+    let histogram = HistogramVec::new(HistogramOpts::new(name, help), labels).unwrap();
+    registry.register(Box::new(histogram.clone())).unwrap();
+    histogram
+}
+
+fn gauge(registry: &Registry, name: &str, help: &str) -> IntGauge {
+    let gauge = IntGauge::new(name, help).unwrap();
+    registry.register(Box::new(gauge.clone())).unwrap();
+    gauge
+}
+
+fn gauge_vec(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntGaugeVec {
+    let gauge = IntGaugeVec::new(Opts::new(name, help), labels).unwrap();
+    registry.register(Box::new(gauge.clone())).unwrap();
+    gauge
+}
+
+struct Metrics {
+    registry: Registry,
+    commands: IntCounterVec,
+    command_seconds: HistogramVec,
+    job_runs: IntCounterVec,
+    job_seconds: HistogramVec,
+    discord_errors: IntCounterVec,
+    scrums_closed: IntCounterVec,
+    scrum_responses: IntCounterVec,
+    transfers: IntCounter,
+    transferred_ugocents: IntCounter,
+    supply_ugocents: IntGauge,
+    users: IntGauge,
+    outbox_pending: IntGauge,
+    gateway_connected: IntGauge,
+    job_last_run: IntGaugeVec,
+    job_last_succeeded: IntGaugeVec,
+}
+
+lazy_static! {
+    static ref METRICS: Metrics = {
+        let registry = Registry::new();
+        Metrics {
+            commands: counter_vec(
+                &registry,
+                "ugo_commands_total",
+                "Slash commands run, by command and outcome.",
+                &["command", "outcome"],
+            ),
+            command_seconds: histogram_vec(
+                &registry,
+                "ugo_command_duration_seconds",
+                "How long slash commands take, by command and outcome.",
+                &["command", "outcome"],
+            ),
+            job_runs: counter_vec(
+                &registry,
+                "ugo_job_runs_total",
+                "Scheduled job runs, by job and outcome.",
+                &["job", "outcome"],
+            ),
+            job_seconds: histogram_vec(
+                &registry,
+                "ugo_job_duration_seconds",
+                "How long scheduled jobs take, by job.",
+                &["job"],
+            ),
+            discord_errors: counter_vec(
+                &registry,
+                "ugo_discord_errors_total",
+                "Failed Discord API calls, by kind.",
+                &["kind"],
+            ),
+            scrums_closed: counter_vec(
+                &registry,
+                "ugo_scrums_closed_total",
+                "Scrums closed, by whether they could happen.",
+                &["status"],
+            ),
+            scrum_responses: counter_vec(
+                &registry,
+                "ugo_scrum_responses_total",
+                "Answers recorded when scrums close, by response.",
+                &["response"],
+            ),
+            transfers: counter(
+                &registry,
+                "ugo_ugocoin_transfers_total",
+                "UGOcoin transfers between different accounts.",
+            ),
+            transferred_ugocents: counter(
+                &registry,
+                "ugo_ugocoin_transferred_ugocents_total",
+                "UGOcoin moved between different accounts, in ugocents.",
+            ),
+            supply_ugocents: gauge(
+                &registry,
+                "ugo_ugocoin_supply_ugocents",
+                "UGOcoin held across all accounts, in ugocents.",
+            ),
+            users: gauge(&registry, "ugo_users", "Registered users."),
+            outbox_pending: gauge(
+                &registry,
+                "ugo_outbox_pending",
+                "Outbox messages not yet delivered.",
+            ),
+            gateway_connected: gauge(
+                &registry,
+                "ugo_gateway_connected",
+                "Whether the Discord gateway is connected.",
+            ),
+            job_last_run: gauge_vec(
+                &registry,
+                "ugo_job_last_run_timestamp_seconds",
+                "When each job last finished.",
+                &["job"],
+            ),
+            job_last_succeeded: gauge_vec(
+                &registry,
+                "ugo_job_last_run_succeeded",
+                "Whether each job's last run succeeded.",
+                &["job"],
+            ),
+            registry,
+        }
+    };
+}
+
+// Rejected means the user asked for something we refused, as opposed to us failing.
+fn outcome(result: &Result<(), Error>) -> &'static str {
+    match result {
+        Ok(()) => "ok",
+        Err(err) if err.error.user_message().is_some() => "rejected",
+        Err(_) => "error",
+    }
+}
+
+pub fn record_command(command: &str, result: &Result<(), Error>, elapsed: Duration) {
+    let labels = [command, outcome(result)];
+    METRICS.commands.with_label_values(&labels).inc();
+    METRICS
+        .command_seconds
+        .with_label_values(&labels)
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn record_job_run(job: &str, result: &Result<(), Error>, elapsed: Duration) {
+    METRICS
+        .job_runs
+        .with_label_values(&[job, outcome(result)])
+        .inc();
+    METRICS
+        .job_seconds
+        .with_label_values(&[job])
+        .observe(elapsed.as_secs_f64());
+}
+
+fn discord_error_kind(err: &serenity::Error) -> String {
+    match err {
+        serenity::Error::Http(http_err) => match http_err.status_code() {
+            Some(status) => format!("http_{}", status.as_u16()),
+            None => String::from("http"),
+        },
+        serenity::Error::Gateway(_) | serenity::Error::Tungstenite(_) => String::from("gateway"),
+        serenity::Error::Model(_) => String::from("model"),
+        serenity::Error::Json(_) | serenity::Error::Decode(..) => String::from("decode"),
+        serenity::Error::Io(_) => String::from("io"),
+        _ => String::from("other"),
+    }
+}
+
+pub fn record_discord_error(err: &serenity::Error) {
+    METRICS
+        .discord_errors
+        .with_label_values(&[&discord_error_kind(err)])
+        .inc();
+}
+
+pub fn record_scrum_closed<'a>(
+    status: ScrumStatus,
+    responses: impl IntoIterator<Item = &'a ScrumReact>,
+) {
+    let status = match status {
+        ScrumStatus::Possible => "possible",
+        ScrumStatus::Impossible => "impossible",
+        ScrumStatus::Unknown => "unknown",
+    };
+    METRICS.scrums_closed.with_label_values(&[status]).inc();
+
+    for response in responses {
+        METRICS
+            .scrum_responses
+            .with_label_values(&[response.as_db_str()])
+            .inc();
+    }
+}
+
+pub fn set_gateway_connected(connected: bool) {
+    METRICS.gateway_connected.set(connected as i64);
+}
+
+// Counters only go up, so catch them up to a total instead of setting them.
+fn catch_up(counter: &IntCounter, total: i64) {
+    let total = total.max(0) as u64;
+    if total > counter.get() {
+        counter.inc_by(total - counter.get());
+    }
+}
+
+async fn refresh_from_db(db: &SqlitePool) -> Result<(), Error> {
+    let supply =
+        sqlx::query!(r#"SELECT COALESCE(SUM(balance), 0) as "supply!: i64" FROM ugocoin_accounts"#)
+            .fetch_one(db)
+            .await?;
+    METRICS.supply_ugocents.set(supply.supply);
+
+    // Minting shows up as the central bank paying itself. It isn't volume.
+    let volume = sqlx::query!(
+        r#"SELECT COUNT(*) as "count!: i64", COALESCE(SUM(amount), 0) as "total!: i64"
+        FROM ugocoin_tx_logs WHERE from_account_id != to_account_id"#
+    )
+    .fetch_one(db)
+    .await?;
+    catch_up(&METRICS.transfers, volume.count);
+    catch_up(&METRICS.transferred_ugocents, volume.total);
+
+    let users = sqlx::query!(r#"SELECT COUNT(*) as "count!: i64" FROM users"#)
+        .fetch_one(db)
+        .await?;
+    METRICS.users.set(users.count);
+
+    let outbox_pending = outbox::get_undelivered(db).await?.len();
+    METRICS.outbox_pending.set(outbox_pending as i64);
+
+    for job in jobs::jobs() {
+        let last_run = match scheduler::get_last_run(db, job.name()).await? {
+            Some(last_run) => last_run,
+            None => continue,
+        };
+        if let Some(finished_at) = last_run.finished_at {
+            METRICS
+                .job_last_run
+                .with_label_values(&[job.name()])
+                .set(finished_at);
+        }
+        if let Some(succeeded) = last_run.succeeded {
+            METRICS
+                .job_last_succeeded
+                .with_label_values(&[job.name()])
+                .set(succeeded as i64);
+        }
+    }
+
+    Ok(())
+}
+
+// Everything, in Prometheus text format.
+pub async fn render(db: &SqlitePool) -> Result<String, Error> {
+    refresh_from_db(db).await?;
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&METRICS.registry.gather(), &mut buffer)
+        .map_err(|err| InnerError::MetricsError(err.to_string()))?;
+
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
@@ -0,0 +1,527 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Duration, Local};
+
+use log::{info, warn};
+use serenity::model::id::{ChannelId, MessageId, UserId};
+
+use sqlx::{Executor, Sqlite, SqlitePool};
+
+use crate::chat::{Chat, SelectMenu};
+use crate::error::{Error, InnerError, WithContext, HTTP_TOO_MANY_REQUESTS};
+use crate::scrum;
+
+// How long a flush gets to deliver a message before another flush may try it.
+const CLAIM_SECONDS: i64 = 60;
+
+// Retries back off exponentially from the base, up to the cap. With these numbers we give up after
+// roughly half a day.
+const BASE_BACKOFF_SECONDS: i64 = 30;
+const MAX_BACKOFF_SECONDS: i64 = 60 * 60;
+pub const MAX_ATTEMPTS: i64 = 18;
+
+// Discord rate limits bursts, so a big backlog drains over several flushes instead of all at once.
+const MAX_DELIVERIES_PER_FLUSH: usize = 20;
+
+// A Discord action owed because of a committed DB change. Writing it in the same transaction as the
+// change means a crash can't lose it; it's delivered afterwards, and retried until it goes through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutboxMessage {
+    Send {
+        channel_id: ChannelId,
+        content: String,
+    },
+    Edit {
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: String,
+    },
+    React {
+        channel_id: ChannelId,
+        message_id: MessageId,
+        emoji: String,
+    },
+    DirectMessage {
+        user_id: UserId,
+        content: String,
+    },
+    Menu {
+        channel_id: ChannelId,
+        content: String,
+        menu: SelectMenu,
+    },
+}
+
+// Bookkeeping that needs the ID of a message once it's sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OnSent {
+    // The message is the scrum notification for this date. Slot polls list their slots.
+    OpenScrum {
+        scrum_date: String,
+        slots: Vec<String>,
+    },
+}
+
+impl OnSent {
+    // Slots follow the date, like "2022-12-21 10:00,14:00".
+    fn as_db_strs(&self) -> (&'static str, String) {
+        match self {
+            OnSent::OpenScrum { scrum_date, slots } if slots.is_empty() => {
+                ("open_scrum", scrum_date.clone())
+            }
+            OnSent::OpenScrum { scrum_date, slots } => {
+                ("open_scrum", format!("{} {}", scrum_date, slots.join(",")))
+            }
+        }
+    }
+
+    fn from_db_strs(on_sent: Option<&str>, arg: Option<&str>) -> Option<OnSent> {
+        match (on_sent, arg) {
+            (Some("open_scrum"), Some(arg)) => {
+                let (scrum_date, slots) = match arg.split_once(' ') {
+                    Some((scrum_date, slots)) => {
+                        (scrum_date, slots.split(',').map(String::from).collect())
+                    }
+                    None => (arg, Vec::new()),
+                };
+                Some(OnSent::OpenScrum {
+                    scrum_date: scrum_date.to_string(),
+                    slots,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl OutboxMessage {
+    fn kind(&self) -> &'static str {
+        match self {
+            OutboxMessage::Send { .. } => "send",
+            OutboxMessage::Edit { .. } => "edit",
+            OutboxMessage::React { .. } => "react",
+            OutboxMessage::DirectMessage { .. } => "dm",
+            OutboxMessage::Menu { .. } => "menu",
+        }
+    }
+
+    // Sends, DMs and menus return the new message's ID.
+    async fn deliver(&self, chat: &dyn Chat) -> Result<Option<MessageId>, Error> {
+        match self {
+            OutboxMessage::Send {
+                channel_id,
+                content,
+            } => Ok(Some(chat.send_message(*channel_id, content).await?)),
+            OutboxMessage::Edit {
+                channel_id,
+                message_id,
+                content,
+            } => {
+                chat.edit_message(*channel_id, *message_id, content).await?;
+                Ok(None)
+            }
+            OutboxMessage::React {
+                channel_id,
+                message_id,
+                emoji,
+            } => {
+                chat.react(*channel_id, *message_id, emoji).await?;
+                Ok(None)
+            }
+            OutboxMessage::DirectMessage { user_id, content } => {
+                Ok(Some(chat.send_direct_message(*user_id, content).await?))
+            }
+            OutboxMessage::Menu {
+                channel_id,
+                content,
+                menu,
+            } => Ok(Some(chat.send_menu(*channel_id, content, menu).await?)),
+        }
+    }
+}
+
+pub struct OutboxEntry {
+    pub id: i64,
+    pub kind: String,
+    pub channel_id: Option<String>,
+    pub user_id: Option<String>,
+    pub message_id: Option<String>,
+    pub content: String,
+    pub menu: Option<String>,
+    pub on_sent: Option<String>,
+    pub on_sent_arg: Option<String>,
+    pub attempts: i64,
+    pub next_attempt_at: i64,
+    pub last_error: Option<String>,
+    pub failed_at: Option<i64>,
+}
+
+fn parse_id(id: Option<&str>) -> Result<u64, Error> {
+    id.unwrap_or_default()
+        .parse()
+        .map_err(|err| InnerError::IdParseError(err).into())
+}
+
+impl OutboxEntry {
+    fn message(&self) -> Result<OutboxMessage, Error> {
+        let channel_id = || parse_id(self.channel_id.as_deref()).map(ChannelId);
+        let message_id = || parse_id(self.message_id.as_deref()).map(MessageId);
+
+        Ok(match self.kind.as_str() {
+            "send" => OutboxMessage::Send {
+                channel_id: channel_id()?,
+                content: self.content.clone(),
+            },
+            "edit" => OutboxMessage::Edit {
+                channel_id: channel_id()?,
+                message_id: message_id()?,
+                content: self.content.clone(),
+            },
+            "react" => OutboxMessage::React {
+                channel_id: channel_id()?,
+                message_id: message_id()?,
+                emoji: self.content.clone(),
+            },
+            "dm" => OutboxMessage::DirectMessage {
+                user_id: UserId(parse_id(self.user_id.as_deref())?),
+                content: self.content.clone(),
+            },
+            "menu" => OutboxMessage::Menu {
+                channel_id: channel_id()?,
+                content: self.content.clone(),
+                menu: serde_json::from_str(self.menu.as_deref().unwrap_or_default())?,
+            },
+            kind => return Err(InnerError::UnknownOutboxKind(kind.to_string()).into()),
+        })
+    }
+
+    // Entries with the same key are delivered in the order they were queued.
+    fn order_key(&self) -> String {
+        match (&self.channel_id, &self.user_id) {
+            (Some(channel_id), _) => channel_id.clone(),
+            (None, Some(user_id)) => format!("dm:{}", user_id),
+            (None, None) => String::new(),
+        }
+    }
+
+    // A short description for admins.
+    pub fn describe(&self) -> String {
+        let channel = self.channel_id.clone().unwrap_or_default();
+        match self.kind.as_str() {
+            "send" => format!("Message in <#{}>", channel),
+            "edit" => format!("Edit in <#{}>", channel),
+            "react" => format!("{} reaction in <#{}>", self.content, channel),
+            "dm" => format!("DM to <@{}>", self.user_id.clone().unwrap_or_default()),
+            "menu" => format!("Menu in <#{}>", channel),
+            kind => format!("Unknown {}", kind),
+        }
+    }
+}
+
+pub async fn enqueue<'a, E: Executor<'a, Database = Sqlite>>(
+    db: E,
+    now: DateTime<Local>,
+    message: &OutboxMessage,
+) -> Result<(), Error> {
+    enqueue_with(db, now, message, None, None).await
+}
+
+// Enqueues a message, unless one with the same dedupe key was ever queued.
+pub async fn enqueue_with<'a, E: Executor<'a, Database = Sqlite>>(
+    db: E,
+    now: DateTime<Local>,
+    message: &OutboxMessage,
+    dedupe_key: Option<&str>,
+    on_sent: Option<&OnSent>,
+) -> Result<(), Error> {
+    let kind = message.kind();
+    let now = now.timestamp();
+    let mut menu = None;
+    let (channel_id, user_id, message_id, content) = match message {
+        OutboxMessage::Send {
+            channel_id,
+            content,
+        } => (Some(channel_id.to_string()), None, None, content),
+        OutboxMessage::Edit {
+            channel_id,
+            message_id,
+            content,
+        } => (
+            Some(channel_id.to_string()),
+            None,
+            Some(message_id.to_string()),
+            content,
+        ),
+        OutboxMessage::React {
+            channel_id,
+            message_id,
+            emoji,
+        } => (
+            Some(channel_id.to_string()),
+            None,
+            Some(message_id.to_string()),
+            emoji,
+        ),
+        OutboxMessage::DirectMessage { user_id, content } => {
+            (None, Some(user_id.to_string()), None, content)
+        }
+        OutboxMessage::Menu {
+            channel_id,
+            content,
+            menu: select_menu,
+        } => {
+            menu = Some(serde_json::to_string(select_menu)?);
+            (Some(channel_id.to_string()), None, None, content)
+        }
+    };
+    let (on_sent, on_sent_arg) = match on_sent.map(OnSent::as_db_strs) {
+        Some((on_sent, arg)) => (Some(on_sent), Some(arg)),
+        None => (None, None),
+    };
+
+    sqlx::query!(
+        "INSERT OR IGNORE INTO outbox
+            (kind, channel_id, user_id, message_id, content, menu, dedupe_key, on_sent,
+            on_sent_arg, created_at, next_attempt_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        kind,
+        channel_id,
+        user_id,
+        message_id,
+        content,
+        menu,
+        dedupe_key,
+        on_sent,
+        on_sent_arg,
+        now,
+        now
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+// Everything not yet delivered, including entries we've given up on, oldest first.
+pub async fn get_undelivered(db: &SqlitePool) -> Result<Vec<OutboxEntry>, Error> {
+    Ok(sqlx::query_as!(
+        OutboxEntry,
+        "SELECT id, kind, channel_id, user_id, message_id, content, menu, on_sent, on_sent_arg,
+            attempts, next_attempt_at, last_error, failed_at
+        FROM outbox
+        WHERE sent_at IS NULL
+        ORDER BY id"
+    )
+    .fetch_all(db)
+    .await?)
+}
+
+// Puts an entry we gave up on back in the queue, to be tried on the next flush.
+pub async fn retry(db: &SqlitePool, id: i64, now: DateTime<Local>) -> Result<(), Error> {
+    let now = now.timestamp();
+    let result = sqlx::query!(
+        "UPDATE outbox SET failed_at = NULL, attempts = 0, next_attempt_at = ?
+        WHERE id = ? AND failed_at IS NOT NULL AND sent_at IS NULL",
+        now,
+        id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(InnerError::OutboxItemNotFound.into());
+    }
+
+    Ok(())
+}
+
+fn backoff(attempts: i64) -> Duration {
+    let exponent = attempts.clamp(0, 16) as u32;
+    let seconds = BASE_BACKOFF_SECONDS.saturating_mul(2_i64.pow(exponent));
+    Duration::seconds(seconds.min(MAX_BACKOFF_SECONDS))
+}
+
+fn discord_status(err: &Error) -> Option<u16> {
+    match &err.error {
+        InnerError::DiscordError(discord_err) => match discord_err.as_ref() {
+            serenity::Error::Http(http_err) => http_err.status_code().map(|status| status.as_u16()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+async fn claim(db: &SqlitePool, id: i64, now: DateTime<Local>) -> Result<bool, Error> {
+    let claimed_until = (now + Duration::seconds(CLAIM_SECONDS)).timestamp();
+    let now = now.timestamp();
+
+    let result = sqlx::query!(
+        "UPDATE outbox SET claimed_until = ?
+        WHERE id = ? AND sent_at IS NULL AND (claimed_until IS NULL OR claimed_until < ?)",
+        claimed_until,
+        id,
+        now
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+async fn mark_sent(
+    db: &SqlitePool,
+    now: DateTime<Local>,
+    entry: &OutboxEntry,
+    sent_message_id: Option<MessageId>,
+) -> Result<(), Error> {
+    let mut db_tx = db.begin().await?;
+
+    let sent_at = now.timestamp();
+    sqlx::query!(
+        "UPDATE outbox SET sent_at = ?, claimed_until = NULL WHERE id = ?",
+        sent_at,
+        entry.id
+    )
+    .execute(&mut db_tx)
+    .await?;
+
+    let on_sent = OnSent::from_db_strs(entry.on_sent.as_deref(), entry.on_sent_arg.as_deref());
+    if let (Some(on_sent), Some(message_id)) = (on_sent, sent_message_id) {
+        match on_sent {
+            OnSent::OpenScrum { scrum_date, slots } => {
+                let channel_id = ChannelId(parse_id(entry.channel_id.as_deref())?);
+                scrum::record_scrum_message(
+                    &mut db_tx,
+                    now,
+                    channel_id,
+                    &scrum_date,
+                    message_id,
+                    &slots,
+                )
+                .await?;
+            }
+        }
+    }
+
+    db_tx.commit().await?;
+
+    Ok(())
+}
+
+// Returns whether we gave up on the entry.
+async fn record_failure(
+    db: &SqlitePool,
+    now: DateTime<Local>,
+    entry: &OutboxEntry,
+    err: &Error,
+) -> Result<bool, Error> {
+    let attempts = entry.attempts + 1;
+    let last_error = err.to_string();
+    let give_up = attempts >= MAX_ATTEMPTS || !err.is_retryable();
+    let failed_at = give_up.then(|| now.timestamp());
+    let next_attempt_at = (now + backoff(attempts)).timestamp();
+
+    sqlx::query!(
+        "UPDATE outbox
+        SET attempts = ?, last_error = ?, failed_at = ?, next_attempt_at = ?, claimed_until = NULL
+        WHERE id = ?",
+        attempts,
+        last_error,
+        failed_at,
+        next_attempt_at,
+        entry.id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(give_up)
+}
+
+// Delivers what's due, oldest first. A failure holds back later entries for the same channel or
+// user, so they never arrive out of order, but doesn't hold up anyone else. Returns an error if we
+// gave up on anything.
+pub async fn flush(db: &SqlitePool, chat: &dyn Chat, now: DateTime<Local>) -> Result<(), Error> {
+    let now_unix = now.timestamp();
+    let mut delivered = 0;
+    let mut gave_up = None;
+
+    // Delivering can queue more, like the reactions on a new scrum, so go again while there's
+    // progress.
+    loop {
+        let pending = sqlx::query_as!(
+            OutboxEntry,
+            "SELECT id, kind, channel_id, user_id, message_id, content, menu, on_sent, on_sent_arg,
+                attempts, next_attempt_at, last_error, failed_at
+            FROM outbox
+            WHERE sent_at IS NULL AND failed_at IS NULL
+            ORDER BY id"
+        )
+        .fetch_all(db)
+        .await
+        .with_context("Fetching pending outbox messages")?;
+
+        let mut blocked: HashSet<String> = HashSet::new();
+        let mut progressed = false;
+
+        for entry in pending {
+            if delivered >= MAX_DELIVERIES_PER_FLUSH {
+                info!("Outbox delivery limit reached. Continuing next flush.");
+                break;
+            }
+
+            let key = entry.order_key();
+            if blocked.contains(&key) || entry.next_attempt_at > now_unix {
+                blocked.insert(key);
+                continue;
+            }
+
+            // Another flush may be delivering this one right now.
+            if !claim(db, entry.id, now)
+                .await
+                .with_context("Claiming outbox message")?
+            {
+                blocked.insert(key);
+                continue;
+            }
+            progressed = true;
+
+            let result = match entry.message() {
+                Ok(message) => message.deliver(chat).await,
+                Err(err) => Err(err),
+            };
+
+            match result {
+                Ok(sent_message_id) => {
+                    mark_sent(db, now, &entry, sent_message_id)
+                        .await
+                        .with_context("Marking outbox message sent")?;
+                    delivered += 1;
+                }
+                Err(err) => {
+                    blocked.insert(key);
+                    warn!("Outbox message {} failed: {}", entry.id, err);
+
+                    if record_failure(db, now, &entry, &err)
+                        .await
+                        .with_context("Recording outbox failure")?
+                    {
+                        gave_up = Some(err);
+                    } else if discord_status(&err) == Some(HTTP_TOO_MANY_REQUESTS) {
+                        // Everything else would get rate limited too.
+                        warn!("Rate limited by Discord. Pausing outbox until the next flush.");
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        if !progressed || delivered >= MAX_DELIVERIES_PER_FLUSH {
+            break;
+        }
+    }
+
+    match gave_up {
+        Some(err) => Err(err).with_context("Delivering outbox message"),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,57 @@
+use std::env;
+
+use serenity::model::guild::Member;
+use serenity::model::id::{RoleId, UserId};
+
+use sqlx::SqlitePool;
+
+use crate::error::Error;
+use crate::user;
+
+// Permission levels are ordered, so anyone with a higher level can do everything a lower one can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    Everyone,
+    Admin,
+}
+
+fn admin_role_id() -> Option<RoleId> {
+    env::var("ADMIN_ROLE_ID")
+        .ok()
+        .and_then(|id| id.parse().ok())
+        .map(RoleId)
+}
+
+pub async fn is_user_admin(db: &SqlitePool, user_id: i64) -> Result<bool, Error> {
+    let result = sqlx::query!("SELECT id FROM admins WHERE user_id = ?", user_id)
+        .fetch_optional(db)
+        .await?;
+
+    Ok(result.is_some())
+}
+
+// Admins are Discord administrators, members of the ADMIN_ROLE_ID role, or users in the admins table.
+pub async fn get_permission(
+    db: &SqlitePool,
+    member: Option<&Member>,
+    user_id: &UserId,
+) -> Result<Permission, Error> {
+    if let Some(member) = member {
+        let is_discord_admin = member
+            .permissions
+            .is_some_and(|permissions| permissions.administrator());
+        let has_admin_role = admin_role_id().is_some_and(|role| member.roles.contains(&role));
+
+        if is_discord_admin || has_admin_role {
+            return Ok(Permission::Admin);
+        }
+    }
+
+    if let Some(user) = user::find_user(db, user_id).await? {
+        if is_user_admin(db, user.id).await? {
+            return Ok(Permission::Admin);
+        }
+    }
+
+    Ok(Permission::Everyone)
+}
@@ -1,19 +1,21 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::env;
 
-use chrono::NaiveDate;
 use chrono::{DateTime, Local, Timelike};
+use chrono::{NaiveDate, NaiveTime};
 
 use log::info;
-use serenity::client::Context;
-use serenity::model::channel::Message;
-use serenity::model::channel::ReactionType;
 use serenity::model::id::ChannelId;
 use serenity::model::id::MessageId;
+use serenity::model::id::UserId;
 
-use sqlx::SqlitePool;
+use sqlx::{Executor, Sqlite, SqlitePool, Transaction};
 
-use crate::error::{Error, InnerError};
-use crate::ugocoin::account::{credit_account, get_user_account, Ugocoin};
+use crate::chat::{Chat, InteractionHandle, InteractionReply, Reply, SelectMenu, SelectOption};
+use crate::error::{Error, InnerError, WithContext};
+use crate::metrics;
+use crate::outbox::{self, OnSent, OutboxMessage};
+use crate::ugocoin::account::{credit_account_in_tx, get_user_account, Ugocoin};
 use crate::user;
 
 pub struct Scrum {
@@ -41,26 +43,26 @@ pub fn date_to_scrum_db_format(date: DateTime<Local>) -> String {
     date.format("%Y-%m-%d").to_string()
 }
 
-pub async fn create_scrum_row(
-    db: &SqlitePool,
-    datetime: DateTime<Local>,
+// Returns false if there's already a scrum for that date.
+pub async fn create_scrum_row<'a, E: Executor<'a, Database = Sqlite>>(
+    db: E,
+    scrum_date: &str,
     message_id: MessageId,
-) -> Result<(), Error> {
-    let date_str = date_to_scrum_db_format(datetime);
+) -> Result<bool, Error> {
     let message_str = message_id.to_string();
 
-    sqlx::query!(
+    let result = sqlx::query!(
         "
-        INSERT INTO scrums (scrum_date, is_open, message_id)
+        INSERT OR IGNORE INTO scrums (scrum_date, is_open, message_id)
         VALUES (?, true, ?)
     ",
-        date_str,
+        scrum_date,
         message_str
     )
     .execute(db)
     .await?;
 
-    Ok(())
+    Ok(result.rows_affected() == 1)
 }
 
 pub async fn get_scrum_from_message(
@@ -95,8 +97,12 @@ pub async fn get_scrum_for_date(
     .await?)
 }
 
+// Scrums go out at 3 AM and force close at 4 PM.
+pub const SCRUM_OPEN_HOUR: u32 = 3;
+pub const SCRUM_CLOSE_HOUR: u32 = 12 + 4;
+
 fn is_past_scrum_notification_time(datetime: DateTime<Local>) -> bool {
-    datetime.hour() >= 3
+    datetime.hour() >= SCRUM_OPEN_HOUR
 }
 
 pub fn should_create_scrum(datetime: DateTime<Local>, today_scrum: Option<&Scrum>) -> bool {
@@ -106,63 +112,238 @@ pub fn should_create_scrum(datetime: DateTime<Local>, today_scrum: Option<&Scrum
 const SCRUM_NOTIFY_STRING: &str = "@everyone
 UGO-BOT SCRUMMONS: React if you are available for scrum!";
 
+const SCRUM_SLOTS_NOTIFY_STRING: &str = "@everyone
+UGO-BOT SCRUMMONS: Pick every time you can make scrum today!";
+
 const SCRUM_ACCEPT_EMOJI: &str = "👍";
 const SCRUM_DECLINE_EMOJI: &str = "👎";
 
+// Picks from the slot menu come back with this ID.
+pub const SCRUM_SLOT_MENU_ID: &str = "scrum_slots";
+// Picking this means none of the slots work, whatever else was picked.
+pub const NO_SLOT_VALUE: &str = "none";
+const SLOT_FORMAT: &str = "%H:%M";
+// Discord menus hold 25 options, and one is "None of these".
+const MAX_SLOTS: usize = 24;
+
+// How a scrum asks people whether they can make it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScrumPoll {
+    // React 👍 or 👎.
+    YesNo,
+    // Pick every one of these times you can make from a menu.
+    Slots(Vec<NaiveTime>),
+}
+
+impl ScrumPoll {
+    // Set SCRUM_SLOTS to comma separated times, like 10:00,14:00,19:30, to poll time slots instead
+    // of asking yes or no.
+    pub fn from_env() -> ScrumPoll {
+        ScrumPoll::parse(&env::var("SCRUM_SLOTS").unwrap_or_default())
+    }
+
+    // Times that don't parse are skipped. No times at all means yes or no.
+    pub fn parse(slots: &str) -> ScrumPoll {
+        let mut slots: Vec<NaiveTime> = slots
+            .split(',')
+            .filter_map(|slot| NaiveTime::parse_from_str(slot.trim(), SLOT_FORMAT).ok())
+            .collect();
+        slots.sort();
+        slots.dedup();
+        slots.truncate(MAX_SLOTS);
+
+        if slots.is_empty() {
+            ScrumPoll::YesNo
+        } else {
+            ScrumPoll::Slots(slots)
+        }
+    }
+}
+
+fn slot_menu(slots: &[String]) -> SelectMenu {
+    let mut options: Vec<SelectOption> = slots
+        .iter()
+        .map(|slot| SelectOption {
+            value: slot.clone(),
+            label: slot.clone(),
+        })
+        .collect();
+    options.push(SelectOption {
+        value: NO_SLOT_VALUE.to_string(),
+        label: "None of these".to_string(),
+    });
+
+    SelectMenu {
+        custom_id: SCRUM_SLOT_MENU_ID.to_string(),
+        placeholder: "Times you can make".to_string(),
+        options,
+    }
+}
+
+// Queues the scrum notification. The scrum itself is recorded once the message is sent and we know
+// its ID, so a failed send is retried rather than leaving a scrum nobody can answer.
 pub async fn notify_scrum(
     db: &SqlitePool,
     datetime: DateTime<Local>,
-    ctx: &Context,
+    chat: &dyn Chat,
     channel_id: ChannelId,
+    poll: &ScrumPoll,
 ) -> Result<(), Error> {
-    let message = channel_id
-        .send_message(&ctx.http, |message| message.content(SCRUM_NOTIFY_STRING))
-        .await?;
+    let scrum_date = date_to_scrum_db_format(datetime);
+    let dedupe_key = format!("scrum_notify:{}", scrum_date);
+
+    let (message, slots) = match poll {
+        ScrumPoll::YesNo => (
+            OutboxMessage::Send {
+                channel_id,
+                content: SCRUM_NOTIFY_STRING.to_string(),
+            },
+            Vec::new(),
+        ),
+        ScrumPoll::Slots(times) => {
+            let slots: Vec<String> = times
+                .iter()
+                .map(|time| time.format(SLOT_FORMAT).to_string())
+                .collect();
+            (
+                OutboxMessage::Menu {
+                    channel_id,
+                    content: SCRUM_SLOTS_NOTIFY_STRING.to_string(),
+                    menu: slot_menu(&slots),
+                },
+                slots,
+            )
+        }
+    };
 
-    message
-        .react(
-            &ctx.http,
-            ReactionType::Unicode(SCRUM_ACCEPT_EMOJI.to_string()),
-        )
-        .await?;
-    message
-        .react(
-            &ctx.http,
-            ReactionType::Unicode(SCRUM_DECLINE_EMOJI.to_string()),
-        )
-        .await?;
+    outbox::enqueue_with(
+        db,
+        datetime,
+        &message,
+        Some(&dedupe_key),
+        Some(&OnSent::OpenScrum { scrum_date, slots }),
+    )
+    .await?;
+
+    outbox::flush(db, chat, datetime)
+        .await
+        .with_context("Sending scrum notification")
+}
+
+// Called by the outbox once the scrum notification is sent.
+pub async fn record_scrum_message(
+    db_tx: &mut Transaction<'_, Sqlite>,
+    now: DateTime<Local>,
+    channel_id: ChannelId,
+    scrum_date: &str,
+    message_id: MessageId,
+    slots: &[String],
+) -> Result<(), Error> {
+    // A notification sent twice, say after a crash, only counts the first time.
+    if !create_scrum_row(&mut *db_tx, scrum_date, message_id).await? {
+        return Ok(());
+    }
 
-    let result = create_scrum_row(db, datetime, message.id).await;
+    // Slot polls are answered from the menu, so they don't get reactions.
+    if !slots.is_empty() {
+        for slot in slots {
+            sqlx::query!(
+                "INSERT INTO scrum_slots (scrum_id, slot)
+                SELECT id, ? FROM scrums WHERE scrum_date = ?",
+                slot,
+                scrum_date
+            )
+            .execute(&mut *db_tx)
+            .await?;
+        }
 
-    // Roll back the message on databse failure, so we can re-try next time this job runs
-    if let Err(err) = result {
-        // If the delete fails, just throw up our hands and give up
-        message.delete(&ctx.http).await?;
-        return Err(err);
+        return Ok(());
+    }
+
+    for emoji in [SCRUM_ACCEPT_EMOJI, SCRUM_DECLINE_EMOJI] {
+        outbox::enqueue(
+            &mut *db_tx,
+            now,
+            &OutboxMessage::React {
+                channel_id,
+                message_id,
+                emoji: emoji.to_string(),
+            },
+        )
+        .await?;
     }
 
     Ok(())
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ScrumReact {
     Available,
     Unavailable,
     Unknown,
 }
 
+impl ScrumReact {
+    pub fn as_db_str(&self) -> &'static str {
+        match self {
+            ScrumReact::Available => "available",
+            ScrumReact::Unavailable => "unavailable",
+            ScrumReact::Unknown => "unknown",
+        }
+    }
+
+    pub fn from_db_str(response: &str) -> ScrumReact {
+        match response {
+            "available" => ScrumReact::Available,
+            "unavailable" => ScrumReact::Unavailable,
+            _ => ScrumReact::Unknown,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ParsedScrumReacts {
     pub availability: HashMap<user::User, ScrumReact>,
     pub num_available: u8,
     pub num_unavailable: u8,
     pub num_unknown: u8,
+    // For slot polls, the slot these answers are for.
+    pub slot: Option<String>,
+}
+
+impl ParsedScrumReacts {
+    fn new(
+        availability: HashMap<user::User, ScrumReact>,
+        slot: Option<String>,
+    ) -> ParsedScrumReacts {
+        let num_available = availability
+            .values()
+            .filter(|v| matches!(v, ScrumReact::Available))
+            .count();
+
+        let num_unavailable = availability
+            .values()
+            .filter(|v| matches!(v, ScrumReact::Unavailable))
+            .count();
+
+        let num_unknown = availability.len() - num_available - num_unavailable;
+
+        // If we have more than 255 users in each category, I guess I'll change this.
+        ParsedScrumReacts {
+            availability,
+            num_available: num_available.try_into().unwrap(),
+            num_unavailable: num_unavailable.try_into().unwrap(),
+            num_unknown: num_unknown.try_into().unwrap(),
+            slot,
+        }
+    }
 }
 
 pub async fn parse_scrum_reactions(
     db: &SqlitePool,
-    ctx: &Context,
-    message: &Message,
+    chat: &dyn Chat,
+    channel_id: ChannelId,
+    message_id: MessageId,
 ) -> Result<ParsedScrumReacts, Error> {
     let mut user_availability: HashMap<user::User, ScrumReact> = HashMap::new();
 
@@ -173,16 +354,11 @@ pub async fn parse_scrum_reactions(
 
     // It's technically inefficient to refetch all the users that react, but we're going to have like four total,
     // so whatever.
-    for unavail_discord_user in message
-        .reaction_users(
-            &ctx.http,
-            ReactionType::Unicode(SCRUM_DECLINE_EMOJI.to_string()),
-            None,
-            None,
-        )
+    for unavail_discord_id in chat
+        .reactors(channel_id, message_id, SCRUM_DECLINE_EMOJI)
         .await?
     {
-        let unavail_user = match user::get_user(db, &unavail_discord_user.id).await {
+        let unavail_user = match user::get_user(db, &unavail_discord_id).await {
             Ok(user) => Ok(user),
             Err(Error {
                 error: InnerError::UserNotFound,
@@ -193,16 +369,11 @@ pub async fn parse_scrum_reactions(
         user_availability.insert(unavail_user, ScrumReact::Unavailable);
     }
 
-    for avail_discord_user in message
-        .reaction_users(
-            &ctx.http,
-            ReactionType::Unicode(SCRUM_ACCEPT_EMOJI.to_string()),
-            None,
-            None,
-        )
+    for avail_discord_id in chat
+        .reactors(channel_id, message_id, SCRUM_ACCEPT_EMOJI)
         .await?
     {
-        let avail_user = match user::get_user(db, &avail_discord_user.id).await {
+        let avail_user = match user::get_user(db, &avail_discord_id).await {
             Ok(user) => Ok(user),
             Err(Error {
                 error: InnerError::UserNotFound,
@@ -213,30 +384,148 @@ pub async fn parse_scrum_reactions(
         user_availability.insert(avail_user, ScrumReact::Available);
     }
 
-    let num_available = user_availability
-        .values()
-        .filter(|v| matches!(v, ScrumReact::Available))
-        .count();
+    Ok(ParsedScrumReacts::new(user_availability, None))
+}
+
+// A slot poll's candidate times, earliest first. Empty for yes or no scrums.
+pub async fn get_scrum_slots(db: &SqlitePool, scrum_id: i64) -> Result<Vec<String>, Error> {
+    let rows = sqlx::query!(
+        "SELECT slot FROM scrum_slots WHERE scrum_id = ? ORDER BY slot",
+        scrum_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| row.slot).collect())
+}
 
-    let num_unavailable = user_availability
-        .values()
-        .filter(|v| matches!(v, ScrumReact::Unavailable))
-        .count();
+// Replaces someone's answer to a slot poll. No slots means they can't make any.
+async fn record_slot_answer(
+    db: &SqlitePool,
+    now: DateTime<Local>,
+    scrum: &Scrum,
+    user: &user::User,
+    slots: &[String],
+) -> Result<(), Error> {
+    let mut db_tx = db.begin().await?;
 
-    let num_unknown = user_availability.len() - num_available - num_unavailable;
+    let answered_at = now.timestamp();
+    sqlx::query!(
+        "INSERT INTO scrum_slot_answers (scrum_id, user_id, answered_at) VALUES (?, ?, ?)
+        ON CONFLICT (scrum_id, user_id) DO UPDATE SET answered_at = excluded.answered_at",
+        scrum.id,
+        user.id,
+        answered_at
+    )
+    .execute(&mut db_tx)
+    .await?;
 
-    // If we have more than 255 users in each category, I guess I'll change this.
-    Ok(ParsedScrumReacts {
-        availability: user_availability,
-        num_available: num_available.try_into().unwrap(),
-        num_unavailable: num_unavailable.try_into().unwrap(),
-        num_unknown: num_unknown.try_into().unwrap(),
-    })
+    sqlx::query!(
+        "DELETE FROM scrum_slot_picks WHERE scrum_id = ? AND user_id = ?",
+        scrum.id,
+        user.id
+    )
+    .execute(&mut db_tx)
+    .await?;
+
+    for slot in slots {
+        sqlx::query!(
+            "INSERT INTO scrum_slot_picks (scrum_id, user_id, slot) VALUES (?, ?, ?)",
+            scrum.id,
+            user.id,
+            slot
+        )
+        .execute(&mut db_tx)
+        .await?;
+    }
+
+    db_tx.commit().await?;
+
+    Ok(())
+}
+
+// Everyone's answers for each slot, in slot order. Anyone who answered but didn't pick a slot is
+// unavailable for it.
+pub async fn parse_slot_answers(
+    db: &SqlitePool,
+    scrum_id: i64,
+    slots: Vec<String>,
+) -> Result<Vec<ParsedScrumReacts>, Error> {
+    let all_users = user::get_all_users(db).await?;
+
+    let answered: HashSet<i64> = sqlx::query!(
+        "SELECT user_id FROM scrum_slot_answers WHERE scrum_id = ?",
+        scrum_id
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|row| row.user_id)
+    .collect();
+
+    let picks: HashSet<(i64, String)> = sqlx::query!(
+        "SELECT user_id, slot FROM scrum_slot_picks WHERE scrum_id = ?",
+        scrum_id
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|row| (row.user_id, row.slot))
+    .collect();
+
+    Ok(slots
+        .into_iter()
+        .map(|slot| {
+            let availability = all_users
+                .iter()
+                .map(|u| {
+                    let react = if picks.contains(&(u.id, slot.clone())) {
+                        ScrumReact::Available
+                    } else if answered.contains(&u.id) {
+                        ScrumReact::Unavailable
+                    } else {
+                        ScrumReact::Unknown
+                    };
+                    (u.clone(), react)
+                })
+                .collect();
+            ParsedScrumReacts::new(availability, Some(slot))
+        })
+        .collect())
+}
+
+// The slot most people can make. Ties go to the earliest, which leaves the most room if it runs
+// late.
+pub fn choose_slot(tallies: Vec<ParsedScrumReacts>) -> Option<ParsedScrumReacts> {
+    let mut best: Option<ParsedScrumReacts> = None;
+    for tally in tallies {
+        match &best {
+            Some(current) if current.num_available >= tally.num_available => {}
+            _ => best = Some(tally),
+        }
+    }
+
+    best
+}
+
+// Everyone's answers to a scrum. For slot polls, these are the answers for the slot we'd pick.
+pub async fn get_scrum_answers(
+    db: &SqlitePool,
+    chat: &dyn Chat,
+    channel_id: ChannelId,
+    scrum: &Scrum,
+) -> Result<ParsedScrumReacts, Error> {
+    let slots = get_scrum_slots(db, scrum.id).await?;
+    if slots.is_empty() {
+        return parse_scrum_reactions(db, chat, channel_id, scrum.message_id()?).await;
+    }
+
+    let tallies = parse_slot_answers(db, scrum.id, slots).await?;
+    choose_slot(tallies).ok_or_else(|| InnerError::ScrumHasNoSlots(scrum.scrum_date.clone()).into())
 }
 
 fn is_past_scrum_close_time(datetime: DateTime<Local>) -> bool {
-    // Auto close scrums after 4 PM
-    datetime.hour() >= 12 + 4
+    datetime.hour() >= SCRUM_CLOSE_HOUR
 }
 
 pub fn should_force_close_scrum(
@@ -245,7 +534,7 @@ pub fn should_force_close_scrum(
 ) -> Option<&Scrum> {
     today_scrum.filter(|today_scrum| today_scrum.is_open && is_past_scrum_close_time(datetime))
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum ScrumStatus {
     Possible,
     Impossible,
@@ -257,10 +546,15 @@ fn format_scrum_close_notif(reactions: &ParsedScrumReacts, scrum_status: ScrumSt
         ScrumStatus::Possible => "SCRUM POSSIBLE",
         _ => "SCRUM FAILED",
     };
+    let at_slot = match &reactions.slot {
+        Some(slot) => format!(" at {}", slot),
+        None => String::new(),
+    };
 
     let mut close_message: String = format!(
-        "@everyone {}: {}/{} available for scrum.\n\nNot available:\n",
+        "@everyone {}{}: {}/{} available for scrum.\n\nNot available:\n",
         header_msg,
+        at_slot,
         reactions.num_available,
         reactions.availability.len()
     );
@@ -291,34 +585,47 @@ fn calculate_scrum_reward(streak: i64) -> Ugocoin {
 
 const SCRUM_CLOSED_MESSAGE: &str = "Voting is closed for this scrum.";
 
+// Closing is one transaction: the scrum's state, attendance, streaks and rewards all land together
+// or not at all. The Discord side goes through the outbox, so it's retried if Discord is down.
 pub async fn close_scrum(
     db: &SqlitePool,
-    ctx: &Context,
+    chat: &dyn Chat,
+    now: DateTime<Local>,
     scrum: &Scrum,
     reactions: &ParsedScrumReacts,
     channel_id: ChannelId,
     scrum_status: ScrumStatus,
 ) -> Result<(), Error> {
-    sqlx::query!("UPDATE scrums SET is_open = false WHERE id = ?", scrum.id)
-        .execute(db)
-        .await?;
+    let mut db_tx = db.begin().await?;
 
-    let mut message = channel_id.message(&ctx.http, scrum.message_id()?).await?;
-
-    message
-        .edit(&ctx.http, |edited| edited.content(SCRUM_CLOSED_MESSAGE))
-        .await?;
+    // Only one close gets to flip the scrum shut. A racing close, or a retry after one that
+    // already committed, stops here instead of paying out twice.
+    let closed = sqlx::query!(
+        "UPDATE scrums SET is_open = false WHERE id = ? AND is_open",
+        scrum.id
+    )
+    .execute(&mut db_tx)
+    .await?;
 
-    let msg = format_scrum_close_notif(reactions, scrum_status);
+    if closed.rows_affected() == 0 {
+        info!("Scrum {} is already closed.", scrum.scrum_date);
+        return Ok(());
+    }
 
-    channel_id
-        .send_message(&ctx.http, |message| message.content(msg))
+    for (user, avail) in &reactions.availability {
+        let response = avail.as_db_str();
+        sqlx::query!(
+            "INSERT INTO scrum_attendance (scrum_id, user_id, response) VALUES (?, ?, ?)",
+            scrum.id,
+            user.id,
+            response
+        )
+        .execute(&mut db_tx)
         .await?;
 
-    for (user, avail) in &reactions.availability {
         match avail {
             ScrumReact::Available | ScrumReact::Unavailable => {
-                user::increment_streak(db, user.id).await?;
+                user::increment_streak(&mut db_tx, user.id).await?;
                 info!(
                     "New streak for {} is {}",
                     user.display_name,
@@ -328,16 +635,365 @@ pub async fn close_scrum(
                 let reward = calculate_scrum_reward(user.streak + 1);
                 info!("Crediting scrum reward of {}", reward);
 
-                let user_account = get_user_account(db, user).await?;
+                let reward_ugocents = reward.as_ugocents();
+                sqlx::query!(
+                    "INSERT INTO scrum_rewards (scrum_id, user_id, amount) VALUES (?, ?, ?)",
+                    scrum.id,
+                    user.id,
+                    reward_ugocents
+                )
+                .execute(&mut db_tx)
+                .await?;
+
+                let user_account = get_user_account(&mut db_tx, user).await?;
                 let memo = format!("Scrum reward for {}", scrum.date()?.format("%Y-%m-%d"));
 
-                credit_account(db, &user_account, reward, &memo).await?;
+                credit_account_in_tx(&mut db_tx, now, &user_account, reward, &memo).await?;
             }
             ScrumReact::Unknown => {
-                user::clear_streak(db, user.id).await?;
+                user::clear_streak(&mut db_tx, user.id).await?;
             }
         }
     }
 
+    outbox::enqueue(
+        &mut db_tx,
+        now,
+        &OutboxMessage::Edit {
+            channel_id,
+            message_id: scrum.message_id()?,
+            content: SCRUM_CLOSED_MESSAGE.to_string(),
+        },
+    )
+    .await?;
+
+    outbox::enqueue(
+        &mut db_tx,
+        now,
+        &OutboxMessage::Send {
+            channel_id,
+            content: format_scrum_close_notif(reactions, scrum_status),
+        },
+    )
+    .await?;
+
+    db_tx.commit().await?;
+    metrics::record_scrum_closed(scrum_status, reactions.availability.values());
+
+    // Send right away if we can. Anything that fails stays in the outbox for the outbox job.
+    outbox::flush(db, chat, now)
+        .await
+        .with_context("Sending scrum close messages")
+}
+
+// Opens today's scrum once it's time. Does nothing if it's already out.
+pub async fn open_scrum(
+    db: &SqlitePool,
+    chat: &dyn Chat,
+    now: DateTime<Local>,
+    channel_id: ChannelId,
+    poll: &ScrumPoll,
+) -> Result<(), Error> {
+    let today_scrum = get_scrum_for_date(db, now)
+        .await
+        .with_context("Getting today's scrum")?;
+
+    if should_create_scrum(now, today_scrum.as_ref()) {
+        info!("Creating new scrum.");
+        notify_scrum(db, now, chat, channel_id, poll)
+            .await
+            .with_context("Notifying scrum")?;
+    }
+
+    Ok(())
+}
+
+// Force closes the scrum for `day` if nobody finished voting by the end of it. `day` is separate
+// from `now` so a close that was missed while the bot was down still closes the right scrum.
+pub async fn force_close_scrum(
+    db: &SqlitePool,
+    chat: &dyn Chat,
+    now: DateTime<Local>,
+    day: DateTime<Local>,
+    channel_id: ChannelId,
+) -> Result<(), Error> {
+    let day_scrum = get_scrum_for_date(db, day)
+        .await
+        .with_context("Getting scrum to close")?;
+
+    if let Some(to_close) = should_force_close_scrum(day, day_scrum.as_ref()) {
+        info!("Force closing scrum.");
+
+        let reactions = get_scrum_answers(db, chat, channel_id, to_close)
+            .await
+            .with_context("Getting scrum answers")?;
+
+        info!(
+            "Reactions parsed. {} available. {} unavailable. {} unknown.",
+            reactions.num_available, reactions.num_unavailable, reactions.num_unknown
+        );
+
+        let status = scrum_status(&reactions);
+        info!("Scrum status {:?}", status);
+
+        close_scrum(db, chat, now, to_close, &reactions, channel_id, status)
+            .await
+            .with_context(format!("Force closing scrum {}", to_close.scrum_date))?;
+    }
+
+    Ok(())
+}
+
+// Opens and force closes today's scrum as needed. Safe to run as often as we like.
+pub async fn poll_scrum(
+    db: &SqlitePool,
+    chat: &dyn Chat,
+    now: DateTime<Local>,
+    channel_id: ChannelId,
+    poll: &ScrumPoll,
+) -> Result<(), Error> {
+    open_scrum(db, chat, now, channel_id, poll).await?;
+    force_close_scrum(db, chat, now, now, channel_id).await
+}
+
+// Called whenever a reaction is added to or removed from a message. Closes today's scrum early
+// once everyone has answered.
+pub async fn on_scrum_react(
+    db: &SqlitePool,
+    chat: &dyn Chat,
+    now: DateTime<Local>,
+    channel_id: ChannelId,
+    message_id: MessageId,
+) -> Result<(), Error> {
+    let scrum = match get_scrum_from_message(db, message_id)
+        .await
+        .with_context("Fetching scrum from message")?
+    {
+        Some(scrum) => scrum,
+        None => return Ok(()),
+    };
+    info!("Reaction changed for scrum {}", scrum.scrum_date);
+
+    // If this is a closed scrum, ignore it.
+    if !scrum.is_open {
+        info!("Scrum closed. Ignoring.");
+        return Ok(());
+    }
+
+    // If this isn't today's scrum, ignore it.
+    if scrum.date()? != now.date_naive() {
+        info!("Scrum for previous date. Ignoring.");
+        return Ok(());
+    }
+
+    // Slot polls are answered from the menu.
+    if !get_scrum_slots(db, scrum.id).await?.is_empty() {
+        return Ok(());
+    }
+
+    let reactions = parse_scrum_reactions(db, chat, channel_id, message_id)
+        .await
+        .with_context("Parsing scrum reactions")?;
+
+    close_if_everyone_answered(db, chat, now, &scrum, &reactions, channel_id).await
+}
+
+async fn close_if_everyone_answered(
+    db: &SqlitePool,
+    chat: &dyn Chat,
+    now: DateTime<Local>,
+    scrum: &Scrum,
+    reactions: &ParsedScrumReacts,
+    channel_id: ChannelId,
+) -> Result<(), Error> {
+    info!(
+        "Reactions parsed. {} available. {} unavailable. {} unknown.",
+        reactions.num_available, reactions.num_unavailable, reactions.num_unknown
+    );
+
+    // Only close on react if all the votes are in
+    if reactions.num_unknown == 0 {
+        info!("Closing scrum.");
+        let status = scrum_status(reactions);
+        info!("Scrum status: {:?}", status);
+        close_scrum(db, chat, now, scrum, reactions, channel_id, status)
+            .await
+            .with_context(format!("Closing scrum {}", scrum.scrum_date))?;
+    }
+
     Ok(())
 }
+
+async fn reply_to_pick(
+    chat: &dyn Chat,
+    interaction: &InteractionHandle,
+    content: String,
+) -> Result<(), Error> {
+    chat.respond_to_interaction(
+        interaction,
+        InteractionReply::Message(Reply {
+            content: Some(content),
+            ephemeral: true,
+            file: None,
+        }),
+    )
+    .await
+}
+
+// Someone picking from a scrum's slot menu.
+pub struct SlotPick {
+    pub interaction: InteractionHandle,
+    pub channel_id: ChannelId,
+    pub message_id: MessageId,
+    pub picker_id: UserId,
+    pub values: Vec<String>,
+}
+
+// Called when someone picks from a scrum's slot menu. Closes the scrum early once everyone has
+// answered, for whichever slot most people can make.
+pub async fn on_slot_pick(
+    db: &SqlitePool,
+    chat: &dyn Chat,
+    now: DateTime<Local>,
+    pick: &SlotPick,
+) -> Result<(), Error> {
+    let interaction = &pick.interaction;
+    let scrum = match get_scrum_from_message(db, pick.message_id)
+        .await
+        .with_context("Fetching scrum from message")?
+    {
+        Some(scrum) if scrum.is_open && scrum.date()? == now.date_naive() => scrum,
+        _ => return reply_to_pick(chat, interaction, SCRUM_CLOSED_MESSAGE.to_string()).await,
+    };
+
+    let picker = match user::find_user(db, &pick.picker_id)
+        .await
+        .with_context("Fetching picking user")?
+    {
+        Some(picker) => picker,
+        None => {
+            let message = InnerError::UserNotFound.user_message().unwrap_or_default();
+            return reply_to_pick(chat, interaction, message).await;
+        }
+    };
+
+    let scrum_slots = get_scrum_slots(db, scrum.id).await?;
+    let picked: Vec<String> = if pick.values.iter().any(|value| value == NO_SLOT_VALUE) {
+        Vec::new()
+    } else {
+        scrum_slots
+            .iter()
+            .filter(|slot| pick.values.contains(slot))
+            .cloned()
+            .collect()
+    };
+
+    record_slot_answer(db, now, &scrum, &picker, &picked)
+        .await
+        .with_context(format!(
+            "Recording {}'s slots for scrum {}",
+            picker.display_name, scrum.scrum_date
+        ))?;
+    info!(
+        "{} can make {:?} for scrum {}",
+        picker.display_name, picked, scrum.scrum_date
+    );
+
+    // Interactions have to be answered within a few seconds, so do that before closing.
+    let confirmation = if picked.is_empty() {
+        "Got it. You can't make any of them.".to_string()
+    } else {
+        format!("Got it. You can make {}.", picked.join(", "))
+    };
+    reply_to_pick(chat, interaction, confirmation).await?;
+
+    let tallies = parse_slot_answers(db, scrum.id, scrum_slots)
+        .await
+        .with_context("Parsing slot answers")?;
+    let reactions = choose_slot(tallies)
+        .ok_or_else(|| InnerError::ScrumHasNoSlots(scrum.scrum_date.clone()))?;
+
+    close_if_everyone_answered(db, chat, now, &scrum, &reactions, pick.channel_id).await
+}
+
+pub struct Attendance {
+    pub scrum_date: NaiveDate,
+    pub user_id: i64,
+    pub response: ScrumReact,
+}
+
+pub async fn get_attendance_since(
+    db: &SqlitePool,
+    since: NaiveDate,
+) -> Result<Vec<Attendance>, Error> {
+    let since_str = since.format("%Y-%m-%d").to_string();
+
+    let rows = sqlx::query!(
+        "SELECT scrums.scrum_date, scrum_attendance.user_id, scrum_attendance.response
+        FROM scrum_attendance
+        INNER JOIN scrums ON scrums.id = scrum_attendance.scrum_id
+        WHERE scrums.scrum_date >= ?
+        ORDER BY scrums.scrum_date",
+        since_str
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut attendance = Vec::new();
+    for row in rows {
+        attendance.push(Attendance {
+            scrum_date: NaiveDate::parse_from_str(&row.scrum_date, "%Y-%m-%d")?,
+            user_id: row.user_id,
+            response: ScrumReact::from_db_str(&row.response),
+        });
+    }
+
+    Ok(attendance)
+}
+
+pub struct ScrumSummary {
+    pub scrum: Scrum,
+    pub available: i64,
+    pub unavailable: i64,
+    pub unknown: i64,
+}
+
+// The most recent scrums first, with how everyone answered. Open scrums have no attendance yet.
+pub async fn get_recent_scrums(
+    db: &SqlitePool,
+    limit: i64,
+    open_only: bool,
+) -> Result<Vec<ScrumSummary>, Error> {
+    let rows = sqlx::query!(
+        r#"SELECT scrums.id as "id!", scrums.is_open as "is_open!", scrums.scrum_date as "scrum_date!",
+            scrums.message_id as "message_id!",
+            COUNT(CASE WHEN scrum_attendance.response = 'available' THEN 1 END) as "available!: i64",
+            COUNT(CASE WHEN scrum_attendance.response = 'unavailable' THEN 1 END) as "unavailable!: i64",
+            COUNT(CASE WHEN scrum_attendance.response = 'unknown' THEN 1 END) as "unknown!: i64"
+        FROM scrums
+        LEFT JOIN scrum_attendance ON scrum_attendance.scrum_id = scrums.id
+        WHERE scrums.is_open OR NOT ?
+        GROUP BY scrums.id
+        ORDER BY scrums.scrum_date DESC
+        LIMIT ?"#,
+        open_only,
+        limit
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| ScrumSummary {
+            scrum: Scrum {
+                id: row.id,
+                is_open: row.is_open,
+                scrum_date: row.scrum_date,
+                message_id: row.message_id,
+            },
+            available: row.available,
+            unavailable: row.unavailable,
+            unknown: row.unknown,
+        })
+        .collect())
+}
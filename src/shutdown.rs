@@ -0,0 +1,87 @@
+// Keeps track of event handlers that are still running so we can let them finish before exiting.
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+
+#[derive(Default)]
+pub struct Shutdown {
+    stopping: AtomicBool,
+    in_flight: AtomicUsize,
+    idle: Notify,
+}
+
+// Held while handling an event. Dropping it marks the event as done.
+pub struct InFlight<'a> {
+    shutdown: &'a Shutdown,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if self.shutdown.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shutdown.idle.notify_waiters();
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    // Marks an event as being handled. Returns None once we're shutting down, so nothing new
+    // starts.
+    pub fn enter(&self) -> Option<InFlight<'_>> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let guard = InFlight { shutdown: self };
+        if self.is_stopping() {
+            return None;
+        }
+
+        Some(guard)
+    }
+
+    // Stops new events and waits for the ones already running. Returns false if they didn't all
+    // finish in time.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.stopping.store(true, Ordering::SeqCst);
+
+        let finished = async {
+            loop {
+                // Register before checking, so a handler finishing in between still wakes us.
+                let idle = self.idle.notified();
+                if self.in_flight.load(Ordering::SeqCst) == 0 {
+                    return;
+                }
+                idle.await;
+            }
+        };
+
+        tokio::time::timeout(timeout, finished).await.is_ok()
+    }
+}
+
+// Resolves on Ctrl-C, or SIGTERM from whatever is supervising the bot.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl-C");
+    }
+}
@@ -5,22 +5,24 @@ use crate::{
     user::User,
 };
 
-use sqlx::SqlitePool;
+use chrono::{DateTime, Local};
+
+use sqlx::{Executor, Sqlite, SqlitePool, Transaction};
 
 use thousands::Separable;
 
 use super::tx;
 
 // Ugocoins are represented as a fixed-point number of ugocents.
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub struct Ugocoin(i64);
 
 impl Ugocoin {
-    pub fn from_ugocents(cents: i64) -> Ugocoin {
+    pub const fn from_ugocents(cents: i64) -> Ugocoin {
         Ugocoin(cents)
     }
 
-    pub fn from_ugocoin(coins: i64) -> Ugocoin {
+    pub const fn from_ugocoin(coins: i64) -> Ugocoin {
         Ugocoin(coins * 100)
     }
 
@@ -50,10 +52,20 @@ pub struct UgocoinAccount {
     pub balance: Ugocoin,
 }
 
-pub async fn get_user_account(db: &SqlitePool, user: &User) -> Result<UgocoinAccount, Error> {
+pub async fn get_user_account<'a, E: Executor<'a, Database = Sqlite>>(
+    db: E,
+    user: &User,
+) -> Result<UgocoinAccount, Error> {
+    get_account_by_user_id(db, user.id).await
+}
+
+pub async fn get_account_by_user_id<'a, E: Executor<'a, Database = Sqlite>>(
+    db: E,
+    user_id: i64,
+) -> Result<UgocoinAccount, Error> {
     let result = sqlx::query!(
         "SELECT id, user_id, balance from ugocoin_accounts WHERE user_id = ?",
-        user.id
+        user_id
     )
     .fetch_one(db)
     .await?;
@@ -65,7 +77,9 @@ pub async fn get_user_account(db: &SqlitePool, user: &User) -> Result<UgocoinAcc
     })
 }
 
-pub async fn get_central_bank_account(db: &SqlitePool) -> Result<UgocoinAccount, Error> {
+pub async fn get_central_bank_account<'a, E: Executor<'a, Database = Sqlite>>(
+    db: E,
+) -> Result<UgocoinAccount, Error> {
     // The central bank account is the account with no user ID associated
     let result =
         sqlx::query!("SELECT id, user_id, balance from ugocoin_accounts WHERE user_id IS NULL",)
@@ -81,6 +95,26 @@ pub async fn get_central_bank_account(db: &SqlitePool) -> Result<UgocoinAccount,
 
 pub async fn transfer(
     db: &SqlitePool,
+    now: DateTime<Local>,
+    from: &UgocoinAccount,
+    to: &UgocoinAccount,
+    amount: Ugocoin,
+    memo: &String,
+) -> Result<(), Error> {
+    // Start a transaction since we need to debit/credit accounts and add a transaction log
+    let mut db_tx = db.begin().await?;
+
+    transfer_in_tx(&mut db_tx, now, from, to, amount, memo).await?;
+
+    db_tx.commit().await?;
+
+    Ok(())
+}
+
+// Same as transfer, but runs inside an existing transaction so callers can bundle other writes with it.
+pub async fn transfer_in_tx(
+    db_tx: &mut Transaction<'_, Sqlite>,
+    now: DateTime<Local>,
     from: &UgocoinAccount,
     to: &UgocoinAccount,
     amount: Ugocoin,
@@ -90,37 +124,34 @@ pub async fn transfer(
         return Err(InnerError::NegativeTransfer.into());
     }
 
-    if from.balance < amount {
-        return Err(InnerError::InsufficientFunds.into());
-    }
-
     let amount_ugocents = amount.as_ugocents();
 
-    // Start a transaction since we need to debit/credit accounts and add a transaction log
-    let mut db_tx = db.begin().await?;
-
-    // Debit the from account
-    sqlx::query!(
-        "UPDATE ugocoin_accounts SET balance = balance - ? WHERE id = ?",
+    // Debit the from account, as long as it still has the money. The balance the caller read can
+    // be stale by now, so this is the check that counts.
+    let debited = sqlx::query!(
+        "UPDATE ugocoin_accounts SET balance = balance - ? WHERE id = ? AND balance >= ?",
         amount_ugocents,
-        from.id
+        from.id,
+        amount_ugocents
     )
-    .execute(&mut db_tx)
+    .execute(&mut *db_tx)
     .await?;
 
+    if debited.rows_affected() == 0 {
+        return Err(InnerError::InsufficientFunds.into());
+    }
+
     // Credit the to account
     sqlx::query!(
         "UPDATE ugocoin_accounts SET balance = balance + ? WHERE id = ?",
         amount_ugocents,
         to.id
     )
-    .execute(&mut db_tx)
+    .execute(&mut *db_tx)
     .await?;
 
     // And finally create the transaction log
-    tx::create_log(&mut db_tx, from, to, amount, memo).await?;
-
-    db_tx.commit().await?;
+    tx::create_log(&mut *db_tx, now, from, to, amount, memo).await?;
 
     Ok(())
 }
@@ -128,12 +159,67 @@ pub async fn transfer(
 // Credits an account from the central bank account.
 pub async fn credit_account(
     db: &SqlitePool,
+    now: DateTime<Local>,
     to: &UgocoinAccount,
     amount: Ugocoin,
     memo: &String,
 ) -> Result<(), Error> {
     let central_account = get_central_bank_account(db).await?;
-    transfer(db, &central_account, to, amount, memo).await?;
+    transfer(db, now, &central_account, to, amount, memo).await?;
+
+    Ok(())
+}
+
+// Same as credit_account, but inside an existing transaction. The central bank's balance is read
+// inside it too, so several credits in one transaction each see the last one's debit.
+pub async fn credit_account_in_tx(
+    db_tx: &mut Transaction<'_, Sqlite>,
+    now: DateTime<Local>,
+    to: &UgocoinAccount,
+    amount: Ugocoin,
+    memo: &String,
+) -> Result<(), Error> {
+    let central_account = get_central_bank_account(&mut *db_tx).await?;
+    transfer_in_tx(db_tx, now, &central_account, to, amount, memo).await?;
+
+    Ok(())
+}
+
+// Creates new UGOcoin in the central bank. It's logged as a transfer from the central bank to
+// itself, so the ledger can account for it.
+pub async fn mint(
+    db: &SqlitePool,
+    now: DateTime<Local>,
+    amount: Ugocoin,
+    memo: &String,
+) -> Result<(), Error> {
+    if amount < Ugocoin::from_ugocents(0) {
+        return Err(InnerError::NegativeTransfer.into());
+    }
+
+    let mut db_tx = db.begin().await?;
+
+    let central_account = get_central_bank_account(&mut db_tx).await?;
+    let amount_ugocents = amount.as_ugocents();
+    sqlx::query!(
+        "UPDATE ugocoin_accounts SET balance = balance + ? WHERE id = ?",
+        amount_ugocents,
+        central_account.id
+    )
+    .execute(&mut db_tx)
+    .await?;
+
+    tx::create_log(
+        &mut db_tx,
+        now,
+        &central_account,
+        &central_account,
+        amount,
+        memo,
+    )
+    .await?;
+
+    db_tx.commit().await?;
 
     Ok(())
 }
@@ -141,12 +227,13 @@ pub async fn credit_account(
 // Debits an account, and sends the money back to the central bank account
 pub async fn debit_account(
     db: &SqlitePool,
+    now: DateTime<Local>,
     from: &UgocoinAccount,
     amount: Ugocoin,
     memo: &String,
 ) -> Result<(), Error> {
     let central_account = get_central_bank_account(db).await?;
-    transfer(db, from, &central_account, amount, memo).await?;
+    transfer(db, now, from, &central_account, amount, memo).await?;
 
     Ok(())
 }
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+
+use sqlx::{Sqlite, SqlitePool, Transaction};
+
+use crate::error::Error;
+
+use super::account::{get_central_bank_account, Ugocoin};
+
+pub struct AccountAudit {
+    pub account_id: i64,
+    pub name: String,
+    pub balance: Ugocoin,
+    // What the balance should be, going by the transaction logs.
+    pub ledger_balance: Ugocoin,
+}
+
+impl AccountAudit {
+    pub fn discrepancy(&self) -> Ugocoin {
+        Ugocoin::from_ugocents(self.balance.as_ugocents() - self.ledger_balance.as_ugocents())
+    }
+}
+
+pub struct AuditReport {
+    pub accounts: Vec<AccountAudit>,
+    // Central bank funds that aren't explained by the ledger, e.g. added with SQL before minting
+    // was logged. Not a problem by itself, but worth knowing.
+    pub untraced_central_funds: Ugocoin,
+    pub problems: Vec<String>,
+}
+
+impl AuditReport {
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn total_supply(&self) -> Ugocoin {
+        Ugocoin::from_ugocents(
+            self.accounts
+                .iter()
+                .map(|account| account.balance.as_ugocents())
+                .sum(),
+        )
+    }
+}
+
+// Replays the transaction logs and checks every account ends up where its balance says.
+pub async fn audit_ledger(db: &SqlitePool) -> Result<AuditReport, Error> {
+    // A transaction keeps the balances and logs from moving between queries.
+    let mut db_tx = db.begin().await?;
+    let report = audit_ledger_in_tx(&mut db_tx).await?;
+    db_tx.commit().await?;
+
+    Ok(report)
+}
+
+// Same as audit_ledger, but inside an existing transaction, so writes can be checked before commit.
+pub async fn audit_ledger_in_tx(db_tx: &mut Transaction<'_, Sqlite>) -> Result<AuditReport, Error> {
+    let central_account = get_central_bank_account(&mut *db_tx).await?;
+
+    let accounts = sqlx::query!(
+        r#"SELECT ugocoin_accounts.id, ugocoin_accounts.balance,
+            users.display_name as "display_name?"
+        FROM ugocoin_accounts
+        LEFT JOIN users ON users.id = ugocoin_accounts.user_id
+        ORDER BY ugocoin_accounts.id"#
+    )
+    .fetch_all(&mut *db_tx)
+    .await?;
+
+    // Transfers to yourself don't move anything, except that the central bank mints that way.
+    let mut ledger: HashMap<i64, i64> = HashMap::new();
+    let received = sqlx::query!(
+        r#"SELECT to_account_id, SUM(amount) as "total!: i64" FROM ugocoin_tx_logs
+        WHERE from_account_id != to_account_id OR from_account_id = ?
+        GROUP BY to_account_id"#,
+        central_account.id
+    )
+    .fetch_all(&mut *db_tx)
+    .await?;
+    for row in received {
+        *ledger.entry(row.to_account_id).or_default() += row.total;
+    }
+
+    let sent = sqlx::query!(
+        r#"SELECT from_account_id, SUM(amount) as "total!: i64" FROM ugocoin_tx_logs
+        WHERE from_account_id != to_account_id
+        GROUP BY from_account_id"#
+    )
+    .fetch_all(&mut *db_tx)
+    .await?;
+    for row in sent {
+        *ledger.entry(row.from_account_id).or_default() -= row.total;
+    }
+
+    let mut problems = Vec::new();
+    let mut untraced_central_funds = Ugocoin::from_ugocents(0);
+
+    let accounts: Vec<AccountAudit> = accounts
+        .into_iter()
+        .map(|row| AccountAudit {
+            account_id: row.id,
+            name: row
+                .display_name
+                .unwrap_or_else(|| String::from("UGOcoin Central Bank")),
+            balance: Ugocoin::from_ugocents(row.balance),
+            ledger_balance: Ugocoin::from_ugocents(ledger.remove(&row.id).unwrap_or_default()),
+        })
+        .collect();
+
+    for account in &accounts {
+        if account.balance < Ugocoin::from_ugocents(0) {
+            problems.push(format!(
+                "Account #{} ({}) has a negative balance of {}.",
+                account.account_id,
+                account.name,
+                account.balance.as_ugocents()
+            ));
+        }
+
+        let discrepancy = account.discrepancy();
+        if account.account_id == central_account.id && discrepancy >= Ugocoin::from_ugocents(0) {
+            untraced_central_funds = discrepancy;
+        } else if discrepancy != Ugocoin::from_ugocents(0) {
+            problems.push(format!(
+                "Account #{} ({}) holds {} ugocents, but the ledger says {}.",
+                account.account_id,
+                account.name,
+                account.balance.as_ugocents(),
+                account.ledger_balance.as_ugocents()
+            ));
+        }
+    }
+
+    // Whatever's left in the ledger belongs to accounts that don't exist.
+    let mut missing: Vec<&i64> = ledger.keys().collect();
+    missing.sort();
+    for account_id in missing {
+        problems.push(format!(
+            "Transactions reference account #{}, which doesn't exist.",
+            account_id
+        ));
+    }
+
+    let negative = sqlx::query!("SELECT id FROM ugocoin_tx_logs WHERE amount < 0 ORDER BY id")
+        .fetch_all(&mut *db_tx)
+        .await?;
+    for row in negative {
+        problems.push(format!("Transaction #{} has a negative amount.", row.id));
+    }
+
+    Ok(AuditReport {
+        accounts,
+        untraced_central_funds,
+        problems,
+    })
+}
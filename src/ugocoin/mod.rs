@@ -1,2 +1,4 @@
 pub mod account;
+pub mod audit;
+pub mod tip;
 pub mod tx;
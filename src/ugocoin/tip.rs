@@ -0,0 +1,234 @@
+use chrono::{DateTime, Local};
+
+use log::info;
+
+use serenity::model::channel::ReactionType;
+use serenity::model::id::{ChannelId, MessageId, UserId};
+
+use sqlx::SqlitePool;
+
+use crate::chat::Chat;
+use crate::error::{Error, InnerError, WithContext};
+use crate::user::{self, User};
+
+use super::account::{get_account_by_user_id, get_user_account, transfer_in_tx, Ugocoin};
+
+pub const TIP_EMOJI: &str = "🪙";
+pub const TIP_AMOUNT: Ugocoin = Ugocoin::from_ugocents(10);
+
+// Tips given per user per day. Refunded tips don't count against this.
+const DAILY_TIP_LIMIT: i64 = 5;
+// Removing a tip reaction within this window refunds the tip.
+const TIP_REFUND_GRACE_SECONDS: i64 = 5 * 60;
+
+pub fn is_tip_reaction(emoji: &ReactionType) -> bool {
+    matches!(emoji, ReactionType::Unicode(unicode) if unicode == TIP_EMOJI)
+}
+
+fn date_to_tip_db_format(datetime: DateTime<Local>) -> String {
+    datetime.format("%Y-%m-%d").to_string()
+}
+
+pub async fn tip_message(
+    db: &SqlitePool,
+    now: DateTime<Local>,
+    message_id: MessageId,
+    from: &User,
+    to: &User,
+) -> Result<(), Error> {
+    let date_str = date_to_tip_db_format(now);
+    let memo = format!("Tip from {} to {}", from.display_name, to.display_name);
+    let now_unix = now.timestamp();
+    let message_str = message_id.to_string();
+    let amount_ugocents = TIP_AMOUNT.as_ugocents();
+
+    // The transfer and the tip log need to land together, otherwise refunds can't find the tip.
+    let mut db_tx = db.begin().await?;
+
+    // Log the tip first so this transaction holds the write lock before counting. Concurrent tips
+    // then wait for each other instead of all seeing the same count.
+    sqlx::query!(
+        "INSERT INTO ugocoin_tips (tip_time, tip_date, message_id, from_user_id, to_user_id, amount)
+        VALUES (?, ?, ?, ?, ?, ?)",
+        now_unix,
+        date_str,
+        message_str,
+        from.id,
+        to.id,
+        amount_ugocents
+    )
+    .execute(&mut db_tx)
+    .await?;
+
+    // This count includes the tip we just logged.
+    let tips_today = sqlx::query!(
+        r#"SELECT COUNT(*) as "count: i64" FROM ugocoin_tips
+        WHERE from_user_id = ? AND tip_date = ? AND refunded = false"#,
+        from.id,
+        date_str
+    )
+    .fetch_one(&mut db_tx)
+    .await?
+    .count;
+
+    if tips_today > DAILY_TIP_LIMIT {
+        return Err(InnerError::TipLimitReached.into());
+    }
+
+    let from_account = get_user_account(&mut db_tx, from).await?;
+    let to_account = get_user_account(&mut db_tx, to).await?;
+
+    transfer_in_tx(
+        &mut db_tx,
+        now,
+        &from_account,
+        &to_account,
+        TIP_AMOUNT,
+        &memo,
+    )
+    .await?;
+
+    db_tx.commit().await?;
+
+    Ok(())
+}
+
+// Refunds the most recent tip from this user on this message, if it's still within the grace period.
+// Returns whether a tip was refunded.
+pub async fn refund_tip(
+    db: &SqlitePool,
+    now: DateTime<Local>,
+    message_id: MessageId,
+    from: &User,
+) -> Result<bool, Error> {
+    let message_str = message_id.to_string();
+    let grace_start = now.timestamp() - TIP_REFUND_GRACE_SECONDS;
+
+    let mut db_tx = db.begin().await?;
+
+    let tip = match sqlx::query!(
+        r#"SELECT id, to_user_id as "to_user_id!", amount as "amount!" FROM ugocoin_tips
+        WHERE message_id = ? AND from_user_id = ? AND refunded = false AND tip_time >= ?
+        ORDER BY tip_time DESC LIMIT 1"#,
+        message_str,
+        from.id,
+        grace_start
+    )
+    .fetch_optional(&mut db_tx)
+    .await?
+    {
+        Some(tip) => tip,
+        None => return Ok(false),
+    };
+
+    // Only one refund can win if the reaction is removed twice at once.
+    let marked = sqlx::query!(
+        "UPDATE ugocoin_tips SET refunded = true WHERE id = ? AND refunded = false",
+        tip.id
+    )
+    .execute(&mut db_tx)
+    .await?;
+
+    if marked.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    let from_account = get_user_account(&mut db_tx, from).await?;
+    let to_account = get_account_by_user_id(&mut db_tx, tip.to_user_id).await?;
+
+    let memo = format!("Refund of tip from {}", from.display_name);
+
+    // Money flows back the other way for a refund
+    transfer_in_tx(
+        &mut db_tx,
+        now,
+        &to_account,
+        &from_account,
+        Ugocoin::from_ugocents(tip.amount),
+        &memo,
+    )
+    .await?;
+
+    db_tx.commit().await?;
+
+    Ok(true)
+}
+
+// Called when someone reacts to a message with the tip emoji. Tips the message's author, as long
+// as we know both of them and they aren't the same person.
+pub async fn on_tip_add(
+    db: &SqlitePool,
+    chat: &dyn Chat,
+    now: DateTime<Local>,
+    channel_id: ChannelId,
+    message_id: MessageId,
+    reactor_id: UserId,
+) -> Result<(), Error> {
+    let author = chat
+        .message_author(channel_id, message_id)
+        .await
+        .with_context("Finding Discord message for tip")?;
+
+    if author.bot {
+        return Ok(());
+    }
+
+    // Both sides of a tip need UGOcoin accounts, so ignore anyone we don't know about.
+    let from = match user::find_user(db, &reactor_id)
+        .await
+        .with_context("Fetching tipping user")?
+    {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+    let to = match user::find_user(db, &author.id)
+        .await
+        .with_context("Fetching tipped user")?
+    {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+
+    // Compare our users rather than Discord IDs so alt accounts can't tip their main.
+    if from == to {
+        info!("{} tried to tip themselves. Ignoring.", from.display_name);
+        return Ok(());
+    }
+
+    match tip_message(db, now, message_id, &from, &to).await {
+        Ok(()) => info!("{} tipped {}", from.display_name, to.display_name),
+        Err(Error {
+            error: InnerError::InsufficientFunds | InnerError::TipLimitReached,
+            ..
+        }) => info!("Tip from {} rejected.", from.display_name),
+        Err(other) => return Err(other).with_context("Tipping message"),
+    }
+
+    Ok(())
+}
+
+// Called when someone takes their tip reaction back.
+pub async fn on_tip_remove(
+    db: &SqlitePool,
+    now: DateTime<Local>,
+    message_id: MessageId,
+    reactor_id: UserId,
+) -> Result<(), Error> {
+    let from = match user::find_user(db, &reactor_id)
+        .await
+        .with_context("Fetching tipping user")?
+    {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+
+    let refunded = refund_tip(db, now, message_id, &from)
+        .await
+        .with_context("Refunding tip")?;
+
+    if refunded {
+        info!("Refunded tip from {}", from.display_name);
+    }
+
+    Ok(())
+}
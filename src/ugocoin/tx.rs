@@ -1,26 +1,28 @@
-use chrono::{DateTime, Local};
-use sqlx::{Executor, Sqlite};
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use sqlx::{Executor, Sqlite, SqlitePool};
 
-use crate::error::Error;
+use crate::error::{Error, InnerError};
 
 use super::account::{Ugocoin, UgocoinAccount};
 
 pub struct UgocoinTransaction {
-    id: i64,
-    tx_time: DateTime<Local>,
-    from_account_id: i64,
-    to_account_id: i64,
-    memo: String,
+    pub id: i64,
+    pub tx_time: i64,
+    pub from_account_id: i64,
+    pub to_account_id: i64,
+    pub amount: Ugocoin,
+    pub memo: String,
 }
 
 pub async fn create_log<'a, E: Executor<'a, Database = Sqlite>>(
     db: E,
+    now: DateTime<Local>,
     from: &UgocoinAccount,
     to: &UgocoinAccount,
     amount: Ugocoin,
     memo: &String,
 ) -> Result<(), Error> {
-    let now_unix = Local::now().timestamp();
+    let now_unix = now.timestamp();
     let ugocents = amount.as_ugocents();
 
     sqlx::query!(
@@ -30,3 +32,149 @@ pub async fn create_log<'a, E: Executor<'a, Database = Sqlite>>(
 
     Ok(())
 }
+
+fn start_of_day_unix(date: NaiveDate) -> i64 {
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+    // DST can skip midnight in some timezones. Being off by an hour is fine for charting.
+    Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .map(|datetime| datetime.timestamp())
+        .unwrap_or_else(|| midnight.timestamp())
+}
+
+// Reconstructs an account's balance at the end of each of the given days, by starting from the
+// current balance and undoing every transaction that happened afterwards.
+pub async fn get_balance_history(
+    db: &SqlitePool,
+    account: &UgocoinAccount,
+    dates: &[NaiveDate],
+) -> Result<Vec<(NaiveDate, Ugocoin)>, Error> {
+    let since = match dates.first() {
+        Some(first) => start_of_day_unix(*first),
+        None => return Ok(Vec::new()),
+    };
+
+    let txs = sqlx::query!(
+        "SELECT tx_time, from_account_id, to_account_id, amount FROM ugocoin_tx_logs
+        WHERE tx_time >= ? AND (from_account_id = ? OR to_account_id = ?)",
+        since,
+        account.id,
+        account.id
+    )
+    .fetch_all(db)
+    .await?;
+
+    let history = dates
+        .iter()
+        .map(|date| {
+            let end_of_day = date.succ_opt().map(start_of_day_unix).unwrap_or(i64::MAX);
+
+            let change_after: i64 = txs
+                .iter()
+                .filter(|tx| tx.tx_time >= end_of_day)
+                .map(|tx| {
+                    let credited = if tx.to_account_id == account.id {
+                        tx.amount
+                    } else {
+                        0
+                    };
+                    let debited = if tx.from_account_id == account.id {
+                        tx.amount
+                    } else {
+                        0
+                    };
+                    credited - debited
+                })
+                .sum();
+
+            (
+                *date,
+                Ugocoin::from_ugocents(account.balance.as_ugocents() - change_after),
+            )
+        })
+        .collect();
+
+    Ok(history)
+}
+
+pub async fn get_transaction(db: &SqlitePool, id: i64) -> Result<UgocoinTransaction, Error> {
+    let query = sqlx::query!(
+        "SELECT id, tx_time, from_account_id, to_account_id, amount, memo
+        FROM ugocoin_tx_logs WHERE id = ?",
+        id
+    )
+    .fetch_one(db)
+    .await;
+
+    let row = query.map_err(|err| match err {
+        sqlx::Error::RowNotFound => Error::from(InnerError::TransactionNotFound),
+        _ => err.into(),
+    })?;
+
+    Ok(UgocoinTransaction {
+        id: row.id,
+        tx_time: row.tx_time,
+        from_account_id: row.from_account_id,
+        to_account_id: row.to_account_id,
+        amount: Ugocoin::from_ugocents(row.amount),
+        memo: row.memo,
+    })
+}
+
+pub async fn get_recent_transactions(
+    db: &SqlitePool,
+    account: &UgocoinAccount,
+    limit: i64,
+) -> Result<Vec<UgocoinTransaction>, Error> {
+    let rows = sqlx::query!(
+        r#"SELECT id as "id!", tx_time as "tx_time!", from_account_id as "from_account_id!",
+        to_account_id as "to_account_id!", amount as "amount!", memo as "memo!"
+        FROM ugocoin_tx_logs WHERE from_account_id = ? OR to_account_id = ?
+        ORDER BY tx_time DESC LIMIT ?"#,
+        account.id,
+        account.id,
+        limit
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| UgocoinTransaction {
+            id: row.id,
+            tx_time: row.tx_time,
+            from_account_id: row.from_account_id,
+            to_account_id: row.to_account_id,
+            amount: Ugocoin::from_ugocents(row.amount),
+            memo: row.memo,
+        })
+        .collect())
+}
+
+// The newest transactions across every account.
+pub async fn get_latest_transactions(
+    db: &SqlitePool,
+    limit: i64,
+) -> Result<Vec<UgocoinTransaction>, Error> {
+    let rows = sqlx::query!(
+        r#"SELECT id as "id!", tx_time as "tx_time!", from_account_id as "from_account_id!",
+        to_account_id as "to_account_id!", amount as "amount!", memo as "memo!"
+        FROM ugocoin_tx_logs ORDER BY tx_time DESC, id DESC LIMIT ?"#,
+        limit
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| UgocoinTransaction {
+            id: row.id,
+            tx_time: row.tx_time,
+            from_account_id: row.from_account_id,
+            to_account_id: row.to_account_id,
+            amount: Ugocoin::from_ugocents(row.amount),
+            memo: row.memo,
+        })
+        .collect())
+}
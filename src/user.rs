@@ -1,12 +1,14 @@
 use std::hash::Hash;
 
-use sqlx::SqlitePool;
+use chrono::{DateTime, Local};
+
+use sqlx::{Executor, Sqlite, SqlitePool};
 
 use serenity::model::id::UserId;
 
 use crate::error::{Error, InnerError};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct User {
     pub id: i64,
     pub display_name: String,
@@ -46,6 +48,45 @@ pub async fn get_user(db: &SqlitePool, user_id: &UserId) -> Result<User, Error>
     })
 }
 
+// Like get_user, but treats an unregistered Discord user as a normal outcome.
+pub async fn find_user(db: &SqlitePool, user_id: &UserId) -> Result<Option<User>, Error> {
+    match get_user(db, user_id).await {
+        Ok(user) => Ok(Some(user)),
+        Err(Error {
+            error: InnerError::UserNotFound,
+            ..
+        }) => Ok(None),
+        Err(other) => Err(other),
+    }
+}
+
+// Looks a user up by our own ID, rather than a Discord ID.
+pub async fn get_user_by_id(db: &SqlitePool, id: i64) -> Result<User, Error> {
+    let query = sqlx::query_as!(
+        User,
+        "SELECT users.id, users.display_name, users.streak FROM users WHERE users.id = ?",
+        id
+    )
+    .fetch_one(db)
+    .await;
+
+    query.map_err(|err| match err {
+        sqlx::Error::RowNotFound => InnerError::UserNotFound.into(),
+        _ => err.into(),
+    })
+}
+
+pub async fn get_discord_ids(db: &SqlitePool, id: i64) -> Result<Vec<String>, Error> {
+    let rows = sqlx::query!(
+        "SELECT discord_id FROM users_discord_ids WHERE user_id = ? ORDER BY id",
+        id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| row.discord_id).collect())
+}
+
 pub async fn get_all_users(db: &SqlitePool) -> Result<Vec<User>, Error> {
     Ok(sqlx::query_as!(
         User,
@@ -55,7 +96,10 @@ pub async fn get_all_users(db: &SqlitePool) -> Result<Vec<User>, Error> {
     .await?)
 }
 
-pub async fn increment_streak(db: &SqlitePool, id: i64) -> Result<(), Error> {
+pub async fn increment_streak<'a, E: Executor<'a, Database = Sqlite>>(
+    db: E,
+    id: i64,
+) -> Result<(), Error> {
     sqlx::query!("UPDATE users SET streak = streak + 1 WHERE id = ?", id)
         .execute(db)
         .await?;
@@ -63,10 +107,256 @@ pub async fn increment_streak(db: &SqlitePool, id: i64) -> Result<(), Error> {
     Ok(())
 }
 
-pub async fn clear_streak(db: &SqlitePool, id: i64) -> Result<(), Error> {
+pub async fn clear_streak<'a, E: Executor<'a, Database = Sqlite>>(
+    db: E,
+    id: i64,
+) -> Result<(), Error> {
     sqlx::query!("UPDATE users SET streak = 0 WHERE id = ?", id)
         .execute(db)
         .await?;
 
     Ok(())
 }
+
+async fn is_discord_id_linked(db: &SqlitePool, user_id: &UserId) -> Result<bool, Error> {
+    let user_id_str = user_id.to_string();
+
+    let result = sqlx::query!(
+        "SELECT id FROM users_discord_ids WHERE discord_id = ?",
+        user_id_str
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(result.is_some())
+}
+
+// Creates a user, links their Discord ID and opens their UGOcoin account, all or nothing.
+pub async fn register_user(
+    db: &SqlitePool,
+    user_id: &UserId,
+    display_name: &str,
+) -> Result<User, Error> {
+    if is_discord_id_linked(db, user_id).await? {
+        return Err(InnerError::AlreadyRegistered.into());
+    }
+
+    let user_id_str = user_id.to_string();
+
+    let mut db_tx = db.begin().await?;
+
+    let id = sqlx::query!("INSERT INTO users (display_name) VALUES (?)", display_name)
+        .execute(&mut db_tx)
+        .await?
+        .last_insert_rowid();
+
+    // The check above is only a fast path. The unique index on discord_id catches a registration
+    // that raced us.
+    sqlx::query!(
+        "INSERT INTO users_discord_ids (discord_id, user_id) VALUES (?, ?)",
+        user_id_str,
+        id
+    )
+    .execute(&mut db_tx)
+    .await
+    .map_err(already_registered_on_conflict)?;
+
+    sqlx::query!(
+        "INSERT INTO ugocoin_accounts (user_id, balance) VALUES (?, 0)",
+        id
+    )
+    .execute(&mut db_tx)
+    .await?;
+
+    db_tx.commit().await?;
+
+    Ok(User {
+        id,
+        display_name: display_name.to_string(),
+        streak: 0,
+    })
+}
+
+// Links another Discord account to a user straight away, skipping the request and approval.
+pub async fn link_discord_id<'a, E: Executor<'a, Database = Sqlite>>(
+    db: E,
+    id: i64,
+    discord_id: &UserId,
+) -> Result<(), Error> {
+    let discord_id_str = discord_id.to_string();
+
+    // The unique index on discord_id catches accounts that are already linked.
+    sqlx::query!(
+        "INSERT INTO users_discord_ids (discord_id, user_id) VALUES (?, ?)",
+        discord_id_str,
+        id
+    )
+    .execute(db)
+    .await
+    .map_err(already_registered_on_conflict)?;
+
+    Ok(())
+}
+
+fn already_registered_on_conflict(err: sqlx::Error) -> Error {
+    match err {
+        sqlx::Error::Database(db_err) if db_err.message().contains("UNIQUE") => {
+            InnerError::AlreadyRegistered.into()
+        }
+        err => err.into(),
+    }
+}
+
+#[derive(Debug)]
+pub struct LinkRequest {
+    pub id: i64,
+    pub discord_id: String,
+    pub user_id: i64,
+    pub display_name: String,
+    pub request_time: i64,
+}
+
+const LINK_PENDING: &str = "pending";
+const LINK_APPROVED: &str = "approved";
+const LINK_DENIED: &str = "denied";
+
+// Asks to link a new Discord account to an existing user. An admin has to approve it
+// before the link takes effect.
+pub async fn request_link(
+    db: &SqlitePool,
+    now: DateTime<Local>,
+    discord_id: &UserId,
+    user: &User,
+) -> Result<i64, Error> {
+    if is_discord_id_linked(db, discord_id).await? {
+        return Err(InnerError::AlreadyRegistered.into());
+    }
+
+    let discord_id_str = discord_id.to_string();
+    let now_unix = now.timestamp();
+
+    // One pending request per Discord account, checked in the insert so two can't race in.
+    let result = sqlx::query!(
+        "INSERT INTO link_requests (discord_id, user_id, request_time, status)
+        SELECT ?, ?, ?, ?
+        WHERE NOT EXISTS (SELECT 1 FROM link_requests WHERE discord_id = ? AND status = ?)",
+        discord_id_str,
+        user.id,
+        now_unix,
+        LINK_PENDING,
+        discord_id_str,
+        LINK_PENDING
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(InnerError::LinkRequestPending.into());
+    }
+
+    Ok(result.last_insert_rowid())
+}
+
+pub async fn get_pending_link_requests(db: &SqlitePool) -> Result<Vec<LinkRequest>, Error> {
+    Ok(sqlx::query_as!(
+        LinkRequest,
+        "SELECT link_requests.id, link_requests.discord_id, link_requests.user_id,
+            users.display_name, link_requests.request_time
+        FROM link_requests
+        INNER JOIN users ON users.id = link_requests.user_id
+        WHERE link_requests.status = ?
+        ORDER BY link_requests.request_time",
+        LINK_PENDING
+    )
+    .fetch_all(db)
+    .await?)
+}
+
+async fn get_pending_link_request(db: &SqlitePool, id: i64) -> Result<LinkRequest, Error> {
+    let query = sqlx::query_as!(
+        LinkRequest,
+        "SELECT link_requests.id, link_requests.discord_id, link_requests.user_id,
+            users.display_name, link_requests.request_time
+        FROM link_requests
+        INNER JOIN users ON users.id = link_requests.user_id
+        WHERE link_requests.id = ? AND link_requests.status = ?",
+        id,
+        LINK_PENDING
+    )
+    .fetch_one(db)
+    .await;
+
+    query.map_err(|err| match err {
+        sqlx::Error::RowNotFound => InnerError::LinkRequestNotFound.into(),
+        _ => err.into(),
+    })
+}
+
+pub async fn approve_link_request(db: &SqlitePool, id: i64) -> Result<LinkRequest, Error> {
+    let request = get_pending_link_request(db, id).await?;
+
+    let mut db_tx = db.begin().await?;
+
+    // Only a request that's still pending, in case a denial got there first.
+    let result = sqlx::query!(
+        "UPDATE link_requests SET status = ? WHERE id = ? AND status = ?",
+        LINK_APPROVED,
+        request.id,
+        LINK_PENDING
+    )
+    .execute(&mut db_tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        db_tx.rollback().await?;
+        return Err(link_request_conflict(db, request.id).await);
+    }
+
+    // The unique index on discord_id catches anyone who registered in the meantime.
+    sqlx::query!(
+        "INSERT INTO users_discord_ids (discord_id, user_id) VALUES (?, ?)",
+        request.discord_id,
+        request.user_id
+    )
+    .execute(&mut db_tx)
+    .await
+    .map_err(already_registered_on_conflict)?;
+
+    db_tx.commit().await?;
+
+    Ok(request)
+}
+
+// Why a request stopped being pending while we were handling it. If it was approved, the account
+// is linked now.
+async fn link_request_conflict(db: &SqlitePool, id: i64) -> Error {
+    let status = sqlx::query_scalar!("SELECT status FROM link_requests WHERE id = ?", id)
+        .fetch_one(db)
+        .await;
+
+    match status {
+        Ok(status) if status == LINK_APPROVED => InnerError::AlreadyRegistered.into(),
+        Ok(_) => InnerError::LinkRequestNotFound.into(),
+        Err(err) => err.into(),
+    }
+}
+
+pub async fn deny_link_request(db: &SqlitePool, id: i64) -> Result<LinkRequest, Error> {
+    let request = get_pending_link_request(db, id).await?;
+
+    // Only a request that's still pending, in case an approval got there first.
+    let result = sqlx::query!(
+        "UPDATE link_requests SET status = ? WHERE id = ? AND status = ?",
+        LINK_DENIED,
+        request.id,
+        LINK_PENDING
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(link_request_conflict(db, request.id).await);
+    }
+
+    Ok(request)
+}
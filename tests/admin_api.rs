@@ -0,0 +1,73 @@
+mod common;
+
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+
+use tower::ServiceExt;
+
+use ugo_ii_bot::admin_api::{self, ApiState, BotStatus};
+use ugo_ii_bot::ugocoin::account::Ugocoin;
+
+async fn get_text(app: &Router, uri: &str) -> (StatusCode, String) {
+    let response = app
+        .clone()
+        .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+async fn get(app: &Router, uri: &str) -> (StatusCode, serde_json::Value) {
+    let (status, body) = get_text(app, uri).await;
+    (status, serde_json::from_str(&body).unwrap_or_default())
+}
+
+#[tokio::test]
+async fn serves_health_and_read_only_data() {
+    let status = Arc::new(BotStatus::new());
+    let app = admin_api::router(ApiState {
+        db: common::test_db().await,
+        status: status.clone(),
+    });
+
+    // Not healthy until the gateway connects.
+    let (code, health) = get(&app, "/health").await;
+    assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(health["database_reachable"], true);
+
+    status.set_gateway_connected(true);
+    let (code, health) = get(&app, "/health").await;
+    assert_eq!(code, StatusCode::OK);
+    assert!(!health["jobs"].as_array().unwrap().is_empty());
+
+    let (code, users) = get(&app, "/api/users").await;
+    assert_eq!(code, StatusCode::OK);
+    assert_eq!(users.as_array().unwrap().len(), 4);
+
+    let (code, _) = get(&app, "/api/transactions/12345").await;
+    assert_eq!(code, StatusCode::NOT_FOUND);
+
+    let (code, _) = get(&app, "/api/scrums?limit=0").await;
+    assert_eq!(code, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn serves_prometheus_metrics() {
+    let db = common::test_db().await;
+    common::fund_central_bank(&db, Ugocoin::from_ugocoin(25)).await;
+    let app = admin_api::router(ApiState {
+        db,
+        status: Arc::new(BotStatus::new()),
+    });
+
+    let (code, metrics) = get_text(&app, "/metrics").await;
+    assert_eq!(code, StatusCode::OK);
+    assert!(metrics.contains("ugo_ugocoin_supply_ugocents 2500"));
+    assert!(metrics.contains("ugo_users 4"));
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use std::fs;
+
+use chrono::Duration;
+
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+
+use ugo_ii_bot::backup::{self, BackupConfig};
+use ugo_ii_bot::db;
+
+use common::simulation::{date, local};
+
+#[tokio::test]
+async fn backups_are_verified_and_pruned() {
+    let dir = std::env::temp_dir().join(format!("ugo-backup-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    // VACUUM INTO from an in-memory database writes another in-memory database, so this test
+    // needs a real file.
+    let options = SqliteConnectOptions::new()
+        .filename(dir.join("live.sqlite"))
+        .create_if_missing(true);
+    let db = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await
+        .unwrap();
+    db::MIGRATOR.run(&db).await.unwrap();
+    let config = BackupConfig {
+        dir: dir.clone(),
+        keep: 2,
+    };
+
+    let start = local(date(2022, 12, 1), 2, 0);
+    let mut taken = Vec::new();
+    for day in 0..3 {
+        let path = backup::run_backup(&db, start + Duration::days(day), &config)
+            .await
+            .unwrap();
+        taken.push(path);
+    }
+
+    // Only the two newest are left, and they're real databases.
+    assert_eq!(backup::list_snapshots(&dir).unwrap(), taken[1..]);
+    for path in &taken[1..] {
+        backup::verify_snapshot(path).await.unwrap();
+    }
+
+    // A damaged snapshot doesn't pass.
+    let mut damaged = fs::read(&taken[2]).unwrap();
+    for byte in damaged.iter_mut().skip(4096).step_by(7) {
+        *byte = 0xFF;
+    }
+    fs::write(&taken[2], damaged).unwrap();
+    assert!(backup::verify_snapshot(&taken[2]).await.is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,164 @@
+use serde_json::{json, Value};
+
+use serenity::model::application::interaction::application_command::CommandData;
+use serenity::model::id::UserId;
+
+use ugo_ii_bot::command::options::{parse_options, CommandArgs, OptionSpec};
+use ugo_ii_bot::error::InnerError;
+use ugo_ii_bot::ugocoin::account::Ugocoin;
+
+// Builds the options the way Discord sends them, so serenity resolves the values for us. Fails
+// with the message the user would see.
+fn parse(specs: &[OptionSpec], options: Value) -> Result<CommandArgs, String> {
+    let data: CommandData = serde_json::from_value(json!({
+        "id": "1",
+        "name": "test",
+        "type": 1,
+        "options": options,
+        "resolved": {
+            "users": {
+                "42": {
+                    "id": "42",
+                    "username": "eitan",
+                    "discriminator": "0001",
+                    "avatar": null,
+                }
+            }
+        },
+    }))
+    .unwrap();
+
+    parse_options(specs, &data.options).map_err(|err| match err.error {
+        InnerError::InvalidArgument(message) => message,
+        other => panic!("Expected an invalid argument, got {:?}", other),
+    })
+}
+
+#[test]
+fn values_are_parsed_by_kind() {
+    let specs = [
+        OptionSpec::user("user", "Who."),
+        OptionSpec::integer("days", "How long.").range(2, 365),
+        OptionSpec::string("memo", "Why.").length(1, 100),
+        OptionSpec::date("end", "Until."),
+    ];
+    let args = parse(
+        &specs,
+        json!([
+            {"name": "user", "type": 6, "value": "42"},
+            {"name": "days", "type": 4, "value": 30},
+            {"name": "memo", "type": 3, "value": "  lunch  "},
+            {"name": "end", "type": 3, "value": "2022-12-25"},
+        ]),
+    )
+    .unwrap();
+
+    assert_eq!(args.user("user"), Some(UserId(42)));
+    assert_eq!(args.integer("days"), Some(30));
+    assert_eq!(args.string("memo"), Some("lunch"));
+    assert_eq!(
+        args.date("end").map(|date| date.to_string()),
+        Some(String::from("2022-12-25"))
+    );
+}
+
+#[test]
+fn integers_outside_the_range_are_rejected() {
+    let specs = [OptionSpec::integer("days", "How long.").range(2, 365)];
+
+    for days in [1, 366] {
+        let result = parse(&specs, json!([{"name": "days", "type": 4, "value": days}]));
+        assert_eq!(result.unwrap_err(), "`days` must be between 2 and 365.");
+    }
+
+    let args = parse(&specs, json!([{"name": "days", "type": 4, "value": 365}])).unwrap();
+    assert_eq!(args.integer("days"), Some(365));
+}
+
+#[test]
+fn strings_must_be_one_of_the_choices() {
+    let specs = [OptionSpec::string("kind", "Which.").choices(&["balance", "streak"])];
+
+    let result = parse(
+        &specs,
+        json!([{"name": "kind", "type": 3, "value": "vibes"}]),
+    );
+    assert_eq!(
+        result.unwrap_err(),
+        "`kind` must be one of: balance, streak."
+    );
+
+    let args = parse(
+        &specs,
+        json!([{"name": "kind", "type": 3, "value": "streak"}]),
+    )
+    .unwrap();
+    assert_eq!(args.string("kind"), Some("streak"));
+}
+
+#[test]
+fn ugocoin_amounts_are_rounded_to_ugocents() {
+    let specs = [OptionSpec::ugocoin("amount", "How much.")
+        .amount_range(Ugocoin::from_ugocents(1), Ugocoin::from_ugocoin(100))];
+
+    for (coins, ugocents) in [(0.1 + 0.2, 30), (0.29, 29), (12.344, 1234), (0.006, 1)] {
+        let args = parse(
+            &specs,
+            json!([{"name": "amount", "type": 10, "value": coins}]),
+        )
+        .unwrap();
+        assert_eq!(
+            args.ugocoin("amount"),
+            Some(Ugocoin::from_ugocents(ugocents))
+        );
+    }
+
+    // Rounds to zero, which is below the minimum.
+    let result = parse(
+        &specs,
+        json!([{"name": "amount", "type": 10, "value": 0.004}]),
+    );
+    assert!(result.unwrap_err().starts_with("`amount` must be between"));
+
+    let result = parse(
+        &specs,
+        json!([{"name": "amount", "type": 10, "value": 100.01}]),
+    );
+    assert!(result.unwrap_err().starts_with("`amount` must be between"));
+}
+
+#[test]
+fn missing_required_options_are_rejected() {
+    let specs = [
+        OptionSpec::user("user", "Who.").required(),
+        OptionSpec::integer("days", "How long."),
+    ];
+
+    let result = parse(&specs, json!([{"name": "days", "type": 4, "value": 3}]));
+    assert_eq!(result.unwrap_err(), "`user` is required.");
+
+    // Optional options can be left out.
+    let args = parse(&specs, json!([{"name": "user", "type": 6, "value": "42"}])).unwrap();
+    assert_eq!(args.integer("days"), None);
+}
+
+#[test]
+fn values_of_the_wrong_type_are_rejected() {
+    let specs = [
+        OptionSpec::integer("days", "How long."),
+        OptionSpec::ugocoin("amount", "How much."),
+    ];
+
+    let result = parse(&specs, json!([{"name": "days", "type": 3, "value": "30"}]));
+    assert_eq!(result.unwrap_err(), "`days` has the wrong type of value.");
+
+    let result = parse(&specs, json!([{"name": "amount", "type": 4, "value": 5}]));
+    assert_eq!(result.unwrap_err(), "`amount` has the wrong type of value.");
+
+    let specs = [OptionSpec::date("end", "Until.")];
+    let result = parse(
+        &specs,
+        json!([{"name": "end", "type": 3, "value": "25/12/2022"}]),
+    );
+    assert_eq!(result.unwrap_err(), "`end` must be a date like 2022-12-25.");
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use chrono::{Local, TimeZone};
+
+use serde_json::json;
+
+use ugo_ii_bot::chat::MemoryChat;
+use ugo_ii_bot::command::autocomplete::{
+    filter_choices, AutocompleteChoice, MAX_AUTOCOMPLETE_CHOICES,
+};
+use ugo_ii_bot::command::{reply_with_error, run_command};
+use ugo_ii_bot::error::InnerError;
+
+use common::{
+    command_interaction, get_user, make_admin, test_db, BOT_ID, INTERACTION_TOKEN, KEVIN,
+};
+
+fn choices(names: &[&str]) -> Vec<AutocompleteChoice> {
+    names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| AutocompleteChoice::Integer {
+            name: name.to_string(),
+            value: i as i64,
+        })
+        .collect()
+}
+
+fn names(choices: &[AutocompleteChoice]) -> Vec<&str> {
+    choices.iter().map(|choice| choice.name()).collect()
+}
+
+#[tokio::test]
+async fn subcommands_are_looked_up_by_name() {
+    let db = test_db().await;
+    let chat = MemoryChat::new(BOT_ID);
+    let now = Local.with_ymd_and_hms(2022, 12, 19, 12, 0, 0).unwrap();
+
+    let command = command_interaction(KEVIN, "chart", json!([]));
+    let err = run_command(&db, &chat, now, &command).await.unwrap_err();
+    assert!(matches!(err.error, InnerError::CommandNotFound(path) if path == "chart"));
+
+    let command = command_interaction(
+        KEVIN,
+        "chart",
+        json!([{"name": "pie", "type": 1, "options": []}]),
+    );
+    let err = run_command(&db, &chat, now, &command).await.unwrap_err();
+    assert!(matches!(err.error, InnerError::CommandNotFound(path) if path == "chart pie"));
+
+    // The subcommand's options are the ones that get validated.
+    let command = command_interaction(
+        KEVIN,
+        "chart",
+        json!([{
+            "name": "streak",
+            "type": 1,
+            "options": [{"name": "days", "type": 4, "value": 1}],
+        }]),
+    );
+    let err = run_command(&db, &chat, now, &command).await.unwrap_err();
+    assert!(matches!(
+        err.error,
+        InnerError::InvalidArgument(message) if message == "`days` must be between 2 and 365."
+    ));
+
+    assert!(chat.interaction_replies(INTERACTION_TOKEN).is_empty());
+}
+
+#[tokio::test]
+async fn a_group_permission_guards_its_subcommands() {
+    let db = test_db().await;
+    let chat = MemoryChat::new(BOT_ID);
+    let now = Local.with_ymd_and_hms(2022, 12, 19, 12, 0, 0).unwrap();
+    let command = command_interaction(
+        KEVIN,
+        "links",
+        json!([{"name": "pending", "type": 1, "options": []}]),
+    );
+
+    let err = run_command(&db, &chat, now, &command).await.unwrap_err();
+    assert!(matches!(err.error, InnerError::PermissionDenied));
+
+    make_admin(&db, KEVIN).await;
+
+    run_command(&db, &chat, now, &command).await.unwrap();
+    let replies = chat.interaction_replies(INTERACTION_TOKEN);
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0].content, "No pending link requests.");
+}
+
+#[tokio::test]
+async fn errors_after_a_public_deferral_stay_private() {
+    let db = test_db().await;
+    let chat = MemoryChat::new(BOT_ID);
+    let now = Local.with_ymd_and_hms(2022, 12, 19, 12, 0, 0).unwrap();
+    // /balances defers publicly, then fails looking up Kevin's missing account.
+    let kevin = get_user(&db, KEVIN).await;
+    sqlx::query("DELETE FROM ugocoin_accounts WHERE user_id = ?")
+        .bind(kevin.id)
+        .execute(&db)
+        .await
+        .unwrap();
+
+    let command = command_interaction(KEVIN, "balances", json!([]));
+    let err = run_command(&db, &chat, now, &command).await.unwrap_err();
+    reply_with_error(&chat, &command, &err).await.unwrap();
+
+    let replies = chat.interaction_replies(INTERACTION_TOKEN);
+    assert_eq!(replies.len(), 2);
+    assert!(!replies[0].ephemeral);
+    assert!(!replies[0].content.contains("incident"));
+    assert!(replies[1].ephemeral);
+    assert!(replies[1].content.contains("incident"));
+}
+
+#[test]
+fn choices_are_filtered_by_what_was_typed() {
+    let all = choices(&["#1: Eitan", "#2: Kevin", "#12: Bobby", "#3: kevin's alt"]);
+
+    assert_eq!(
+        names(&filter_choices(" KEV ", all.clone())),
+        ["#2: Kevin", "#3: kevin's alt"]
+    );
+    assert_eq!(
+        names(&filter_choices("1", all.clone())),
+        ["#1: Eitan", "#12: Bobby"]
+    );
+    assert_eq!(filter_choices("", all.clone()).len(), all.len());
+    assert!(filter_choices("justin", all).is_empty());
+}
+
+#[test]
+fn at_most_a_page_of_choices_is_offered() {
+    let many: Vec<String> = (0..40).map(|i| format!("#{}", i)).collect();
+    let many: Vec<&str> = many.iter().map(String::as_str).collect();
+
+    let filtered = filter_choices("#", choices(&many));
+    assert_eq!(filtered.len(), MAX_AUTOCOMPLETE_CHOICES);
+    assert_eq!(filtered[0].name(), "#0");
+}
@@ -0,0 +1,108 @@
+// Each test binary only uses some of these helpers.
+#![allow(dead_code)]
+
+use std::str::FromStr;
+
+use serde_json::{json, Value};
+
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::SqlitePool;
+
+use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
+use serenity::model::id::{ChannelId, UserId};
+
+use ugo_ii_bot::db;
+use ugo_ii_bot::ugocoin::account::Ugocoin;
+use ugo_ii_bot::user::{self, User};
+
+pub mod simulation;
+pub mod time_zone;
+
+pub const BOT_ID: UserId = UserId(1);
+// Every interaction from `command_interaction` carries this token, so its replies can be looked up.
+pub const INTERACTION_TOKEN: &str = "interaction-token";
+pub const SCRUM_CHANNEL: ChannelId = ChannelId(100);
+
+// The Discord IDs the migrations seed the four founding users with.
+pub const EITAN: UserId = UserId(724665949248290836);
+pub const KEVIN: UserId = UserId(718250610910298124);
+pub const BOBBY: UserId = UserId(154391881185361920);
+pub const JUSTIN: UserId = UserId(214580656200482816);
+
+// A database with no tables at all. In-memory databases exist per connection, so the pool only
+// ever opens one.
+pub async fn empty_db() -> SqlitePool {
+    let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await
+        .expect("Failed to open test database")
+}
+
+// A fresh, fully migrated database.
+pub async fn test_db() -> SqlitePool {
+    let db = empty_db().await;
+
+    db::MIGRATOR
+        .run(&db)
+        .await
+        .expect("Failed to migrate test database");
+
+    db
+}
+
+// The central bank can't go negative, so give it something to pay rewards out of.
+pub async fn fund_central_bank(db: &SqlitePool, amount: Ugocoin) {
+    let amount = amount.as_ugocents();
+    sqlx::query("UPDATE ugocoin_accounts SET balance = ? WHERE user_id IS NULL")
+        .bind(amount)
+        .execute(db)
+        .await
+        .expect("Failed to fund central bank");
+}
+
+pub async fn get_user(db: &SqlitePool, discord_id: UserId) -> User {
+    user::get_user(db, &discord_id)
+        .await
+        .expect("Failed to fetch user")
+}
+
+pub async fn make_admin(db: &SqlitePool, discord_id: UserId) {
+    let user = get_user(db, discord_id).await;
+    sqlx::query("INSERT INTO admins (user_id) VALUES (?)")
+        .bind(user.id)
+        .execute(db)
+        .await
+        .expect("Failed to make admin");
+}
+
+// A slash command the way Discord sends it, with subcommands nested in the options.
+pub fn command_interaction(
+    user_id: UserId,
+    name: &str,
+    options: Value,
+) -> ApplicationCommandInteraction {
+    serde_json::from_value(json!({
+        "id": "10",
+        "application_id": BOT_ID.0.to_string(),
+        "type": 2,
+        "data": {
+            "id": "20",
+            "name": name,
+            "type": 1,
+            "options": options,
+        },
+        "channel_id": "30",
+        "user": {
+            "id": user_id.0.to_string(),
+            "username": "kevin",
+            "discriminator": "0001",
+            "avatar": null,
+        },
+        "token": INTERACTION_TOKEN,
+        "version": 1,
+        "locale": "en-US",
+    }))
+    .unwrap()
+}
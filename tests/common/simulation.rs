@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike};
+
+use serenity::model::id::{ChannelId, UserId};
+
+use sqlx::SqlitePool;
+
+use ugo_ii_bot::chat::MemoryChat;
+use ugo_ii_bot::clock::{Clock, SimulatedClock};
+use ugo_ii_bot::jobs::{JobChannels, Scheduler};
+use ugo_ii_bot::scrum::{self, SCRUM_CLOSE_HOUR, SCRUM_OPEN_HOUR};
+use ugo_ii_bot::ugocoin::account::{self, Ugocoin};
+
+use super::{fund_central_bank, get_user, test_db, BOT_ID, SCRUM_CHANNEL};
+
+pub const DIGEST_CHANNEL: ChannelId = ChannelId(200);
+pub const CENTRAL_BANK_FUNDS: Ugocoin = Ugocoin::from_ugocoin(10_000);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Answer {
+    Accept,
+    Decline,
+}
+
+impl Answer {
+    fn emoji(&self) -> &'static str {
+        match self {
+            Answer::Accept => "👍",
+            Answer::Decline => "👎",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScriptedAnswer {
+    pub scrum_date: NaiveDate,
+    pub at: DateTime<Local>,
+    pub user: UserId,
+    pub answer: Answer,
+}
+
+// A wall clock time, which has to exist on that day in the test's time zone.
+pub fn local(date: NaiveDate, hour: u32, minute: u32) -> DateTime<Local> {
+    let naive = NaiveDateTime::new(date, NaiveTime::from_hms_opt(hour, minute, 0).unwrap());
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .unwrap_or_else(|| panic!("{} doesn't exist in this time zone", naive))
+}
+
+pub fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+// Drives the bot's scheduled jobs and reaction handling against a simulated clock, the way the
+// Discord handler would, while playing back scripted scrum answers.
+pub struct Simulation {
+    pub db: SqlitePool,
+    pub chat: MemoryChat,
+    pub clock: SimulatedClock,
+    pub scheduler: Scheduler,
+    script: Vec<ScriptedAnswer>,
+    channels: JobChannels,
+}
+
+impl Simulation {
+    pub async fn new(start: DateTime<Local>) -> Simulation {
+        let db = test_db().await;
+        fund_central_bank(&db, CENTRAL_BANK_FUNDS).await;
+
+        Simulation {
+            db,
+            chat: MemoryChat::new(BOT_ID),
+            clock: SimulatedClock::new(start),
+            scheduler: Scheduler::new(),
+            script: Vec::new(),
+            channels: JobChannels {
+                scrum: SCRUM_CHANNEL,
+                digest: DIGEST_CHANNEL,
+            },
+        }
+    }
+
+    pub fn add_script(&mut self, answers: &[ScriptedAnswer]) {
+        self.script.extend_from_slice(answers);
+        self.script.sort_by_key(|scripted| scripted.at);
+    }
+
+    // Steps the clock forward in real elapsed time until `end`, ticking the scheduler after every
+    // step like the bot's job loop does.
+    pub async fn run_until(&mut self, end: DateTime<Local>, step: Duration) {
+        while self.clock.now() < end {
+            let now = self.clock.advance(step);
+
+            let outcomes = self
+                .scheduler
+                .tick(&self.db, &self.chat, now, &self.channels)
+                .await
+                .unwrap_or_else(|err| panic!("Scheduler failed at {}: {}", now, err));
+            for outcome in outcomes {
+                if let Err(err) = outcome.result {
+                    panic!("Job {} failed at {}: {}", outcome.job, now, err);
+                }
+            }
+
+            // Answers come in after the poll, so one at 03:00 sees the scrum that just opened and
+            // one at 16:00 finds it already closed.
+            let due: Vec<ScriptedAnswer> = self
+                .script
+                .iter()
+                .filter(|scripted| scripted.at <= now)
+                .cloned()
+                .collect();
+            self.script.retain(|scripted| scripted.at > now);
+
+            for scripted in due {
+                self.play(now, &scripted).await;
+            }
+        }
+    }
+
+    async fn play(&self, now: DateTime<Local>, scripted: &ScriptedAnswer) {
+        let scrum_time = local(scripted.scrum_date, 12, 0);
+        let scrum = match scrum::get_scrum_for_date(&self.db, scrum_time)
+            .await
+            .unwrap()
+        {
+            Some(scrum) => scrum,
+            // Nothing to react to yet.
+            None => return,
+        };
+        let message_id = scrum.message_id().unwrap();
+
+        self.chat
+            .add_reaction(message_id, scripted.user, scripted.answer.emoji());
+        scrum::on_scrum_react(&self.db, &self.chat, now, SCRUM_CHANNEL, message_id)
+            .await
+            .unwrap_or_else(|err| panic!("Reaction failed at {}: {}", now, err));
+    }
+
+    pub async fn streak(&self, user: UserId) -> i64 {
+        get_user(&self.db, user).await.streak
+    }
+
+    pub async fn balance(&self, user: UserId) -> Ugocoin {
+        let user = get_user(&self.db, user).await;
+        account::get_user_account(&self.db, &user)
+            .await
+            .unwrap()
+            .balance
+    }
+
+    pub async fn total_balance(&self) -> Ugocoin {
+        let total: i64 = sqlx::query_scalar("SELECT SUM(balance) FROM ugocoin_accounts")
+            .fetch_one(&self.db)
+            .await
+            .unwrap();
+        Ugocoin::from_ugocents(total)
+    }
+
+    pub async fn scrum_dates(&self) -> Vec<String> {
+        sqlx::query_scalar("SELECT scrum_date FROM scrums ORDER BY scrum_date")
+            .fetch_all(&self.db)
+            .await
+            .unwrap()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Expected {
+    pub streak: i64,
+    pub balance: Ugocoin,
+}
+
+// Works out streaks and balances from the script independently of the bot: an answer counts if it
+// lands while that day's scrum is open, and answering either way keeps your streak going.
+pub fn expected_outcome(
+    users: &[UserId],
+    days: &[NaiveDate],
+    script: &[ScriptedAnswer],
+) -> HashMap<UserId, Expected> {
+    let mut expected: HashMap<UserId, Expected> = users
+        .iter()
+        .map(|user| {
+            (
+                *user,
+                Expected {
+                    streak: 0,
+                    balance: Ugocoin::from_ugocents(0),
+                },
+            )
+        })
+        .collect();
+
+    for day in days {
+        let answered: Vec<UserId> = users
+            .iter()
+            .filter(|user| {
+                script.iter().any(|scripted| {
+                    scripted.scrum_date == *day
+                        && scripted.user == **user
+                        && scripted.at.date_naive() == *day
+                        && scripted.at.hour() >= SCRUM_OPEN_HOUR
+                        && scripted.at.hour() < SCRUM_CLOSE_HOUR
+                })
+            })
+            .cloned()
+            .collect();
+
+        for user in users {
+            let state = expected.get_mut(user).unwrap();
+            if answered.contains(user) {
+                state.streak += 1;
+                let reward = Ugocoin::from_ugocoin(1 + state.streak / 7);
+                state.balance =
+                    Ugocoin::from_ugocents(state.balance.as_ugocents() + reward.as_ugocents());
+            } else {
+                state.streak = 0;
+            }
+        }
+    }
+
+    expected
+}
@@ -0,0 +1,60 @@
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::process;
+
+pub type TestFuture = Pin<Box<dyn Future<Output = ()>>>;
+pub type Test = (&'static str, fn() -> TestFuture);
+
+// Runs a test binary's tests in one time zone. The bot reads the time zone from TZ, which is
+// process wide, so binaries that depend on it set `harness = false` and call this from main. TZ is
+// then set before any other thread exists, and the tests run one after another, each on its own
+// runtime. Like the default harness, a test name filter can be passed on the command line.
+pub fn run_tests(time_zone: &str, tests: &[Test]) {
+    std::env::set_var("TZ", time_zone);
+
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with('-'));
+    let tests: Vec<&Test> = tests
+        .iter()
+        .filter(|(name, _)| {
+            filter
+                .as_ref()
+                .is_none_or(|filter| name.contains(filter.as_str()))
+        })
+        .collect();
+
+    println!("\nrunning {} tests in {}", tests.len(), time_zone);
+    let mut failed = Vec::new();
+    for (name, test) in &tests {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Failed to build test runtime");
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| runtime.block_on(test())));
+        match result {
+            Ok(()) => println!("test {} ... ok", name),
+            Err(_) => {
+                println!("test {} ... FAILED", name);
+                failed.push(*name);
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        println!("\nfailures:");
+        for name in &failed {
+            println!("    {}", name);
+        }
+    }
+    println!(
+        "\ntest result: {}. {} passed; {} failed\n",
+        if failed.is_empty() { "ok" } else { "FAILED" },
+        tests.len() - failed.len(),
+        failed.len()
+    );
+
+    if !failed.is_empty() {
+        process::exit(101);
+    }
+}
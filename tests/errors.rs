@@ -0,0 +1,45 @@
+use std::error::Error as _;
+
+use ugo_ii_bot::error::{Error, InnerError, WithContext};
+
+fn lookup_user() -> Error {
+    Err::<(), _>(sqlx::Error::RowNotFound)
+        .with_context(format!("Fetching user {}", 42))
+        .unwrap_err()
+}
+
+#[test]
+fn context_frames_stack_outermost_first() {
+    let err = Err::<(), _>(lookup_user())
+        .with_context("Transferring payment")
+        .unwrap_err();
+
+    assert_eq!(err.context(), "Transferring payment: Fetching user 42");
+    assert!(err
+        .to_string()
+        .starts_with("Transferring payment: Fetching user 42 failed! (Database error:"));
+}
+
+#[test]
+fn source_is_the_wrapped_error() {
+    let err = lookup_user();
+    let source = err.source().unwrap();
+    assert!(source.downcast_ref::<sqlx::Error>().is_some());
+
+    let err = Error::from(InnerError::InsufficientFunds);
+    assert!(err.source().is_none());
+}
+
+#[test]
+fn only_transient_failures_are_retryable() {
+    assert!(Error::from(sqlx::Error::PoolTimedOut).is_retryable());
+    assert!(!Error::from(sqlx::Error::RowNotFound).is_retryable());
+    assert!(!Error::from(InnerError::UnknownOutboxKind(String::from("poke"))).is_retryable());
+}
+
+#[test]
+fn json_errors_keep_their_source() {
+    let err = Error::from(serde_json::from_str::<u64>("nope").unwrap_err());
+    assert!(matches!(err.error, InnerError::JsonError(_)));
+    assert!(err.source().unwrap().is::<serde_json::Error>());
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use chrono::{DateTime, Local, TimeZone};
+
+use sqlx::SqlitePool;
+
+use serenity::model::id::{ChannelId, InteractionId, MessageId, UserId};
+
+use ugo_ii_bot::chat::{InteractionHandle, MemoryChat};
+use ugo_ii_bot::error::InnerError;
+use ugo_ii_bot::ugocoin::account::{self, Ugocoin};
+use ugo_ii_bot::ugocoin::{audit, tip};
+use ugo_ii_bot::{digest, export, scrum, user};
+
+use common::{BOBBY, BOT_ID, EITAN, JUSTIN, KEVIN, SCRUM_CHANNEL};
+
+fn at(hour: u32, minute: u32) -> DateTime<Local> {
+    Local
+        .with_ymd_and_hms(2022, 12, 19, hour, minute, 0)
+        .single()
+        .expect("Ambiguous test time")
+}
+
+async fn pick(db: &SqlitePool, chat: &MemoryChat, message_id: MessageId, discord_id: UserId) {
+    let pick = scrum::SlotPick {
+        interaction: InteractionHandle {
+            id: InteractionId(1),
+            token: format!("pick-{}", discord_id),
+        },
+        channel_id: SCRUM_CHANNEL,
+        message_id,
+        picker_id: discord_id,
+        values: vec![String::from("10:00")],
+    };
+    scrum::on_slot_pick(db, chat, at(9, 0), &pick)
+        .await
+        .unwrap();
+}
+
+// A database with some money moved around, so the ledger has something to check, and a row in
+// every table an export carries.
+async fn populated_db() -> SqlitePool {
+    let db = common::test_db().await;
+    let chat = MemoryChat::new(BOT_ID);
+    let now = at(12, 0);
+
+    account::mint(&db, now, Ugocoin::from_ugocoin(100), &String::from("Seed"))
+        .await
+        .unwrap();
+    let eitan = account::get_user_account(&db, &common::get_user(&db, EITAN).await)
+        .await
+        .unwrap();
+    let kevin = account::get_user_account(&db, &common::get_user(&db, KEVIN).await)
+        .await
+        .unwrap();
+    account::credit_account(
+        &db,
+        now,
+        &eitan,
+        Ugocoin::from_ugocoin(30),
+        &String::from("Pay"),
+    )
+    .await
+    .unwrap();
+    let eitan = account::get_user_account(&db, &common::get_user(&db, EITAN).await)
+        .await
+        .unwrap();
+    account::transfer(
+        &db,
+        now,
+        &eitan,
+        &kevin,
+        Ugocoin::from_ugocents(1250),
+        &String::from("Tip"),
+    )
+    .await
+    .unwrap();
+
+    // A slot poll everyone answers, which closes it and pays out rewards.
+    scrum::poll_scrum(
+        &db,
+        &chat,
+        at(3, 0),
+        SCRUM_CHANNEL,
+        &scrum::ScrumPoll::parse("10:00,14:00"),
+    )
+    .await
+    .unwrap();
+    let poll = scrum::get_scrum_for_date(&db, at(3, 0))
+        .await
+        .unwrap()
+        .unwrap();
+    for discord_id in [EITAN, KEVIN, BOBBY, JUSTIN] {
+        pick(&db, &chat, poll.message_id().unwrap(), discord_id).await;
+    }
+
+    // One tip that sticks and one that's refunded.
+    let eitan = common::get_user(&db, EITAN).await;
+    let kevin = common::get_user(&db, KEVIN).await;
+    tip::tip_message(&db, at(13, 0), MessageId(500), &eitan, &kevin)
+        .await
+        .unwrap();
+    tip::tip_message(&db, at(13, 1), MessageId(501), &eitan, &kevin)
+        .await
+        .unwrap();
+    tip::refund_tip(&db, at(13, 2), MessageId(501), &eitan)
+        .await
+        .unwrap();
+
+    user::request_link(&db, now, &UserId(42), &kevin)
+        .await
+        .unwrap();
+
+    digest::run_digest(&db, at(18, 0), digest::DigestPeriod::Daily, ChannelId(200))
+        .await
+        .unwrap();
+
+    db
+}
+
+#[tokio::test]
+async fn json_and_csv_round_trip() {
+    let db = populated_db().await;
+    let original = export::export(&db, Local::now()).await.unwrap();
+    assert!(!original.tips.is_empty());
+    assert!(!original.balance_snapshots.is_empty());
+    assert!(!original.link_requests.is_empty());
+    assert!(!original.scrum_rewards.is_empty());
+    assert!(!original.scrum_slots.is_empty());
+    assert!(!original.scrum_slot_answers.is_empty());
+    assert!(!original.scrum_slot_picks.is_empty());
+
+    let json = serde_json::to_string(&original).unwrap();
+    let from_json = serde_json::from_str(&json).unwrap();
+    let imported = common::empty_db().await;
+    let summary = export::import(&imported, &from_json).await.unwrap();
+    assert_eq!(summary.total_supply, Ugocoin::from_ugocoin(100));
+    assert!(audit::audit_ledger(&imported).await.unwrap().is_clean());
+
+    let dir = std::env::temp_dir().join(format!("ugo-export-test-{}", std::process::id()));
+    export::write_csv(&original, &dir).unwrap();
+    let from_csv = export::read_csv(&dir).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let imported = common::empty_db().await;
+    export::import(&imported, &from_csv).await.unwrap();
+
+    // Both copies export exactly what went in.
+    let reexported = export::export(&imported, Local::now()).await.unwrap();
+    let mut reexported = serde_json::to_value(reexported).unwrap();
+    let mut original = serde_json::to_value(original).unwrap();
+    reexported["exported_at"] = 0.into();
+    original["exported_at"] = 0.into();
+    assert_eq!(reexported, original);
+}
+
+#[tokio::test]
+async fn import_refuses_a_ledger_that_does_not_add_up() {
+    let db = populated_db().await;
+
+    let mut tampered = export::export(&db, Local::now()).await.unwrap();
+    tampered.accounts[0].balance += 500;
+    tampered.total_supply += 500;
+
+    let imported = common::empty_db().await;
+    let err = export::import(&imported, &tampered).await.unwrap_err();
+    assert!(matches!(err.error, InnerError::ImportRejected(_)));
+
+    // Nothing from the export was committed.
+    let transactions: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM ugocoin_tx_logs")
+        .fetch_one(&imported)
+        .await
+        .unwrap();
+    assert_eq!(transactions, 0);
+}
+
+#[tokio::test]
+async fn import_refuses_a_database_with_tables() {
+    let db = populated_db().await;
+    let original = export::export(&db, Local::now()).await.unwrap();
+
+    let err = export::import(&db, &original).await.unwrap_err();
+    assert!(matches!(err.error, InnerError::ImportRejected(_)));
+}
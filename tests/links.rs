@@ -0,0 +1,69 @@
+mod common;
+
+use chrono::{DateTime, Local, TimeZone};
+
+use serenity::model::id::UserId;
+
+use ugo_ii_bot::error::InnerError;
+use ugo_ii_bot::user;
+
+use common::{get_user, test_db, EITAN, KEVIN};
+
+const ALT: UserId = UserId(42);
+
+fn now() -> DateTime<Local> {
+    Local.timestamp_opt(1_671_451_200, 0).unwrap()
+}
+
+#[tokio::test]
+async fn an_account_has_one_pending_request_at_a_time() {
+    let db = test_db().await;
+    let kevin = get_user(&db, KEVIN).await;
+    let eitan = get_user(&db, EITAN).await;
+
+    let first = user::request_link(&db, now(), &ALT, &kevin).await.unwrap();
+    let err = user::request_link(&db, now(), &ALT, &eitan)
+        .await
+        .unwrap_err();
+    assert!(matches!(err.error, InnerError::LinkRequestPending));
+    assert_eq!(user::get_pending_link_requests(&db).await.unwrap().len(), 1);
+
+    // Once it's dealt with, the account can ask again.
+    user::deny_link_request(&db, first).await.unwrap();
+    let second = user::request_link(&db, now(), &ALT, &eitan).await.unwrap();
+    let request = user::approve_link_request(&db, second).await.unwrap();
+    assert_eq!(request.user_id, eitan.id);
+    assert_eq!(get_user(&db, ALT).await.id, eitan.id);
+}
+
+#[tokio::test]
+async fn approving_an_account_that_registered_meanwhile_is_rejected() {
+    let db = test_db().await;
+    let kevin = get_user(&db, KEVIN).await;
+
+    let request = user::request_link(&db, now(), &ALT, &kevin).await.unwrap();
+    user::register_user(&db, &ALT, "Alt").await.unwrap();
+
+    let err = user::approve_link_request(&db, request).await.unwrap_err();
+    assert!(matches!(err.error, InnerError::AlreadyRegistered));
+
+    // The request stays pending, for an admin to deny.
+    assert_eq!(user::get_pending_link_requests(&db).await.unwrap().len(), 1);
+    assert_eq!(get_user(&db, ALT).await.display_name, "Alt");
+}
+
+#[tokio::test]
+async fn registering_twice_at_once_reports_already_registered() {
+    let db = test_db().await;
+
+    let (first, second) = tokio::join!(
+        user::register_user(&db, &ALT, "Alt"),
+        user::register_user(&db, &ALT, "Alt again"),
+    );
+    let errors: Vec<_> = [first, second]
+        .into_iter()
+        .filter_map(Result::err)
+        .collect();
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(errors[0].error, InnerError::AlreadyRegistered));
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use ugo_ii_bot::db::{self, MigrationMode};
+use ugo_ii_bot::error::InnerError;
+
+#[tokio::test]
+async fn fresh_database_needs_migrating() {
+    let db = common::empty_db().await;
+
+    let err = db::prepare(&db, MigrationMode::Check).await.unwrap_err();
+    assert!(matches!(err.error, InnerError::PendingMigrations(_)));
+
+    db::prepare(&db, MigrationMode::Auto).await.unwrap();
+    db::prepare(&db, MigrationMode::Check).await.unwrap();
+
+    // The schema is usable straight away.
+    let users: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert!(users > 0);
+}
+
+#[tokio::test]
+async fn refuses_schema_newer_than_binary() {
+    let db = common::test_db().await;
+
+    sqlx::query(
+        "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+        VALUES (99991231235959, 'from the future', true, x'00', 0)",
+    )
+    .execute(&db)
+    .await
+    .unwrap();
+
+    for mode in [MigrationMode::Check, MigrationMode::Auto] {
+        let err = db::prepare(&db, mode).await.unwrap_err();
+        assert!(matches!(
+            err.error,
+            InnerError::SchemaTooNew(99991231235959)
+        ));
+    }
+}
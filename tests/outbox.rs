@@ -0,0 +1,219 @@
+mod common;
+
+use chrono::{DateTime, Duration, Local, TimeZone};
+
+use serde_json::json;
+
+use sqlx::SqlitePool;
+
+use serenity::model::id::ChannelId;
+
+use ugo_ii_bot::chat::MemoryChat;
+use ugo_ii_bot::command::run_command;
+use ugo_ii_bot::error::InnerError;
+use ugo_ii_bot::outbox::{self, OutboxEntry, OutboxMessage, MAX_ATTEMPTS};
+
+use common::{command_interaction, make_admin, test_db, BOT_ID, INTERACTION_TOKEN, KEVIN};
+
+const GENERAL: ChannelId = ChannelId(400);
+const RANDOM: ChannelId = ChannelId(401);
+
+fn start() -> DateTime<Local> {
+    Local.timestamp_opt(1_671_451_200, 0).unwrap()
+}
+
+fn send(channel_id: ChannelId, content: &str) -> OutboxMessage {
+    OutboxMessage::Send {
+        channel_id,
+        content: content.to_string(),
+    }
+}
+
+async fn queue(db: &SqlitePool, message: &OutboxMessage) {
+    outbox::enqueue(db, start(), message)
+        .await
+        .expect("Failed to enqueue message");
+}
+
+async fn undelivered(db: &SqlitePool) -> Vec<OutboxEntry> {
+    outbox::get_undelivered(db)
+        .await
+        .expect("Failed to fetch undelivered messages")
+}
+
+fn contents(chat: &MemoryChat, channel_id: ChannelId) -> Vec<String> {
+    chat.messages(channel_id)
+        .into_iter()
+        .map(|message| message.content)
+        .collect()
+}
+
+#[tokio::test]
+async fn failed_deliveries_back_off() {
+    let db = test_db().await;
+    let chat = MemoryChat::new(BOT_ID);
+    chat.break_channel(GENERAL, true);
+    queue(&db, &send(GENERAL, "Hello")).await;
+
+    let mut now = start();
+    let mut waits = Vec::new();
+    for _ in 0..8 {
+        outbox::flush(&db, &chat, now).await.unwrap();
+        let entry = &undelivered(&db).await[0];
+        let next_attempt_at = Local.timestamp_opt(entry.next_attempt_at, 0).unwrap();
+        waits.push((next_attempt_at - now).num_seconds());
+
+        // Nothing is tried again before the wait is up.
+        outbox::flush(&db, &chat, next_attempt_at - Duration::seconds(1))
+            .await
+            .unwrap();
+        assert_eq!(undelivered(&db).await[0].attempts, entry.attempts);
+
+        now = next_attempt_at;
+    }
+
+    assert_eq!(waits, [60, 120, 240, 480, 960, 1920, 3600, 3600]);
+    let entry = &undelivered(&db).await[0];
+    assert_eq!(entry.attempts, 8);
+    assert!(entry.last_error.is_some());
+    assert!(entry.failed_at.is_none());
+
+    chat.restore_channel(GENERAL);
+    outbox::flush(&db, &chat, now).await.unwrap();
+    assert!(undelivered(&db).await.is_empty());
+    assert_eq!(contents(&chat, GENERAL), ["Hello"]);
+}
+
+#[tokio::test]
+async fn deliveries_are_given_up_on_after_enough_attempts() {
+    let db = test_db().await;
+    let chat = MemoryChat::new(BOT_ID);
+    chat.break_channel(GENERAL, true);
+    queue(&db, &send(GENERAL, "Hello")).await;
+
+    let mut now = start();
+    for _ in 1..MAX_ATTEMPTS {
+        outbox::flush(&db, &chat, now).await.unwrap();
+        now = Local
+            .timestamp_opt(undelivered(&db).await[0].next_attempt_at, 0)
+            .unwrap();
+    }
+
+    // The last attempt gives up, which the flush reports.
+    assert!(outbox::flush(&db, &chat, now).await.is_err());
+    let entry = &undelivered(&db).await[0];
+    assert_eq!(entry.attempts, MAX_ATTEMPTS);
+    assert_eq!(entry.failed_at, Some(now.timestamp()));
+
+    // Given up on means it's not tried again, even once the channel works.
+    chat.restore_channel(GENERAL);
+    outbox::flush(&db, &chat, now + Duration::days(1))
+        .await
+        .unwrap();
+    assert!(chat.messages(GENERAL).is_empty());
+}
+
+#[tokio::test]
+async fn failures_that_wont_go_away_are_given_up_on_at_once() {
+    let db = test_db().await;
+    let chat = MemoryChat::new(BOT_ID);
+    chat.break_channel(GENERAL, false);
+    queue(&db, &send(GENERAL, "Hello")).await;
+
+    assert!(outbox::flush(&db, &chat, start()).await.is_err());
+    let entry = &undelivered(&db).await[0];
+    assert_eq!(entry.attempts, 1);
+    assert!(entry.failed_at.is_some());
+}
+
+#[tokio::test]
+async fn a_blocked_channel_holds_up_only_itself() {
+    let db = test_db().await;
+    let chat = MemoryChat::new(BOT_ID);
+    chat.break_channel(GENERAL, true);
+    queue(&db, &send(GENERAL, "First")).await;
+    queue(&db, &send(RANDOM, "Elsewhere")).await;
+    queue(&db, &send(GENERAL, "Second")).await;
+
+    outbox::flush(&db, &chat, start()).await.unwrap();
+    assert_eq!(contents(&chat, RANDOM), ["Elsewhere"]);
+
+    // The second message waits behind the first instead of overtaking it.
+    let entries = undelivered(&db).await;
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].attempts, 1);
+    assert_eq!(entries[1].attempts, 0);
+
+    chat.restore_channel(GENERAL);
+    outbox::flush(&db, &chat, start() + Duration::minutes(1))
+        .await
+        .unwrap();
+    assert_eq!(contents(&chat, GENERAL), ["First", "Second"]);
+}
+
+#[tokio::test]
+async fn retrying_puts_a_given_up_entry_back_in_the_queue() {
+    let db = test_db().await;
+    let chat = MemoryChat::new(BOT_ID);
+    make_admin(&db, KEVIN).await;
+    chat.break_channel(GENERAL, false);
+    queue(&db, &send(GENERAL, "Hello")).await;
+    outbox::flush(&db, &chat, start()).await.unwrap_err();
+    let id = undelivered(&db).await[0].id;
+
+    let retry = command_interaction(
+        KEVIN,
+        "outbox",
+        json!([{
+            "name": "retry",
+            "type": 1,
+            "options": [{"name": "id", "type": 4, "value": id}],
+        }]),
+    );
+    let now = start() + Duration::hours(1);
+    run_command(&db, &chat, now, &retry).await.unwrap();
+    assert_eq!(
+        chat.interaction_replies(INTERACTION_TOKEN)[0].content,
+        format!("Outbox item #{} will be retried shortly.", id)
+    );
+
+    let entry = &undelivered(&db).await[0];
+    assert_eq!(entry.attempts, 0);
+    assert_eq!(entry.next_attempt_at, now.timestamp());
+    assert!(entry.failed_at.is_none());
+
+    chat.restore_channel(GENERAL);
+    outbox::flush(&db, &chat, now).await.unwrap();
+    assert_eq!(contents(&chat, GENERAL), ["Hello"]);
+
+    // Only entries we gave up on can be retried.
+    let err = run_command(&db, &chat, now, &retry).await.unwrap_err();
+    assert!(matches!(err.error, InnerError::OutboxItemNotFound));
+}
+
+#[tokio::test]
+async fn a_dedupe_key_is_only_ever_queued_once() {
+    let db = test_db().await;
+    let chat = MemoryChat::new(BOT_ID);
+    let message = send(GENERAL, "Scrum time");
+
+    for _ in 0..2 {
+        outbox::enqueue_with(&db, start(), &message, Some("scrum 2022-12-19"), None)
+            .await
+            .unwrap();
+    }
+    assert_eq!(undelivered(&db).await.len(), 1);
+
+    // Not even after the first one went out.
+    outbox::flush(&db, &chat, start()).await.unwrap();
+    outbox::enqueue_with(&db, start(), &message, Some("scrum 2022-12-19"), None)
+        .await
+        .unwrap();
+    assert!(undelivered(&db).await.is_empty());
+
+    outbox::enqueue_with(&db, start(), &message, Some("scrum 2022-12-20"), None)
+        .await
+        .unwrap();
+    outbox::flush(&db, &chat, start()).await.unwrap();
+    assert_eq!(contents(&chat, GENERAL), ["Scrum time", "Scrum time"]);
+}
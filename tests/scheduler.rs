@@ -0,0 +1,297 @@
+// Trigger times and the scheduler's run bookkeeping, in New York so the triggers cross DST in
+// both directions. The time zone is process wide, so this binary sets it once in main.
+mod common;
+
+use chrono::{DateTime, Duration, Local, TimeZone, Utc};
+
+use sqlx::SqlitePool;
+
+use serenity::model::id::ChannelId;
+
+use ugo_ii_bot::chat::MemoryChat;
+use ugo_ii_bot::jobs::scheduler::{self, JobOutcome};
+use ugo_ii_bot::jobs::{JobChannels, Scheduler, Trigger};
+
+use common::{test_db, BOT_ID, SCRUM_CHANNEL};
+
+const CHANNELS: JobChannels = JobChannels {
+    scrum: SCRUM_CHANNEL,
+    digest: ChannelId(200),
+};
+
+fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+    Local
+        .with_ymd_and_hms(year, month, day, hour, minute, 0)
+        .single()
+        .expect("Ambiguous test time")
+}
+
+// For local times that happen twice.
+fn utc(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+    Utc.with_ymd_and_hms(2022, month, day, hour, minute, 0)
+        .unwrap()
+        .with_timezone(&Local)
+}
+
+async fn tick(
+    scheduler: &Scheduler,
+    db: &SqlitePool,
+    chat: &MemoryChat,
+    now: DateTime<Local>,
+) -> Vec<JobOutcome> {
+    scheduler
+        .tick(db, chat, now, &CHANNELS)
+        .await
+        .expect("Failed to tick scheduler")
+}
+
+fn jobs(outcomes: &[JobOutcome]) -> Vec<&str> {
+    outcomes.iter().map(|outcome| outcome.job).collect()
+}
+
+async fn attempts(db: &SqlitePool, job_name: &str) -> i64 {
+    scheduler::get_last_run(db, job_name)
+        .await
+        .expect("Failed to fetch last run")
+        .expect("Job never ran")
+        .attempts
+}
+
+async fn daily_triggers_fire_once_a_day() {
+    let trigger = Trigger::DailyAt {
+        hour: 17,
+        minute: 0,
+    };
+
+    assert_eq!(
+        trigger.latest(at(2022, 12, 19, 16, 59)),
+        at(2022, 12, 18, 17, 0)
+    );
+    assert_eq!(
+        trigger.latest(at(2022, 12, 19, 17, 0)),
+        at(2022, 12, 19, 17, 0)
+    );
+    assert_eq!(
+        trigger.next(at(2022, 12, 19, 16, 59)),
+        at(2022, 12, 19, 17, 0)
+    );
+    assert_eq!(
+        trigger.next(at(2022, 12, 19, 17, 0)),
+        at(2022, 12, 20, 17, 0)
+    );
+
+    // Across the year boundary too.
+    assert_eq!(trigger.next(at(2022, 12, 31, 18, 0)), at(2023, 1, 1, 17, 0));
+}
+
+async fn minute_triggers_line_up_with_the_clock() {
+    let trigger = Trigger::EveryMinutes(5);
+    let now = at(2022, 12, 19, 12, 7) + Duration::seconds(30);
+
+    assert_eq!(trigger.latest(now), at(2022, 12, 19, 12, 5));
+    assert_eq!(trigger.next(now), at(2022, 12, 19, 12, 10));
+    assert_eq!(
+        trigger.latest(at(2022, 12, 19, 12, 10)),
+        at(2022, 12, 19, 12, 10)
+    );
+}
+
+async fn skipped_trigger_times_fire_when_the_clocks_jump() {
+    // 2:30 doesn't exist on 2023-03-12 in New York; the clocks go from 2:00 to 3:00.
+    let trigger = Trigger::DailyAt {
+        hour: 2,
+        minute: 30,
+    };
+
+    assert_eq!(trigger.next(at(2023, 3, 12, 1, 0)), at(2023, 3, 12, 3, 30));
+    assert_eq!(
+        trigger.latest(at(2023, 3, 12, 12, 0)),
+        at(2023, 3, 12, 3, 30)
+    );
+    assert_eq!(
+        trigger.latest(at(2023, 3, 12, 3, 0)),
+        at(2023, 3, 11, 2, 30)
+    );
+    assert_eq!(trigger.next(at(2023, 3, 12, 12, 0)), at(2023, 3, 13, 2, 30));
+}
+
+async fn repeated_trigger_times_fire_once() {
+    // 1:30 happens twice on 2022-11-06 in New York, first in EDT (05:30 UTC), then in EST.
+    let trigger = Trigger::DailyAt {
+        hour: 1,
+        minute: 30,
+    };
+    let first = utc(11, 6, 5, 30);
+    let second = utc(11, 6, 6, 30);
+
+    // Whichever of the two it picks, the other doesn't fire it again.
+    let fires = trigger.next(at(2022, 11, 6, 0, 0));
+    assert!(fires == first || fires == second);
+    assert_eq!(trigger.latest(second + Duration::minutes(1)), fires);
+    assert_eq!(trigger.next(fires), at(2022, 11, 7, 1, 30));
+    assert_eq!(
+        trigger.latest(fires - Duration::minutes(1)),
+        at(2022, 11, 5, 1, 30)
+    );
+}
+
+async fn each_trigger_time_runs_once() {
+    let db = test_db().await;
+    let chat = MemoryChat::new(BOT_ID);
+    let scheduler = Scheduler::new();
+    let now = at(2022, 12, 19, 17, 0);
+
+    let outcomes = tick(&scheduler, &db, &chat, now).await;
+    assert_eq!(
+        jobs(&outcomes),
+        ["open_scrum", "close_scrum", "digest", "outbox", "backup"]
+    );
+    assert!(outcomes.iter().all(|outcome| outcome.result.is_ok()));
+
+    assert!(tick(&scheduler, &db, &chat, now).await.is_empty());
+    assert_eq!(
+        jobs(&tick(&scheduler, &db, &chat, now + Duration::minutes(1)).await),
+        ["outbox"]
+    );
+
+    // A restarted bot doesn't run the day's jobs again.
+    let restarted = Scheduler::new();
+    let later = now + Duration::minutes(10);
+    assert_eq!(jobs(&tick(&restarted, &db, &chat, later).await), ["outbox"]);
+    assert_eq!(attempts(&db, "digest").await, 1);
+}
+
+async fn a_second_instance_stays_idle_while_the_lease_is_held() {
+    let db = test_db().await;
+    let chat = MemoryChat::new(BOT_ID);
+    let first = Scheduler::new();
+    let second = Scheduler::new();
+    let now = at(2022, 12, 19, 12, 0);
+
+    assert!(!tick(&first, &db, &chat, now).await.is_empty());
+    let holder = scheduler::lease_holder(&db, now).await.unwrap();
+    assert!(holder.is_some());
+
+    assert!(tick(&second, &db, &chat, now + Duration::minutes(1))
+        .await
+        .is_empty());
+    assert!(tick(&second, &db, &chat, now + Duration::minutes(3))
+        .await
+        .is_empty());
+
+    // The first instance stopped ticking without releasing the lease, so it lapses and the
+    // second takes over.
+    let takeover = now + Duration::minutes(4);
+    assert_eq!(jobs(&tick(&second, &db, &chat, takeover).await), ["outbox"]);
+    assert_ne!(
+        scheduler::lease_holder(&db, takeover).await.unwrap(),
+        holder
+    );
+    assert!(tick(&first, &db, &chat, takeover + Duration::minutes(1))
+        .await
+        .is_empty());
+}
+
+async fn failed_runs_are_retried_with_backoff() {
+    let db = test_db().await;
+    let chat = MemoryChat::new(BOT_ID);
+    let scheduler = Scheduler::new();
+    let close_scrum = |outcomes: &[JobOutcome]| {
+        outcomes
+            .iter()
+            .find(|outcome| outcome.job == "close_scrum")
+            .map(|outcome| outcome.result.is_ok())
+    };
+
+    tick(&scheduler, &db, &chat, at(2022, 12, 19, 3, 0)).await;
+    assert_eq!(chat.messages(SCRUM_CHANNEL).len(), 1);
+
+    // Closing reads the scrum's reactions, which fails while the channel is unreachable.
+    chat.break_channel(SCRUM_CHANNEL, true);
+    let due = at(2022, 12, 19, 16, 0);
+    let at_minute = |minute| due + Duration::minutes(minute);
+    assert_eq!(
+        close_scrum(&tick(&scheduler, &db, &chat, due).await),
+        Some(false)
+    );
+    assert_eq!(
+        close_scrum(&tick(&scheduler, &db, &chat, due + Duration::seconds(30)).await),
+        None
+    );
+
+    // A minute after the first failure, then two minutes after the second.
+    assert_eq!(
+        close_scrum(&tick(&scheduler, &db, &chat, at_minute(1)).await),
+        Some(false)
+    );
+    assert_eq!(attempts(&db, "close_scrum").await, 2);
+    assert_eq!(
+        close_scrum(&tick(&scheduler, &db, &chat, at_minute(2)).await),
+        None
+    );
+
+    chat.restore_channel(SCRUM_CHANNEL);
+    assert_eq!(
+        close_scrum(&tick(&scheduler, &db, &chat, at_minute(3)).await),
+        Some(true)
+    );
+    assert_eq!(attempts(&db, "close_scrum").await, 3);
+
+    // Once it succeeds, it's done until the next trigger time.
+    assert_eq!(
+        close_scrum(&tick(&scheduler, &db, &chat, at_minute(10)).await),
+        None
+    );
+}
+
+async fn a_digest_that_fails_to_post_goes_out_later() {
+    let db = test_db().await;
+    let chat = MemoryChat::new(BOT_ID);
+    let scheduler = Scheduler::new();
+    let due = at(2022, 12, 19, 17, 0);
+
+    chat.break_channel(CHANNELS.digest, true);
+    let outcomes = tick(&scheduler, &db, &chat, due).await;
+    assert!(outcomes.iter().all(|outcome| outcome.result.is_ok()));
+    assert!(chat.messages(CHANNELS.digest).is_empty());
+
+    // The outbox keeps trying, and the day still gets exactly one digest.
+    chat.restore_channel(CHANNELS.digest);
+    for minute in 1..10 {
+        tick(&scheduler, &db, &chat, due + Duration::minutes(minute)).await;
+    }
+    assert_eq!(chat.messages(CHANNELS.digest).len(), 1);
+}
+
+fn main() {
+    common::time_zone::run_tests(
+        "America/New_York",
+        &[
+            ("daily_triggers_fire_once_a_day", || {
+                Box::pin(daily_triggers_fire_once_a_day())
+            }),
+            ("minute_triggers_line_up_with_the_clock", || {
+                Box::pin(minute_triggers_line_up_with_the_clock())
+            }),
+            ("skipped_trigger_times_fire_when_the_clocks_jump", || {
+                Box::pin(skipped_trigger_times_fire_when_the_clocks_jump())
+            }),
+            ("repeated_trigger_times_fire_once", || {
+                Box::pin(repeated_trigger_times_fire_once())
+            }),
+            ("each_trigger_time_runs_once", || {
+                Box::pin(each_trigger_time_runs_once())
+            }),
+            (
+                "a_second_instance_stays_idle_while_the_lease_is_held",
+                || Box::pin(a_second_instance_stays_idle_while_the_lease_is_held()),
+            ),
+            ("failed_runs_are_retried_with_backoff", || {
+                Box::pin(failed_runs_are_retried_with_backoff())
+            }),
+            ("a_digest_that_fails_to_post_goes_out_later", || {
+                Box::pin(a_digest_that_fails_to_post_goes_out_later())
+            }),
+        ],
+    );
+}
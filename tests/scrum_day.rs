@@ -0,0 +1,463 @@
+mod common;
+
+use chrono::{DateTime, Local, TimeZone};
+
+use sqlx::SqlitePool;
+
+use serenity::model::id::InteractionId;
+
+use ugo_ii_bot::chat::{InteractionHandle, MemoryChat};
+use ugo_ii_bot::scrum;
+use ugo_ii_bot::ugocoin::account::{self, Ugocoin};
+
+use common::{
+    fund_central_bank, get_user, test_db, BOBBY, BOT_ID, EITAN, JUSTIN, KEVIN, SCRUM_CHANNEL,
+};
+
+const CENTRAL_BANK_FUNDS: Ugocoin = Ugocoin::from_ugocoin(1000);
+
+const ACCEPT: &str = "👍";
+const DECLINE: &str = "👎";
+
+// Local times in December 2022. main pins the time zone, so these don't depend on the host's.
+fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+    Local
+        .with_ymd_and_hms(2022, 12, day, hour, minute, 0)
+        .single()
+        .expect("Ambiguous test time")
+}
+
+async fn balance(db: &SqlitePool, discord_id: serenity::model::id::UserId) -> Ugocoin {
+    let user = get_user(db, discord_id).await;
+    account::get_user_account(db, &user)
+        .await
+        .expect("Failed to fetch account")
+        .balance
+}
+
+async fn open_scrum(db: &SqlitePool, chat: &MemoryChat, day: u32) -> scrum::Scrum {
+    scrum::poll_scrum(
+        db,
+        chat,
+        at(day, 3, 0),
+        SCRUM_CHANNEL,
+        &scrum::ScrumPoll::YesNo,
+    )
+    .await
+    .expect("Failed to poll scrum");
+
+    scrum::get_scrum_for_date(db, at(day, 3, 0))
+        .await
+        .expect("Failed to fetch scrum")
+        .expect("Scrum wasn't opened")
+}
+
+async fn open_slot_poll(db: &SqlitePool, chat: &MemoryChat, day: u32) -> scrum::Scrum {
+    let poll = scrum::ScrumPoll::parse("19:30, 10:00,14:00");
+    scrum::poll_scrum(db, chat, at(day, 3, 0), SCRUM_CHANNEL, &poll)
+        .await
+        .expect("Failed to poll scrum");
+
+    scrum::get_scrum_for_date(db, at(day, 3, 0))
+        .await
+        .expect("Failed to fetch scrum")
+        .expect("Scrum wasn't opened")
+}
+
+async fn pick(
+    db: &SqlitePool,
+    chat: &MemoryChat,
+    scrum: &scrum::Scrum,
+    now: DateTime<Local>,
+    discord_id: serenity::model::id::UserId,
+    values: &[&str],
+) -> String {
+    let token = format!("pick-{}-{}", discord_id, now.timestamp());
+    let slot_pick = scrum::SlotPick {
+        interaction: InteractionHandle {
+            id: InteractionId(1),
+            token: token.clone(),
+        },
+        channel_id: SCRUM_CHANNEL,
+        message_id: scrum.message_id().unwrap(),
+        picker_id: discord_id,
+        values: values.iter().map(|value| value.to_string()).collect(),
+    };
+    scrum::on_slot_pick(db, chat, now, &slot_pick)
+        .await
+        .expect("Failed to handle pick");
+
+    chat.interaction_replies(&token)[0].content.clone()
+}
+
+async fn react(
+    db: &SqlitePool,
+    chat: &MemoryChat,
+    scrum: &scrum::Scrum,
+    now: DateTime<Local>,
+    discord_id: serenity::model::id::UserId,
+    emoji: &str,
+) {
+    let message_id = scrum.message_id().unwrap();
+    chat.add_reaction(message_id, discord_id, emoji);
+    scrum::on_scrum_react(db, chat, now, SCRUM_CHANNEL, message_id)
+        .await
+        .expect("Failed to handle react");
+}
+
+async fn scrum_is_not_opened_before_notification_time() {
+    let db = test_db().await;
+    fund_central_bank(&db, CENTRAL_BANK_FUNDS).await;
+    let chat = MemoryChat::new(BOT_ID);
+
+    scrum::poll_scrum(
+        &db,
+        &chat,
+        at(19, 2, 59),
+        SCRUM_CHANNEL,
+        &scrum::ScrumPoll::YesNo,
+    )
+    .await
+    .unwrap();
+
+    assert!(chat.messages(SCRUM_CHANNEL).is_empty());
+    assert!(scrum::get_scrum_for_date(&db, at(19, 2, 59))
+        .await
+        .unwrap()
+        .is_none());
+}
+
+async fn scrum_opens_once_with_reactions() {
+    let db = test_db().await;
+    fund_central_bank(&db, CENTRAL_BANK_FUNDS).await;
+    let chat = MemoryChat::new(BOT_ID);
+
+    let scrum = open_scrum(&db, &chat, 19).await;
+    // Polling again the same day doesn't open a second scrum.
+    scrum::poll_scrum(
+        &db,
+        &chat,
+        at(19, 3, 1),
+        SCRUM_CHANNEL,
+        &scrum::ScrumPoll::YesNo,
+    )
+    .await
+    .unwrap();
+
+    let messages = chat.messages(SCRUM_CHANNEL);
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].content.contains("SCRUMMONS"));
+    assert_eq!(messages[0].id, scrum.message_id().unwrap());
+    assert_eq!(messages[0].reactions[ACCEPT], vec![BOT_ID]);
+    assert_eq!(messages[0].reactions[DECLINE], vec![BOT_ID]);
+    assert!(scrum.is_open);
+}
+
+async fn scrum_closes_when_everyone_answers() {
+    let db = test_db().await;
+    fund_central_bank(&db, CENTRAL_BANK_FUNDS).await;
+    let chat = MemoryChat::new(BOT_ID);
+    let scrum = open_scrum(&db, &chat, 19).await;
+
+    react(&db, &chat, &scrum, at(19, 9, 0), EITAN, ACCEPT).await;
+    react(&db, &chat, &scrum, at(19, 9, 5), KEVIN, ACCEPT).await;
+    react(&db, &chat, &scrum, at(19, 9, 10), BOBBY, ACCEPT).await;
+
+    // Still waiting on Justin.
+    let open = scrum::get_scrum_for_date(&db, at(19, 9, 10))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(open.is_open);
+    assert_eq!(chat.messages(SCRUM_CHANNEL).len(), 1);
+
+    react(&db, &chat, &scrum, at(19, 9, 15), JUSTIN, DECLINE).await;
+
+    let closed = scrum::get_scrum_for_date(&db, at(19, 9, 15))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!closed.is_open);
+
+    let messages = chat.messages(SCRUM_CHANNEL);
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].content, "Voting is closed for this scrum.");
+    assert!(messages[1]
+        .content
+        .contains("SCRUM POSSIBLE: 3/4 available"));
+    assert!(messages[1].content.contains("Justin"));
+
+    // Everyone who answered, either way, keeps their streak and gets paid.
+    for discord_id in [EITAN, KEVIN, BOBBY, JUSTIN] {
+        assert_eq!(get_user(&db, discord_id).await.streak, 1);
+        assert_eq!(balance(&db, discord_id).await, Ugocoin::from_ugocoin(1));
+    }
+
+    let central = account::get_central_bank_account(&db).await.unwrap();
+    assert_eq!(central.balance, Ugocoin::from_ugocoin(1000 - 4));
+}
+
+async fn reacts_after_close_change_nothing() {
+    let db = test_db().await;
+    fund_central_bank(&db, CENTRAL_BANK_FUNDS).await;
+    let chat = MemoryChat::new(BOT_ID);
+    let scrum = open_scrum(&db, &chat, 19).await;
+
+    for discord_id in [EITAN, KEVIN, BOBBY, JUSTIN] {
+        react(&db, &chat, &scrum, at(19, 10, 0), discord_id, DECLINE).await;
+    }
+    assert!(chat.messages(SCRUM_CHANNEL)[1]
+        .content
+        .contains("SCRUM FAILED: 0/4 available"));
+
+    react(&db, &chat, &scrum, at(19, 11, 0), EITAN, ACCEPT).await;
+
+    assert_eq!(chat.messages(SCRUM_CHANNEL).len(), 2);
+    assert_eq!(balance(&db, EITAN).await, Ugocoin::from_ugocoin(1));
+}
+
+async fn unanswered_scrum_force_closes_and_breaks_streaks() {
+    let db = test_db().await;
+    fund_central_bank(&db, CENTRAL_BANK_FUNDS).await;
+    let chat = MemoryChat::new(BOT_ID);
+
+    // Day one: everyone answers, so everyone starts a streak.
+    let first = open_scrum(&db, &chat, 19).await;
+    for discord_id in [EITAN, KEVIN, BOBBY, JUSTIN] {
+        react(&db, &chat, &first, at(19, 9, 0), discord_id, ACCEPT).await;
+    }
+
+    // Day two: Bobby and Justin never answer.
+    let second = open_scrum(&db, &chat, 20).await;
+    react(&db, &chat, &second, at(20, 9, 0), EITAN, ACCEPT).await;
+    react(&db, &chat, &second, at(20, 9, 0), KEVIN, DECLINE).await;
+
+    // Nothing happens until 4 PM.
+    scrum::poll_scrum(
+        &db,
+        &chat,
+        at(20, 15, 59),
+        SCRUM_CHANNEL,
+        &scrum::ScrumPoll::YesNo,
+    )
+    .await
+    .unwrap();
+    assert!(
+        scrum::get_scrum_for_date(&db, at(20, 15, 59))
+            .await
+            .unwrap()
+            .unwrap()
+            .is_open
+    );
+
+    scrum::poll_scrum(
+        &db,
+        &chat,
+        at(20, 16, 0),
+        SCRUM_CHANNEL,
+        &scrum::ScrumPoll::YesNo,
+    )
+    .await
+    .unwrap();
+    assert!(
+        !scrum::get_scrum_for_date(&db, at(20, 16, 0))
+            .await
+            .unwrap()
+            .unwrap()
+            .is_open
+    );
+
+    let close_message = chat.messages(SCRUM_CHANNEL).last().unwrap().content.clone();
+    assert!(close_message.contains("SCRUM FAILED: 1/4 available"));
+
+    assert_eq!(get_user(&db, EITAN).await.streak, 2);
+    assert_eq!(get_user(&db, KEVIN).await.streak, 2);
+    assert_eq!(get_user(&db, BOBBY).await.streak, 0);
+    assert_eq!(get_user(&db, JUSTIN).await.streak, 0);
+
+    assert_eq!(balance(&db, EITAN).await, Ugocoin::from_ugocoin(2));
+    assert_eq!(balance(&db, KEVIN).await, Ugocoin::from_ugocoin(2));
+    assert_eq!(balance(&db, BOBBY).await, Ugocoin::from_ugocoin(1));
+    assert_eq!(balance(&db, JUSTIN).await, Ugocoin::from_ugocoin(1));
+}
+
+async fn yesterdays_scrum_ignores_reacts() {
+    let db = test_db().await;
+    fund_central_bank(&db, CENTRAL_BANK_FUNDS).await;
+    let chat = MemoryChat::new(BOT_ID);
+    let scrum = open_scrum(&db, &chat, 19).await;
+
+    for discord_id in [EITAN, KEVIN, BOBBY, JUSTIN] {
+        react(&db, &chat, &scrum, at(20, 9, 0), discord_id, ACCEPT).await;
+    }
+
+    assert_eq!(chat.messages(SCRUM_CHANNEL).len(), 1);
+    assert_eq!(get_user(&db, EITAN).await.streak, 0);
+}
+
+async fn closing_twice_pays_out_once() {
+    let db = test_db().await;
+    fund_central_bank(&db, CENTRAL_BANK_FUNDS).await;
+    let chat = MemoryChat::new(BOT_ID);
+    let scrum = open_scrum(&db, &chat, 21).await;
+
+    for discord_id in [EITAN, KEVIN, BOBBY, JUSTIN] {
+        chat.add_reaction(scrum.message_id().unwrap(), discord_id, ACCEPT);
+    }
+    let reactions =
+        scrum::parse_scrum_reactions(&db, &chat, SCRUM_CHANNEL, scrum.message_id().unwrap())
+            .await
+            .unwrap();
+
+    // Both hold the scrum as it was before either closed it, like a react racing the force close.
+    for _ in 0..2 {
+        let status = scrum::scrum_status(&reactions);
+        scrum::close_scrum(
+            &db,
+            &chat,
+            at(21, 16, 0),
+            &scrum,
+            &reactions,
+            SCRUM_CHANNEL,
+            status,
+        )
+        .await
+        .unwrap();
+    }
+
+    for discord_id in [EITAN, KEVIN, BOBBY, JUSTIN] {
+        assert_eq!(get_user(&db, discord_id).await.streak, 1);
+        assert_eq!(balance(&db, discord_id).await, Ugocoin::from_ugocoin(1));
+    }
+    assert_eq!(chat.messages(SCRUM_CHANNEL).len(), 2);
+}
+
+async fn slot_poll_closes_on_the_most_popular_slot() {
+    let db = test_db().await;
+    fund_central_bank(&db, CENTRAL_BANK_FUNDS).await;
+    let chat = MemoryChat::new(BOT_ID);
+    let scrum = open_slot_poll(&db, &chat, 19).await;
+
+    let message = chat.message(scrum.message_id().unwrap()).unwrap();
+    let options: Vec<String> = message
+        .menu
+        .unwrap()
+        .options
+        .into_iter()
+        .map(|option| option.value)
+        .collect();
+    assert_eq!(options, ["10:00", "14:00", "19:30", scrum::NO_SLOT_VALUE]);
+    assert!(message.reactions.is_empty());
+
+    let reply = pick(&db, &chat, &scrum, at(19, 9, 0), EITAN, &["10:00", "14:00"]).await;
+    assert_eq!(reply, "Got it. You can make 10:00, 14:00.");
+    pick(&db, &chat, &scrum, at(19, 9, 5), KEVIN, &["14:00"]).await;
+    pick(
+        &db,
+        &chat,
+        &scrum,
+        at(19, 9, 10),
+        BOBBY,
+        &["14:00", "19:30"],
+    )
+    .await;
+    assert!(
+        scrum::get_scrum_for_date(&db, at(19, 9, 10))
+            .await
+            .unwrap()
+            .unwrap()
+            .is_open
+    );
+
+    let reply = pick(
+        &db,
+        &chat,
+        &scrum,
+        at(19, 9, 15),
+        JUSTIN,
+        &[scrum::NO_SLOT_VALUE],
+    )
+    .await;
+    assert_eq!(reply, "Got it. You can't make any of them.");
+
+    let messages = chat.messages(SCRUM_CHANNEL);
+    assert_eq!(messages.len(), 2);
+    assert!(messages[0].menu.is_none());
+    assert!(messages[1]
+        .content
+        .contains("SCRUM POSSIBLE at 14:00: 3/4 available"));
+
+    for discord_id in [EITAN, KEVIN, BOBBY, JUSTIN] {
+        assert_eq!(get_user(&db, discord_id).await.streak, 1);
+    }
+
+    // Too late to change your mind.
+    let reply = pick(&db, &chat, &scrum, at(19, 9, 20), JUSTIN, &["14:00"]).await;
+    assert_eq!(reply, "Voting is closed for this scrum.");
+}
+
+async fn slot_poll_ties_go_to_the_earliest_slot() {
+    let db = test_db().await;
+    fund_central_bank(&db, CENTRAL_BANK_FUNDS).await;
+    let chat = MemoryChat::new(BOT_ID);
+    let scrum = open_slot_poll(&db, &chat, 19).await;
+
+    // Picking again replaces the earlier answer.
+    pick(&db, &chat, &scrum, at(19, 9, 0), EITAN, &["14:00"]).await;
+    pick(&db, &chat, &scrum, at(19, 9, 5), EITAN, &["10:00"]).await;
+    pick(&db, &chat, &scrum, at(19, 9, 10), KEVIN, &["19:30"]).await;
+
+    scrum::poll_scrum(
+        &db,
+        &chat,
+        at(19, 16, 0),
+        SCRUM_CHANNEL,
+        &scrum::ScrumPoll::YesNo,
+    )
+    .await
+    .unwrap();
+
+    let messages = chat.messages(SCRUM_CHANNEL);
+    assert_eq!(messages.len(), 2);
+    assert!(messages[1]
+        .content
+        .contains("SCRUM FAILED at 10:00: 1/4 available"));
+
+    assert_eq!(get_user(&db, KEVIN).await.streak, 1);
+    assert_eq!(get_user(&db, BOBBY).await.streak, 0);
+}
+
+fn main() {
+    common::time_zone::run_tests(
+        "UTC",
+        &[
+            ("scrum_is_not_opened_before_notification_time", || {
+                Box::pin(scrum_is_not_opened_before_notification_time())
+            }),
+            ("scrum_opens_once_with_reactions", || {
+                Box::pin(scrum_opens_once_with_reactions())
+            }),
+            ("scrum_closes_when_everyone_answers", || {
+                Box::pin(scrum_closes_when_everyone_answers())
+            }),
+            ("reacts_after_close_change_nothing", || {
+                Box::pin(reacts_after_close_change_nothing())
+            }),
+            ("unanswered_scrum_force_closes_and_breaks_streaks", || {
+                Box::pin(unanswered_scrum_force_closes_and_breaks_streaks())
+            }),
+            ("yesterdays_scrum_ignores_reacts", || {
+                Box::pin(yesterdays_scrum_ignores_reacts())
+            }),
+            ("closing_twice_pays_out_once", || {
+                Box::pin(closing_twice_pays_out_once())
+            }),
+            ("slot_poll_closes_on_the_most_popular_slot", || {
+                Box::pin(slot_poll_closes_on_the_most_popular_slot())
+            }),
+            ("slot_poll_ties_go_to_the_earliest_slot", || {
+                Box::pin(slot_poll_ties_go_to_the_earliest_slot())
+            }),
+        ],
+    );
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local, TimeZone};
+
+use sqlx::SqlitePool;
+
+use serenity::model::id::ChannelId;
+
+use ugo_ii_bot::chat::MemoryChat;
+use ugo_ii_bot::clock::SimulatedClock;
+use ugo_ii_bot::error_sink::ErrorSink;
+use ugo_ii_bot::jobs::{jobs, scheduler, JobChannels, Scheduler};
+use ugo_ii_bot::shutdown::Shutdown;
+
+// Records every job as already run for its latest trigger time, so a tick at `now` has nothing
+// to do but take the lease.
+async fn mark_jobs_done(db: &SqlitePool, now: DateTime<Local>) {
+    for job in jobs() {
+        sqlx::query(
+            "INSERT INTO job_runs (job_name, scheduled_for, started_at, finished_at, succeeded)
+            VALUES (?, ?, ?, ?, true)",
+        )
+        .bind(job.name())
+        .bind(job.trigger().latest(now).timestamp())
+        .bind(now.timestamp())
+        .bind(now.timestamp())
+        .execute(db)
+        .await
+        .expect("Failed to record job run");
+    }
+}
+
+async fn has_lease_holder(db: &SqlitePool, now: DateTime<Local>) -> bool {
+    scheduler::lease_holder(db, now)
+        .await
+        .expect("Failed to fetch lease holder")
+        .is_some()
+}
+
+#[tokio::test]
+async fn stopping_the_scheduler_releases_the_lease() {
+    let db = common::test_db().await;
+    let now = Local.timestamp_opt(1_671_451_230, 0).unwrap();
+    mark_jobs_done(&db, now).await;
+    let scheduler = Arc::new(Scheduler::new());
+
+    let started = scheduler.clone().start(
+        db.clone(),
+        MemoryChat::new(common::BOT_ID),
+        Arc::new(SimulatedClock::new(now)),
+        Arc::new(ErrorSink::new(ChannelId(300))),
+        JobChannels {
+            scrum: common::SCRUM_CHANNEL,
+            digest: ChannelId(200),
+        },
+    );
+    assert!(started);
+
+    // The first tick happens straight away and takes the lease.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !has_lease_holder(&db, now).await {
+        assert!(
+            Instant::now() < deadline,
+            "The scheduler never took the lease"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    assert!(scheduler.stop(Duration::from_secs(10)).await);
+    assert!(!has_lease_holder(&db, now).await);
+
+    let runs: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM job_runs")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(runs, jobs().len() as i64);
+}
+
+#[tokio::test]
+async fn draining_waits_for_running_events() {
+    let shutdown = Arc::new(Shutdown::new());
+
+    let running = shutdown.clone();
+    let handler = tokio::spawn(async move {
+        let _in_flight = running.enter().unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert!(shutdown.drain(Duration::from_secs(10)).await);
+    assert!(handler.is_finished());
+    assert!(shutdown.enter().is_none());
+}
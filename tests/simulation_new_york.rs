@@ -0,0 +1,219 @@
+// Simulated scrum days in New York, which has a DST transition in each direction. The time zone
+// is process wide, so this binary sets it once in main and runs its tests there.
+mod common;
+
+use chrono::{Duration, Local, NaiveDate, TimeZone, Timelike};
+
+use serenity::model::id::UserId;
+
+use ugo_ii_bot::clock::Clock;
+
+use common::simulation::{
+    date, expected_outcome, local, Answer, ScriptedAnswer, Simulation, CENTRAL_BANK_FUNDS,
+    DIGEST_CHANNEL,
+};
+use common::{BOBBY, EITAN, JUSTIN, KEVIN};
+
+const USERS: [UserId; 4] = [EITAN, KEVIN, BOBBY, JUSTIN];
+const STEP_MINUTES: i64 = 5;
+
+fn days(first: NaiveDate, count: i64) -> Vec<NaiveDate> {
+    (0..count).map(|day| first + Duration::days(day)).collect()
+}
+
+fn answer(
+    scrum_date: NaiveDate,
+    hour: u32,
+    minute: u32,
+    user: UserId,
+    answer: Answer,
+) -> ScriptedAnswer {
+    ScriptedAnswer {
+        scrum_date,
+        at: local(scrum_date, hour, minute),
+        user,
+        answer,
+    }
+}
+
+// A varied but repeatable month: Eitan never misses, the others skip now and then, and Kevin
+// sometimes only answers after the scrum has closed.
+fn scripted_month(days: &[NaiveDate]) -> Vec<ScriptedAnswer> {
+    let mut script = Vec::new();
+
+    for (day, scrum_date) in days.iter().enumerate() {
+        for (i, user) in USERS.iter().enumerate() {
+            if i > 0 && (day + i) % 5 == 0 {
+                continue;
+            }
+
+            let response = if (day + 2 * i) % 3 == 0 {
+                Answer::Decline
+            } else {
+                Answer::Accept
+            };
+
+            let (hour, minute) = if i == 1 && day % 6 == 0 {
+                (17, 30)
+            } else {
+                (8 + i as u32, 7 * i as u32)
+            };
+
+            script.push(answer(*scrum_date, hour, minute, *user, response));
+        }
+    }
+
+    script
+}
+
+async fn assert_matches_model(sim: &Simulation, days: &[NaiveDate], script: &[ScriptedAnswer]) {
+    let expected = expected_outcome(&USERS, days, script);
+    for user in USERS {
+        let expected = expected[&user];
+        assert_eq!(
+            sim.streak(user).await,
+            expected.streak,
+            "streak for {}",
+            user
+        );
+        assert_eq!(
+            sim.balance(user).await,
+            expected.balance,
+            "balance for {}",
+            user
+        );
+    }
+
+    // Rewards only ever move coins out of the central bank.
+    assert_eq!(sim.total_balance().await, CENTRAL_BANK_FUNDS);
+}
+
+fn scrum_dates(days: &[NaiveDate]) -> Vec<String> {
+    days.iter()
+        .map(|day| day.format("%Y-%m-%d").to_string())
+        .collect()
+}
+
+async fn four_weeks_across_fall_back() {
+    // Clocks go back an hour at 2:00 on 2022-11-06.
+    let days = days(date(2022, 10, 24), 28);
+    let script = scripted_month(&days);
+
+    let mut sim = Simulation::new(local(days[0], 0, 0)).await;
+    sim.add_script(&script);
+    sim.run_until(
+        local(date(2022, 11, 21), 0, 0),
+        Duration::minutes(STEP_MINUTES),
+    )
+    .await;
+
+    assert_eq!(sim.scrum_dates().await, scrum_dates(&days));
+    assert_matches_model(&sim, &days, &script).await;
+    assert_eq!(sim.streak(EITAN).await, 28);
+
+    // One daily digest per day, including the 25 hour one.
+    assert_eq!(sim.chat.messages(DIGEST_CHANNEL).len(), days.len());
+
+    // Rewards are logged on the day they were earned, in local time.
+    let rewards: Vec<(String, i64)> = sqlx::query_as(
+        "SELECT memo, tx_time FROM ugocoin_tx_logs WHERE memo LIKE 'Scrum reward for %'",
+    )
+    .fetch_all(&sim.db)
+    .await
+    .unwrap();
+    assert!(!rewards.is_empty());
+    for (memo, tx_time) in rewards {
+        let logged = Local.timestamp_opt(tx_time, 0).unwrap();
+        assert_eq!(
+            memo,
+            format!("Scrum reward for {}", logged.format("%Y-%m-%d"))
+        );
+    }
+}
+
+async fn scrum_opens_after_spring_forward() {
+    // Clocks skip from 2:00 to 3:00 on 2023-03-12.
+    let days = days(date(2023, 3, 11), 3);
+    let script: Vec<ScriptedAnswer> = USERS
+        .iter()
+        .map(|user| answer(days[1], 9, 0, *user, Answer::Accept))
+        .collect();
+
+    let mut sim = Simulation::new(local(days[0], 0, 0)).await;
+    sim.add_script(&script);
+
+    sim.run_until(local(days[1], 1, 55), Duration::minutes(STEP_MINUTES))
+        .await;
+    assert_eq!(sim.scrum_dates().await, scrum_dates(&days[..1]));
+
+    // Five minutes later it's already 3:00, which is when the scrum goes out.
+    let now = sim.clock.now() + Duration::minutes(STEP_MINUTES);
+    assert_eq!((now.hour(), now.minute()), (3, 0));
+    sim.run_until(now, Duration::minutes(STEP_MINUTES)).await;
+    assert_eq!(sim.scrum_dates().await, scrum_dates(&days[..2]));
+
+    sim.run_until(
+        local(date(2023, 3, 14), 0, 0),
+        Duration::minutes(STEP_MINUTES),
+    )
+    .await;
+    assert_eq!(sim.scrum_dates().await, scrum_dates(&days));
+    assert_matches_model(&sim, &days, &script).await;
+}
+
+async fn answers_around_midnight_only_count_for_open_scrums() {
+    let days = days(date(2022, 12, 5), 2);
+    let script = vec![
+        answer(days[0], 10, 0, EITAN, Answer::Accept),
+        answer(days[0], 10, 0, KEVIN, Answer::Accept),
+        answer(days[0], 10, 0, BOBBY, Answer::Decline),
+        // Too late: the scrum force closed at 16:00.
+        answer(days[0], 23, 59, JUSTIN, Answer::Accept),
+        // Yesterday's scrum, just after midnight.
+        ScriptedAnswer {
+            scrum_date: days[0],
+            at: local(days[1], 0, 1),
+            user: JUSTIN,
+            answer: Answer::Accept,
+        },
+        // Today's scrum hasn't gone out yet.
+        answer(days[1], 0, 30, BOBBY, Answer::Accept),
+        // Right as it goes out.
+        answer(days[1], 3, 0, EITAN, Answer::Accept),
+        answer(days[1], 15, 55, KEVIN, Answer::Decline),
+    ];
+
+    let mut sim = Simulation::new(local(days[0], 0, 0)).await;
+    sim.add_script(&script);
+
+    sim.run_until(local(days[1], 2, 55), Duration::minutes(STEP_MINUTES))
+        .await;
+    assert_eq!(sim.scrum_dates().await, scrum_dates(&days[..1]));
+
+    sim.run_until(local(days[1], 23, 55), Duration::minutes(STEP_MINUTES))
+        .await;
+    assert_eq!(sim.scrum_dates().await, scrum_dates(&days));
+    assert_matches_model(&sim, &days, &script).await;
+
+    assert_eq!(sim.streak(EITAN).await, 2);
+    assert_eq!(sim.streak(KEVIN).await, 2);
+    assert_eq!(sim.streak(BOBBY).await, 0);
+    assert_eq!(sim.streak(JUSTIN).await, 0);
+}
+
+fn main() {
+    common::time_zone::run_tests(
+        "America/New_York",
+        &[
+            ("four_weeks_across_fall_back", || {
+                Box::pin(four_weeks_across_fall_back())
+            }),
+            ("scrum_opens_after_spring_forward", || {
+                Box::pin(scrum_opens_after_spring_forward())
+            }),
+            ("answers_around_midnight_only_count_for_open_scrums", || {
+                Box::pin(answers_around_midnight_only_count_for_open_scrums())
+            }),
+        ],
+    );
+}
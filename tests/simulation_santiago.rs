@@ -0,0 +1,139 @@
+// Simulated scrum days in Santiago, where DST starts at midnight, so some days have no 00:00 and
+// the day it ends repeats the last hour before midnight. The time zone is process wide, so this
+// binary sets it once in main and runs its tests there.
+mod common;
+
+use chrono::{Duration, NaiveDate};
+
+use serenity::model::id::UserId;
+
+use common::simulation::{
+    date, expected_outcome, local, Answer, ScriptedAnswer, Simulation, CENTRAL_BANK_FUNDS,
+    DIGEST_CHANNEL,
+};
+use common::{BOBBY, EITAN, JUSTIN, KEVIN};
+
+const USERS: [UserId; 4] = [EITAN, KEVIN, BOBBY, JUSTIN];
+const STEP_MINUTES: i64 = 5;
+
+fn days(first: NaiveDate, count: i64) -> Vec<NaiveDate> {
+    (0..count).map(|day| first + Duration::days(day)).collect()
+}
+
+fn answer(
+    scrum_date: NaiveDate,
+    hour: u32,
+    minute: u32,
+    user: UserId,
+    answer: Answer,
+) -> ScriptedAnswer {
+    ScriptedAnswer {
+        scrum_date,
+        at: local(scrum_date, hour, minute),
+        user,
+        answer,
+    }
+}
+
+async fn assert_matches_model(sim: &Simulation, days: &[NaiveDate], script: &[ScriptedAnswer]) {
+    let expected = expected_outcome(&USERS, days, script);
+    for user in USERS {
+        let expected = expected[&user];
+        assert_eq!(
+            sim.streak(user).await,
+            expected.streak,
+            "streak for {}",
+            user
+        );
+        assert_eq!(
+            sim.balance(user).await,
+            expected.balance,
+            "balance for {}",
+            user
+        );
+    }
+
+    assert_eq!(sim.total_balance().await, CENTRAL_BANK_FUNDS);
+}
+
+fn scrum_dates(days: &[NaiveDate]) -> Vec<String> {
+    days.iter()
+        .map(|day| day.format("%Y-%m-%d").to_string())
+        .collect()
+}
+
+async fn week_across_skipped_midnight() {
+    // 2022-09-11 starts at 01:00.
+    let days = days(date(2022, 9, 8), 7);
+    let mut script = Vec::new();
+    for (day, scrum_date) in days.iter().enumerate() {
+        for (i, user) in USERS.iter().enumerate() {
+            // Bobby misses the day the clocks change.
+            if *user == BOBBY && day == 3 {
+                continue;
+            }
+            script.push(answer(
+                *scrum_date,
+                3 + 2 * i as u32,
+                15,
+                *user,
+                Answer::Accept,
+            ));
+        }
+    }
+
+    let mut sim = Simulation::new(local(days[0], 0, 0)).await;
+    sim.add_script(&script);
+    sim.run_until(
+        local(days[days.len() - 1], 23, 0),
+        Duration::minutes(STEP_MINUTES),
+    )
+    .await;
+
+    assert_eq!(sim.scrum_dates().await, scrum_dates(&days));
+    assert_matches_model(&sim, &days, &script).await;
+    assert_eq!(sim.streak(BOBBY).await, 3);
+    assert_eq!(sim.chat.messages(DIGEST_CHANNEL).len(), days.len());
+}
+
+async fn repeated_hour_before_midnight() {
+    // 23:00 to midnight happens twice on 2022-04-02.
+    let days = days(date(2022, 4, 1), 3);
+    let mut script: Vec<ScriptedAnswer> = days
+        .iter()
+        .flat_map(|scrum_date| {
+            [EITAN, KEVIN, JUSTIN]
+                .into_iter()
+                .map(|user| answer(*scrum_date, 11, 0, user, Answer::Accept))
+        })
+        .collect();
+    // Long after the scrum closed, during the first pass through the repeated hour.
+    script.push(answer(days[1], 23, 30, BOBBY, Answer::Accept));
+
+    let mut sim = Simulation::new(local(days[0], 0, 0)).await;
+    sim.add_script(&script);
+    sim.run_until(
+        local(days[days.len() - 1], 23, 0),
+        Duration::minutes(STEP_MINUTES),
+    )
+    .await;
+
+    assert_eq!(sim.scrum_dates().await, scrum_dates(&days));
+    assert_matches_model(&sim, &days, &script).await;
+    assert_eq!(sim.streak(BOBBY).await, 0);
+    assert_eq!(sim.chat.messages(DIGEST_CHANNEL).len(), days.len());
+}
+
+fn main() {
+    common::time_zone::run_tests(
+        "America/Santiago",
+        &[
+            ("week_across_skipped_midnight", || {
+                Box::pin(week_across_skipped_midnight())
+            }),
+            ("repeated_hour_before_midnight", || {
+                Box::pin(repeated_hour_before_midnight())
+            }),
+        ],
+    );
+}
@@ -0,0 +1,166 @@
+mod common;
+
+use chrono::{DateTime, Local, TimeZone};
+
+use sqlx::SqlitePool;
+
+use serenity::model::id::{ChannelId, MessageId, UserId};
+
+use ugo_ii_bot::chat::MemoryChat;
+use ugo_ii_bot::ugocoin::account::{self, Ugocoin};
+use ugo_ii_bot::ugocoin::tip;
+
+use common::{fund_central_bank, get_user, test_db, BOT_ID, EITAN, KEVIN};
+
+const CHANNEL: ChannelId = ChannelId(400);
+
+fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+    Local
+        .with_ymd_and_hms(2022, 12, day, hour, minute, 0)
+        .single()
+        .expect("Ambiguous test time")
+}
+
+async fn balance(db: &SqlitePool, discord_id: UserId) -> Ugocoin {
+    let user = get_user(db, discord_id).await;
+    account::get_user_account(db, &user)
+        .await
+        .expect("Failed to fetch account")
+        .balance
+}
+
+async fn give(db: &SqlitePool, discord_id: UserId, amount: Ugocoin) {
+    let user = get_user(db, discord_id).await;
+    let account = account::get_user_account(db, &user).await.unwrap();
+    account::credit_account(
+        db,
+        at(1, 0, 0),
+        &account,
+        amount,
+        &String::from("Allowance"),
+    )
+    .await
+    .expect("Failed to fund user");
+}
+
+async fn tip(db: &SqlitePool, chat: &MemoryChat, now: DateTime<Local>, message_id: MessageId) {
+    tip::on_tip_add(db, chat, now, CHANNEL, message_id, EITAN)
+        .await
+        .expect("Failed to handle tip");
+}
+
+async fn untip(db: &SqlitePool, now: DateTime<Local>, message_id: MessageId) {
+    tip::on_tip_remove(db, now, message_id, EITAN)
+        .await
+        .expect("Failed to handle tip removal");
+}
+
+async fn tips_logged(db: &SqlitePool) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM ugocoin_tips")
+        .fetch_one(db)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn tips_stop_at_the_daily_limit() {
+    let db = test_db().await;
+    let chat = MemoryChat::new(BOT_ID);
+    fund_central_bank(&db, Ugocoin::from_ugocoin(100)).await;
+    give(&db, EITAN, Ugocoin::from_ugocoin(10)).await;
+
+    for minute in 0..6 {
+        let message_id = chat.post_as(KEVIN, CHANNEL, "A helpful answer");
+        tip(&db, &chat, at(19, 12, minute), message_id).await;
+    }
+
+    assert_eq!(balance(&db, KEVIN).await, Ugocoin::from_ugocents(50));
+    assert_eq!(balance(&db, EITAN).await, Ugocoin::from_ugocents(950));
+    assert_eq!(tips_logged(&db).await, 5);
+
+    // The limit is per day.
+    let message_id = chat.post_as(KEVIN, CHANNEL, "Another helpful answer");
+    tip(&db, &chat, at(20, 9, 0), message_id).await;
+    assert_eq!(balance(&db, KEVIN).await, Ugocoin::from_ugocents(60));
+}
+
+#[tokio::test]
+async fn removing_a_tip_in_the_grace_period_refunds_it() {
+    let db = test_db().await;
+    let chat = MemoryChat::new(BOT_ID);
+    fund_central_bank(&db, Ugocoin::from_ugocoin(100)).await;
+    give(&db, EITAN, Ugocoin::from_ugocoin(10)).await;
+
+    let message_id = chat.post_as(KEVIN, CHANNEL, "A helpful answer");
+    tip(&db, &chat, at(19, 12, 0), message_id).await;
+    assert_eq!(balance(&db, KEVIN).await, tip::TIP_AMOUNT);
+
+    untip(&db, at(19, 12, 4), message_id).await;
+    assert_eq!(balance(&db, KEVIN).await, Ugocoin::from_ugocents(0));
+    assert_eq!(balance(&db, EITAN).await, Ugocoin::from_ugocoin(10));
+
+    // Removing it again finds nothing left to refund.
+    untip(&db, at(19, 12, 4), message_id).await;
+    assert_eq!(balance(&db, EITAN).await, Ugocoin::from_ugocoin(10));
+
+    // Refunded tips don't count against the daily limit.
+    for minute in 10..15 {
+        let message_id = chat.post_as(KEVIN, CHANNEL, "More answers");
+        tip(&db, &chat, at(19, 12, minute), message_id).await;
+    }
+    assert_eq!(balance(&db, KEVIN).await, Ugocoin::from_ugocents(50));
+}
+
+#[tokio::test]
+async fn removing_a_tip_after_the_grace_period_keeps_it() {
+    let db = test_db().await;
+    let chat = MemoryChat::new(BOT_ID);
+    fund_central_bank(&db, Ugocoin::from_ugocoin(100)).await;
+    give(&db, EITAN, Ugocoin::from_ugocoin(10)).await;
+
+    let message_id = chat.post_as(KEVIN, CHANNEL, "A helpful answer");
+    tip(&db, &chat, at(19, 12, 0), message_id).await;
+
+    untip(&db, at(19, 12, 6), message_id).await;
+    assert_eq!(balance(&db, KEVIN).await, tip::TIP_AMOUNT);
+    assert_eq!(
+        balance(&db, EITAN).await,
+        Ugocoin::from_ugocents(1000 - tip::TIP_AMOUNT.as_ugocents())
+    );
+}
+
+#[tokio::test]
+async fn tips_need_enough_ugocoin() {
+    let db = test_db().await;
+    let chat = MemoryChat::new(BOT_ID);
+    fund_central_bank(&db, Ugocoin::from_ugocoin(100)).await;
+    give(&db, EITAN, Ugocoin::from_ugocents(15)).await;
+
+    let first = chat.post_as(KEVIN, CHANNEL, "A helpful answer");
+    let second = chat.post_as(KEVIN, CHANNEL, "Another helpful answer");
+    tip(&db, &chat, at(19, 12, 0), first).await;
+    tip(&db, &chat, at(19, 12, 1), second).await;
+
+    assert_eq!(balance(&db, EITAN).await, Ugocoin::from_ugocents(5));
+    assert_eq!(balance(&db, KEVIN).await, tip::TIP_AMOUNT);
+    // The rejected tip isn't logged, so there's nothing to refund later.
+    assert_eq!(tips_logged(&db).await, 1);
+}
+
+#[tokio::test]
+async fn tipping_yourself_or_a_stranger_does_nothing() {
+    let db = test_db().await;
+    let chat = MemoryChat::new(BOT_ID);
+    fund_central_bank(&db, Ugocoin::from_ugocoin(100)).await;
+    give(&db, EITAN, Ugocoin::from_ugocoin(10)).await;
+
+    let own = chat.post_as(EITAN, CHANNEL, "My own answer");
+    tip(&db, &chat, at(19, 12, 0), own).await;
+
+    // Nobody we know, so they have no account to tip.
+    let stranger = chat.post_as(UserId(999), CHANNEL, "Hello");
+    tip(&db, &chat, at(19, 12, 1), stranger).await;
+
+    assert_eq!(balance(&db, EITAN).await, Ugocoin::from_ugocoin(10));
+    assert_eq!(tips_logged(&db).await, 0);
+}